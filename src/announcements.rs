@@ -0,0 +1,260 @@
+use crate::crypto::Keyring;
+use crate::mongo::{get_number, read_messages};
+use chrono::{Duration, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::error::Error;
+use mongodb::options::UpdateOptions;
+use mongodb::Database;
+use std::collections::HashMap;
+
+const ANNOUNCEMENT_COLLECTION: &str = "announcements";
+
+// Longest title kept from the first line of an announcement
+const MAX_TITLE_CHARS: usize = 100;
+
+// Topics and key phrases listed per window
+const TOP_TERMS: i64 = 5;
+
+#[derive(Debug, Clone)]
+pub struct Announcement {
+    pub id: ObjectId,
+    pub title: String,
+    pub link: String,
+    // In the UTC+9 timestamps messages are stored with
+    pub posted_at: chrono::DateTime<Utc>,
+}
+
+// Community activity in a window around an announcement
+#[derive(Debug, Clone, Default)]
+pub struct WindowStats {
+    pub messages: i64,
+    pub analyzed: i64,
+    pub positive: i64,
+    pub negative: i64,
+    // Average sentiment score (-1 to 1) of the analyzed messages
+    pub score: Option<f64>,
+    pub topics: Vec<(String, i64)>,
+    pub key_phrases: Vec<(String, i64)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImpactReport {
+    pub announcement: Announcement,
+    pub before_hours: i64,
+    pub after_hours: i64,
+    pub before: WindowStats,
+    pub after: WindowStats,
+}
+
+// Records an announcement post as an event. Edits of the same post keep the first record.
+pub async fn record_announcement(
+    db: &Database,
+    message_id: &str,
+    channel_id: &str,
+    content: &str,
+    link: &str,
+    posted_at: chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    // Announcements usually open with a markdown heading
+    let title: String = content
+        .lines()
+        .map(|line| line.trim_start_matches('#').trim())
+        .find(|line| !line.is_empty())
+        .unwrap_or("Announcement")
+        .chars()
+        .take(MAX_TITLE_CHARS)
+        .collect();
+
+    let collection = db.collection::<Document>(ANNOUNCEMENT_COLLECTION);
+    let options = UpdateOptions::builder().upsert(true).build();
+    collection
+        .update_one(
+            doc! { "messageId": message_id },
+            doc! {
+                "$setOnInsert": {
+                    "channelId": channel_id,
+                    "title": title,
+                    "link": link,
+                    "createdAt": bson::DateTime::from_chrono(posted_at),
+                }
+            },
+            options,
+        )
+        .await?;
+
+    Ok(())
+}
+
+// Announcements posted at least `after_hours` before `now` that haven't been reported yet
+pub async fn due_announcements(
+    db: &Database,
+    now: chrono::DateTime<Utc>,
+    after_hours: i64,
+) -> Result<Vec<Announcement>, Error> {
+    let collection = db.collection::<Document>(ANNOUNCEMENT_COLLECTION);
+    let cutoff = bson::DateTime::from_chrono(now - Duration::hours(after_hours));
+    let documents: Vec<Document> = collection
+        .find(
+            doc! { "createdAt": { "$lte": cutoff }, "reportedAt": { "$exists": false } },
+            None,
+        )
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(documents
+        .iter()
+        .filter_map(|document| {
+            Some(Announcement {
+                id: document.get_object_id("_id").ok()?,
+                title: document.get_str("title").unwrap_or_default().to_string(),
+                link: document.get_str("link").unwrap_or_default().to_string(),
+                posted_at: document.get_datetime("createdAt").ok()?.to_chrono(),
+            })
+        })
+        .collect())
+}
+
+pub async fn mark_reported(db: &Database, announcement: &Announcement) -> Result<(), Error> {
+    let collection = db.collection::<Document>(ANNOUNCEMENT_COLLECTION);
+    collection
+        .update_one(
+            doc! { "_id": announcement.id },
+            doc! { "$set": { "reportedAt": bson::DateTime::now() } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn build_impact_report(
+    db: &Database,
+    keyring: Option<&Keyring>,
+    announcement: Announcement,
+    before_hours: i64,
+    after_hours: i64,
+) -> Result<ImpactReport, Error> {
+    let posted_at = announcement.posted_at;
+    let before = window_stats(
+        db,
+        keyring,
+        posted_at - Duration::hours(before_hours),
+        posted_at,
+    )
+    .await?;
+    let after = window_stats(
+        db,
+        keyring,
+        posted_at,
+        posted_at + Duration::hours(after_hours),
+    )
+    .await?;
+
+    Ok(ImpactReport {
+        announcement,
+        before_hours,
+        after_hours,
+        before,
+        after,
+    })
+}
+
+async fn window_stats(
+    db: &Database,
+    keyring: Option<&Keyring>,
+    start: chrono::DateTime<Utc>,
+    end: chrono::DateTime<Utc>,
+) -> Result<WindowStats, Error> {
+    let collection = db.collection::<Document>("messages");
+    let filter = doc! {
+        "createdAt": {
+            "$gte": bson::DateTime::from_chrono(start),
+            "$lt": bson::DateTime::from_chrono(end),
+        },
+        "role": { "$ne": "staff" },
+        "spam": { "$exists": false },
+    };
+
+    let pipeline = vec![
+        doc! { "$match": filter.clone() },
+        doc! {
+            "$group": {
+                "_id": null,
+                "messages": { "$sum": 1 },
+                "analyzed": { "$sum": { "$cond": [{ "$gt": ["$sentiment", null] }, 1, 0] } },
+                "positive": { "$sum": { "$cond": [{ "$eq": ["$sentiment", "positive"] }, 1, 0] } },
+                "negative": { "$sum": { "$cond": [{ "$eq": ["$sentiment", "negative"] }, 1, 0] } },
+            }
+        },
+    ];
+    let mut stats = WindowStats::default();
+    let mut cursor = collection.aggregate(pipeline, None).await?;
+    if let Some(document) = cursor.try_next().await? {
+        stats.messages = get_number(&document, "messages").unwrap_or_default() as i64;
+        stats.analyzed = get_number(&document, "analyzed").unwrap_or_default() as i64;
+        stats.positive = get_number(&document, "positive").unwrap_or_default() as i64;
+        stats.negative = get_number(&document, "negative").unwrap_or_default() as i64;
+    }
+    if stats.analyzed > 0 {
+        stats.score = Some((stats.positive - stats.negative) as f64 / stats.analyzed as f64);
+    }
+
+    stats.topics = top_values(db, &filter, "topics").await?;
+    stats.key_phrases = match keyring {
+        Some(keyring) => top_key_phrases(db, &filter, keyring).await?,
+        None => top_values(db, &filter, "keyPhrases").await?,
+    };
+    Ok(stats)
+}
+
+// The most frequent values of an array field among the matching messages
+async fn top_values(
+    db: &Database,
+    filter: &Document,
+    field: &str,
+) -> Result<Vec<(String, i64)>, Error> {
+    let collection = db.collection::<Document>("messages");
+    let pipeline = vec![
+        doc! { "$match": filter.clone() },
+        doc! { "$unwind": format!("${}", field) },
+        doc! { "$group": { "_id": format!("${}", field), "count": { "$sum": 1 } } },
+        doc! { "$sort": { "count": -1, "_id": 1 } },
+        doc! { "$limit": TOP_TERMS },
+    ];
+
+    let mut cursor = collection.aggregate(pipeline, None).await?;
+    let mut values = Vec::new();
+    while let Some(document) = cursor.try_next().await? {
+        if let Ok(value) = document.get_str("_id") {
+            values.push((
+                value.to_string(),
+                get_number(&document, "count").unwrap_or_default() as i64,
+            ));
+        }
+    }
+
+    Ok(values)
+}
+
+// Encrypted key phrases can't be grouped by the database, so they are counted here
+async fn top_key_phrases(
+    db: &Database,
+    filter: &Document,
+    keyring: &Keyring,
+) -> Result<Vec<(String, i64)>, Error> {
+    let mut filter = filter.clone();
+    filter.insert("keyPhrases.0", doc! { "$exists": true });
+    let mut reader = read_messages(db, filter, Some(keyring)).await?;
+    let mut counts: HashMap<String, i64> = HashMap::new();
+    while let Some(message) = reader.next().await? {
+        for phrase in message.key_phrases {
+            *counts.entry(phrase).or_default() += 1;
+        }
+    }
+
+    let mut values: Vec<(String, i64)> = counts.into_iter().collect();
+    values.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    values.truncate(TOP_TERMS as usize);
+    Ok(values)
+}
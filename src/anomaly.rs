@@ -0,0 +1,303 @@
+use crate::config::AnomalyConfig;
+use crate::mongo::get_number;
+use crate::util::median;
+use chrono::{Datelike, Duration, NaiveDateTime, Timelike, Utc, Weekday};
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, Document};
+use mongodb::error::Error;
+use mongodb::options::UpdateOptions;
+use mongodb::Database;
+use std::collections::{BTreeMap, HashMap};
+
+const ANOMALY_COLLECTION: &str = "volume_anomalies";
+
+// Days of history the seasonal profile is learned from; messages are kept for three weeks
+const HISTORY_DAYS: i64 = 21;
+
+// Same-hour samples needed before a channel's hour is judged (weekends have six in three weeks)
+const MIN_SAMPLES: usize = 4;
+
+// Scales the median absolute deviation to a standard deviation for normal data
+const MAD_SCALE: f64 = 1.4826;
+
+// Analyzed messages needed in a surge to call it negative
+const MIN_ANALYZED: i64 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AnomalyKind {
+    Surge,
+    Silence,
+}
+
+impl AnomalyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnomalyKind::Surge => "surge",
+            AnomalyKind::Silence => "silence",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VolumeAnomaly {
+    pub channel: String,
+    // Start of the hour, in the UTC+9 timestamps messages are stored with
+    pub hour: chrono::DateTime<Utc>,
+    pub kind: AnomalyKind,
+    // A surge whose analyzed messages are mostly negative, likely an incident
+    pub incident: bool,
+    pub observed: i64,
+    // Seasonal median of the same hour on the same kind of day (weekday or weekend)
+    pub expected: f64,
+    // Robust standard score of the observed volume
+    pub z: f64,
+    pub negative_share: Option<f64>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct HourCounts {
+    messages: i64,
+    analyzed: i64,
+    negative: i64,
+}
+
+fn is_weekend(hour: chrono::DateTime<Utc>) -> bool {
+    matches!(hour.weekday(), Weekday::Sat | Weekday::Sun)
+}
+
+// Start of the hour `now` falls in
+pub fn hour_start(now: chrono::DateTime<Utc>) -> chrono::DateTime<Utc> {
+    now.with_minute(0)
+        .and_then(|time| time.with_second(0))
+        .and_then(|time| time.with_nanosecond(0))
+        .unwrap_or(now)
+}
+
+// The seasonal median of the channel's volume in the same hour on the same kind of day, and
+// the robust standard score of the volume in `hour` against it. None without enough history.
+fn seasonal_score(
+    hours: &HashMap<chrono::DateTime<Utc>, HourCounts>,
+    hour: chrono::DateTime<Utc>,
+) -> Option<(f64, f64)> {
+    // Hours before the channel's first message don't count as silent
+    let first_hour = hours.keys().min().copied()?;
+    let samples: Vec<f64> = (1..=HISTORY_DAYS)
+        .map(|days| hour - Duration::days(days))
+        .filter(|sample| *sample >= first_hour && is_weekend(*sample) == is_weekend(hour))
+        .map(|sample| {
+            hours
+                .get(&sample)
+                .map_or(0.0, |counts| counts.messages as f64)
+        })
+        .collect();
+    if samples.len() < MIN_SAMPLES {
+        return None;
+    }
+
+    let expected = median(&samples);
+    let deviations: Vec<f64> = samples
+        .iter()
+        .map(|sample| (sample - expected).abs())
+        .collect();
+    // Quiet channels have a MAD of zero; fall back to Poisson-like noise
+    let scale = (median(&deviations) * MAD_SCALE).max(expected.sqrt().max(1.0));
+    let observed = hours.get(&hour).map_or(0, |counts| counts.messages);
+    Some((expected, (observed as f64 - expected) / scale))
+}
+
+// Compares each channel's community message volume in the hour starting at `hour` with the
+// same hour on the same kind of day over the last three weeks (seasonal median/MAD), and
+// stores and returns the hours that deviate by at least `config.threshold`
+pub async fn detect_volume_anomalies(
+    db: &Database,
+    config: &AnomalyConfig,
+    hour: chrono::DateTime<Utc>,
+) -> Result<Vec<VolumeAnomaly>, Error> {
+    let since = hour - Duration::days(HISTORY_DAYS);
+    let until = hour + Duration::hours(1);
+
+    let collection = db.collection::<Document>("messages");
+    let pipeline = vec![
+        doc! {
+            "$match": {
+                "createdAt": {
+                    "$gte": bson::DateTime::from_chrono(since),
+                    "$lt": bson::DateTime::from_chrono(until),
+                },
+                "role": { "$ne": "staff" },
+                "spam": { "$exists": false },
+            }
+        },
+        doc! {
+            "$group": {
+                "_id": {
+                    "channel": "$channel",
+                    "hour": { "$dateToString": { "format": "%Y-%m-%dT%H", "date": "$createdAt" } },
+                },
+                "messages": { "$sum": 1 },
+                "analyzed": { "$sum": { "$cond": [{ "$gt": ["$sentiment", null] }, 1, 0] } },
+                "negative": { "$sum": { "$cond": [{ "$eq": ["$sentiment", "negative"] }, 1, 0] } },
+            }
+        },
+    ];
+
+    let mut channels: BTreeMap<String, HashMap<chrono::DateTime<Utc>, HourCounts>> =
+        BTreeMap::new();
+    let mut cursor = collection.aggregate(pipeline, None).await?;
+    while let Some(document) = cursor.try_next().await? {
+        let Ok(group) = document.get_document("_id") else {
+            continue;
+        };
+        let (Ok(channel), Ok(hour)) = (group.get_str("channel"), group.get_str("hour")) else {
+            continue;
+        };
+        let Ok(hour) = NaiveDateTime::parse_from_str(&format!("{}:00", hour), "%Y-%m-%dT%H:%M")
+        else {
+            continue;
+        };
+        channels.entry(channel.to_string()).or_default().insert(
+            hour.and_utc(),
+            HourCounts {
+                messages: get_number(&document, "messages").unwrap_or_default() as i64,
+                analyzed: get_number(&document, "analyzed").unwrap_or_default() as i64,
+                negative: get_number(&document, "negative").unwrap_or_default() as i64,
+            },
+        );
+    }
+
+    let anomalies_collection = db.collection::<Document>(ANOMALY_COLLECTION);
+    let options = UpdateOptions::builder().upsert(true).build();
+    let mut anomalies = Vec::new();
+    for (channel, hours) in channels {
+        let Some((expected, z)) = seasonal_score(&hours, hour) else {
+            continue;
+        };
+        let observed = hours.get(&hour).copied().unwrap_or_default();
+
+        let kind = if z >= config.threshold && observed.messages >= config.min_messages {
+            AnomalyKind::Surge
+        } else if z <= -config.threshold && expected >= config.min_messages as f64 {
+            AnomalyKind::Silence
+        } else {
+            continue;
+        };
+        let negative_share =
+            (observed.analyzed > 0).then(|| observed.negative as f64 / observed.analyzed as f64);
+        let incident = kind == AnomalyKind::Surge
+            && observed.analyzed >= MIN_ANALYZED
+            && negative_share.is_some_and(|share| share >= config.incident_negative_share);
+
+        let anomaly = VolumeAnomaly {
+            channel,
+            hour,
+            kind,
+            incident,
+            observed: observed.messages,
+            expected,
+            z,
+            negative_share,
+        };
+        // An hour evaluated again, e.g. after a restart, updates its earlier record
+        anomalies_collection
+            .update_one(
+                doc! {
+                    "channel": &anomaly.channel,
+                    "hour": bson::DateTime::from_chrono(anomaly.hour),
+                    "kind": anomaly.kind.as_str(),
+                },
+                doc! {
+                    "$set": {
+                        "incident": anomaly.incident,
+                        "observed": anomaly.observed,
+                        "expected": anomaly.expected,
+                        "z": anomaly.z,
+                        "negativeShare": anomaly.negative_share,
+                    },
+                    "$setOnInsert": { "createdAt": bson::DateTime::now() },
+                },
+                options.clone(),
+            )
+            .await?;
+        anomalies.push(anomaly);
+    }
+
+    Ok(anomalies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    // A Wednesday
+    fn hour() -> chrono::DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 24, 20, 0, 0).unwrap()
+    }
+
+    fn counts(messages: i64) -> HourCounts {
+        HourCounts {
+            messages,
+            ..Default::default()
+        }
+    }
+
+    // The same hour on each of the past `days` days: weekends get `weekend` messages and
+    // weekdays cycle through `weekdays`
+    fn history(
+        days: i64,
+        weekdays: &[i64],
+        weekend: i64,
+    ) -> HashMap<chrono::DateTime<Utc>, HourCounts> {
+        let mut cycle = weekdays.iter().cycle();
+        (1..=days)
+            .map(|day| {
+                let sample = hour() - Duration::days(day);
+                let messages = if is_weekend(sample) {
+                    weekend
+                } else {
+                    *cycle.next().unwrap()
+                };
+                (sample, counts(messages))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scores_against_the_median_of_the_same_hour_on_weekdays() {
+        // Busy weekends must not raise the weekday baseline
+        let mut hours = history(HISTORY_DAYS, &[10], 100);
+        hours.insert(hour(), counts(40));
+
+        let (expected, z) = seasonal_score(&hours, hour()).unwrap();
+        assert_eq!(expected, 10.0);
+        // A MAD of zero falls back to the Poisson floor of sqrt(10)
+        assert!((z - 30.0 / 10f64.sqrt()).abs() < 1e-9, "{}", z);
+    }
+
+    #[test]
+    fn uses_the_median_absolute_deviation_when_it_is_larger() {
+        let mut hours = history(HISTORY_DAYS, &[10, 20, 30], 0);
+        hours.insert(hour(), counts(50));
+
+        let (expected, z) = seasonal_score(&hours, hour()).unwrap();
+        assert_eq!(expected, 20.0);
+        assert!((z - 30.0 / (10.0 * MAD_SCALE)).abs() < 1e-9, "{}", z);
+    }
+
+    #[test]
+    fn counts_missing_hours_as_silent_since_the_first_message() {
+        // The channel's first message was a week ago; the weekdays since had none
+        let first = hour() - Duration::days(7);
+        let hours = HashMap::from([(first, counts(10))]);
+
+        let (expected, z) = seasonal_score(&hours, hour()).unwrap();
+        assert_eq!(expected, 0.0);
+        assert_eq!(z, 0.0);
+    }
+
+    #[test]
+    fn needs_enough_history() {
+        let hours = history(3, &[10], 10);
+        assert!(seasonal_score(&hours, hour()).is_none());
+    }
+}
@@ -0,0 +1,228 @@
+use crate::config::{AspectConfig, AspectProviderKind, EmotionConfig};
+use crate::emotion::EmotionLexicon;
+use crate::mongo::get_number;
+use crate::sentiment::sentiment_label;
+use crate::topics::{TopicSentiment, TopicTagger};
+use async_trait::async_trait;
+use aws_sdk_comprehend::types::LanguageCode;
+use aws_sdk_comprehend::Client;
+use chrono::Utc;
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, Document};
+use mongodb::error::Error;
+use mongodb::Database;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+// The sentiment expressed toward one thing the message talks about
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct AspectSentiment {
+    pub aspect: String,
+    // The taxonomy topic the aspect belongs to, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    pub sentiment: String,
+}
+
+#[async_trait]
+pub trait AspectAnalyzer: Send + Sync {
+    async fn analyze(
+        &self,
+        text: &str,
+        topics: &TopicTagger,
+    ) -> Result<Vec<AspectSentiment>, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+pub fn load_aspect_analyzer(
+    config: &AspectConfig,
+    emotions: &EmotionConfig,
+) -> Option<Box<dyn AspectAnalyzer>> {
+    match config.provider {
+        AspectProviderKind::Offline => Some(Box::new(ClauseAspects::new(EmotionLexicon::new(
+            &emotions.extra_terms,
+        )))),
+        AspectProviderKind::Comprehend => Some(Box::new(ComprehendAspects)),
+        AspectProviderKind::Disabled => None,
+    }
+}
+
+// AWS Comprehend targeted sentiment: one aspect per detected entity, named by its most
+// descriptive mention
+pub struct ComprehendAspects;
+
+#[async_trait]
+impl AspectAnalyzer for ComprehendAspects {
+    async fn analyze(
+        &self,
+        text: &str,
+        topics: &TopicTagger,
+    ) -> Result<Vec<AspectSentiment>, Box<dyn std::error::Error + Send + Sync>> {
+        let shared_config = aws_config::load_from_env().await;
+        let client = Client::new(&shared_config);
+        let entities = client
+            .detect_targeted_sentiment()
+            .text(text)
+            .language_code(LanguageCode::En)
+            .send()
+            .await?
+            .entities
+            .unwrap_or_default();
+
+        let mut aspects = Vec::new();
+        for entity in entities {
+            let mentions = entity.mentions.unwrap_or_default();
+            let descriptive = entity
+                .descriptive_mention_index
+                .and_then(|indexes| indexes.first().copied())
+                .and_then(|index| mentions.get(index as usize))
+                .or_else(|| mentions.first());
+            let Some(aspect) = descriptive.and_then(|mention| mention.text.clone()) else {
+                continue;
+            };
+
+            // Mentions of the same entity can disagree ("the app is great, the app crashes")
+            let labels: Vec<&str> = mentions
+                .iter()
+                .filter_map(|mention| mention.mention_sentiment.as_ref()?.sentiment.as_ref())
+                .map(sentiment_label)
+                .collect();
+            let positive = labels.contains(&"positive");
+            let negative = labels.contains(&"negative");
+            let sentiment = match (positive, negative) {
+                (true, true) => "mixed",
+                (true, false) => "positive",
+                (false, true) => "negative",
+                _ if labels.contains(&"mixed") => "mixed",
+                _ => "neutral",
+            };
+
+            let mention_texts: Vec<String> = mentions
+                .iter()
+                .filter_map(|mention| mention.text.clone())
+                .collect();
+            aspects.push(AspectSentiment {
+                topic: topics
+                    .tag(&mention_texts.join("\n"), &[])
+                    .into_iter()
+                    .next(),
+                aspect: aspect.to_lowercase(),
+                sentiment: sentiment.to_string(),
+            });
+        }
+
+        Ok(aspects)
+    }
+}
+
+// Offline: splits the message into clauses at sentence punctuation and contrastive
+// conjunctions, then gives each taxonomy topic found in a clause that clause's polarity
+pub struct ClauseAspects {
+    lexicon: EmotionLexicon,
+    clause_boundary: Regex,
+}
+
+impl ClauseAspects {
+    pub fn new(lexicon: EmotionLexicon) -> ClauseAspects {
+        ClauseAspects {
+            lexicon,
+            clause_boundary: Regex::new(
+                r"(?i)[.!?;\n]+|\b(?:but|however|although|though|yet|whereas|except|while)\b",
+            )
+            .unwrap(),
+        }
+    }
+}
+
+#[async_trait]
+impl AspectAnalyzer for ClauseAspects {
+    async fn analyze(
+        &self,
+        text: &str,
+        topics: &TopicTagger,
+    ) -> Result<Vec<AspectSentiment>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut aspects: Vec<AspectSentiment> = Vec::new();
+        for clause in self.clause_boundary.split(text) {
+            let clause = clause.trim();
+            if clause.is_empty() {
+                continue;
+            }
+            let sentiment = self.lexicon.polarity(clause);
+            for topic in topics.tag(clause, &[]) {
+                // A topic mentioned in several clauses keeps its first polarity unless a
+                // later clause disagrees, which makes it mixed
+                match aspects.iter_mut().find(|aspect| aspect.aspect == topic) {
+                    Some(existing) if existing.sentiment != sentiment => {
+                        existing.sentiment = "mixed".to_string();
+                    }
+                    Some(_) => {}
+                    None => aspects.push(AspectSentiment {
+                        aspect: topic.clone(),
+                        topic: Some(topic),
+                        sentiment: sentiment.to_string(),
+                    }),
+                }
+            }
+        }
+
+        Ok(aspects)
+    }
+}
+
+// Sentiment toward each topic since `since`, counted per aspect mention rather than per
+// message, so "love X but Y is broken" counts positive for X and negative for Y
+pub async fn aspect_sentiment(
+    db: &Database,
+    since: chrono::DateTime<Utc>,
+) -> Result<Vec<TopicSentiment>, Error> {
+    let collection = db.collection::<Document>("messages");
+    let pipeline = vec![
+        doc! {
+            "$match": {
+                "createdAt": { "$gte": bson::DateTime::from_chrono(since) },
+                "role": { "$ne": "staff" },
+                "spam": { "$exists": false },
+                "aspects.topic": { "$exists": true },
+            }
+        },
+        doc! { "$unwind": "$aspects" },
+        doc! { "$match": { "aspects.topic": { "$exists": true } } },
+        doc! {
+            "$group": {
+                "_id": "$aspects.topic",
+                "messages": { "$sum": 1 },
+                "positive": {
+                    "$sum": { "$cond": [{ "$eq": ["$aspects.sentiment", "positive"] }, 1, 0] }
+                },
+                "negative": {
+                    "$sum": { "$cond": [{ "$eq": ["$aspects.sentiment", "negative"] }, 1, 0] }
+                },
+                "score": {
+                    "$avg": {
+                        "$switch": {
+                            "branches": [
+                                { "case": { "$eq": ["$aspects.sentiment", "positive"] }, "then": 1 },
+                                { "case": { "$eq": ["$aspects.sentiment", "negative"] }, "then": -1 },
+                            ],
+                            "default": 0,
+                        }
+                    }
+                },
+            }
+        },
+        doc! { "$sort": { "messages": -1 } },
+    ];
+
+    let mut cursor = collection.aggregate(pipeline, None).await?;
+    let mut topics = Vec::new();
+    while let Some(document) = cursor.try_next().await? {
+        topics.push(TopicSentiment {
+            topic: document.get_str("_id").unwrap_or_default().to_string(),
+            messages: get_number(&document, "messages").unwrap_or_default() as i64,
+            positive: get_number(&document, "positive").unwrap_or_default() as i64,
+            negative: get_number(&document, "negative").unwrap_or_default() as i64,
+            score: get_number(&document, "score"),
+        });
+    }
+
+    Ok(topics)
+}
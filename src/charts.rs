@@ -0,0 +1,308 @@
+use crate::report::SENTIMENT_LABELS;
+use chrono::NaiveDate;
+use image::codecs::png::PngEncoder;
+use image::{ColorType, ImageEncoder};
+use plotters::prelude::*;
+use plotters::style::text_anchor::{HPos, Pos, VPos};
+use serenity::model::channel::AttachmentType;
+use std::borrow::Cow;
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fs;
+
+// Family the configured font is registered under; every chart draws its text with it
+const FONT: &str = "sans-serif";
+
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 450;
+const RADAR_SIZE: u32 = 500;
+
+type ChartResult = Result<Vec<u8>, Box<dyn Error>>;
+
+// A rendered PNG, attached to a message under `filename`
+pub struct Chart {
+    pub filename: &'static str,
+    pub png: Vec<u8>,
+}
+
+impl Chart {
+    pub fn attachment(self) -> AttachmentType<'static> {
+        AttachmentType::Bytes {
+            data: Cow::Owned(self.png),
+            filename: self.filename.to_string(),
+        }
+    }
+
+    // Embed image URL that refers to the attachment
+    pub fn url(&self) -> String {
+        format!("attachment://{}", self.filename)
+    }
+}
+
+// Colors of the sentiment labels, the same in every chart
+pub fn sentiment_color(label: &str) -> RGBColor {
+    match label {
+        "positive" => RGBColor(46, 160, 67),
+        "negative" => RGBColor(215, 58, 73),
+        "mixed" => RGBColor(227, 160, 8),
+        _ => RGBColor(150, 150, 150),
+    }
+}
+
+// Registers the TrueType font charts are drawn with. Without it charts can't render text,
+// so they are left out.
+pub fn register_chart_font(path: &str) -> bool {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            println!("Error reading chart font {}: {:?}", path, e);
+            return false;
+        }
+    };
+    // The registry keeps the font for the life of the process
+    let bytes: &'static [u8] = Box::leak(bytes.into_boxed_slice());
+    match plotters::style::register_font(FONT, FontStyle::Normal, bytes) {
+        Ok(_) => true,
+        Err(_) => {
+            println!("Error registering chart font {}: not a TrueType font", path);
+            false
+        }
+    }
+}
+
+// Renders every chart there is data for. Charts that fail to render are logged and left out.
+pub fn sentiment_charts(
+    daily: &[(NaiveDate, Vec<i64>)],
+    channels: &[(String, Vec<i64>)],
+    emotions: &[(String, f64)],
+) -> Vec<Chart> {
+    let mut charts = Vec::new();
+    let mut add = |filename: &'static str, result: ChartResult| match result {
+        Ok(png) => charts.push(Chart { filename, png }),
+        Err(e) => println!("Error rendering {}: {:?}", filename, e),
+    };
+
+    if daily
+        .iter()
+        .any(|(_, counts)| counts.iter().sum::<i64>() > 0)
+    {
+        add("sentiment.png", sentiment_area_chart(daily));
+    }
+    if !channels.is_empty() {
+        add("channels.png", channel_sentiment_chart(channels));
+    }
+    if emotions.iter().any(|(_, share)| *share > 0.0) {
+        add("emotions.png", emotion_radar_chart(emotions));
+    }
+
+    charts
+}
+
+// Stacked area of the daily message counts per sentiment label
+pub fn sentiment_area_chart(days: &[(NaiveDate, Vec<i64>)]) -> ChartResult {
+    let mut buffer = vec![0u8; (WIDTH * HEIGHT * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut buffer, (WIDTH, HEIGHT)).into_drawing_area();
+        root.fill(&WHITE)?;
+
+        let max = days
+            .iter()
+            .map(|(_, counts)| counts.iter().sum::<i64>())
+            .max()
+            .unwrap_or_default()
+            .max(1);
+        let last = days.len().saturating_sub(1).max(1);
+        let mut chart = ChartBuilder::on(&root)
+            .caption("Sentiment per day", (FONT, 24))
+            .margin(15)
+            .x_label_area_size(30)
+            .y_label_area_size(50)
+            .build_cartesian_2d(0..last, 0..max + max / 10)?;
+        chart
+            .configure_mesh()
+            .disable_x_mesh()
+            .x_labels(days.len())
+            .x_label_formatter(&|index| {
+                days.get(*index)
+                    .map(|(day, _)| day.format("%m-%d").to_string())
+                    .unwrap_or_default()
+            })
+            .label_style((FONT, 14))
+            .draw()?;
+
+        // Each layer is drawn as the running total up to its label, topmost layer first
+        for (index, label) in SENTIMENT_LABELS.iter().enumerate().rev() {
+            let color = sentiment_color(label);
+            let points = days
+                .iter()
+                .enumerate()
+                .map(|(day, (_, counts))| (day, counts.iter().take(index + 1).sum::<i64>()));
+            chart
+                .draw_series(AreaSeries::new(points, 0, color).border_style(color))?
+                .label(*label)
+                .legend(move |(x, y)| {
+                    Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled())
+                });
+        }
+
+        chart
+            .configure_series_labels()
+            .position(SeriesLabelPosition::UpperLeft)
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .label_font((FONT, 14))
+            .draw()?;
+        root.present()?;
+    }
+
+    encode_png(&buffer, WIDTH, HEIGHT)
+}
+
+// Horizontal stacked bars of each channel's message counts per sentiment label
+pub fn channel_sentiment_chart(channels: &[(String, Vec<i64>)]) -> ChartResult {
+    let mut buffer = vec![0u8; (WIDTH * HEIGHT * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut buffer, (WIDTH, HEIGHT)).into_drawing_area();
+        root.fill(&WHITE)?;
+
+        let max = channels
+            .iter()
+            .map(|(_, counts)| counts.iter().sum::<i64>())
+            .max()
+            .unwrap_or_default()
+            .max(1);
+        // A single row would leave the segmented axis without a span
+        let rows = channels.len().max(2);
+        let mut chart = ChartBuilder::on(&root)
+            .caption("Sentiment per channel", (FONT, 24))
+            .margin(15)
+            .x_label_area_size(30)
+            .y_label_area_size(150)
+            .build_cartesian_2d(0..max + max / 3, (0..rows - 1).into_segmented())?;
+        chart
+            .configure_mesh()
+            .disable_y_mesh()
+            .y_labels(rows)
+            .y_label_formatter(&|value| match value {
+                SegmentValue::CenterOf(row) => channels
+                    .get(rows - 1 - row)
+                    .map(|(channel, _)| format!("#{}", channel))
+                    .unwrap_or_default(),
+                _ => String::new(),
+            })
+            .label_style((FONT, 14))
+            .draw()?;
+
+        for (index, label) in SENTIMENT_LABELS.iter().enumerate() {
+            let color = sentiment_color(label);
+            // Rows count up from the bottom, so the busiest channel is drawn at the top
+            let bars = channels.iter().enumerate().map(|(position, (_, counts))| {
+                let row = rows - 1 - position;
+                let start: i64 = counts.iter().take(index).sum();
+                let mut bar = Rectangle::new(
+                    [
+                        (start, SegmentValue::Exact(row)),
+                        (start + counts[index], SegmentValue::Exact(row + 1)),
+                    ],
+                    color.filled(),
+                );
+                bar.set_margin(4, 4, 0, 0);
+                bar
+            });
+            chart
+                .draw_series(bars)?
+                .label(*label)
+                .legend(move |(x, y)| {
+                    Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled())
+                });
+        }
+
+        chart
+            .configure_series_labels()
+            .position(SeriesLabelPosition::UpperRight)
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .label_font((FONT, 14))
+            .draw()?;
+        root.present()?;
+    }
+
+    encode_png(&buffer, WIDTH, HEIGHT)
+}
+
+// Radar of the average share of each emotion, scaled so the strongest emotion reaches the
+// outer ring
+pub fn emotion_radar_chart(emotions: &[(String, f64)]) -> ChartResult {
+    let mut buffer = vec![0u8; (RADAR_SIZE * RADAR_SIZE * 3) as usize];
+    {
+        let root =
+            BitMapBackend::with_buffer(&mut buffer, (RADAR_SIZE, RADAR_SIZE)).into_drawing_area();
+        root.fill(&WHITE)?;
+        let root = root.titled("Emotions", (FONT, 24))?;
+
+        let mut chart = ChartBuilder::on(&root)
+            .margin(20)
+            .build_cartesian_2d(-1.4f64..1.4, -1.4f64..1.4)?;
+
+        // Spokes run clockwise from the top
+        let spokes = emotions.len().max(3);
+        let point = |spoke: usize, radius: f64| {
+            let angle = PI / 2.0 - 2.0 * PI * spoke as f64 / spokes as f64;
+            (radius * angle.cos(), radius * angle.sin())
+        };
+
+        let grid = RGBColor(200, 200, 200);
+        for ring in 1..=4 {
+            let radius = ring as f64 / 4.0;
+            let mut outline: Vec<(f64, f64)> =
+                (0..spokes).map(|spoke| point(spoke, radius)).collect();
+            outline.push(outline[0]);
+            chart.draw_series(std::iter::once(PathElement::new(outline, grid)))?;
+        }
+        for spoke in 0..spokes {
+            chart.draw_series(std::iter::once(PathElement::new(
+                vec![(0.0, 0.0), point(spoke, 1.0)],
+                grid,
+            )))?;
+        }
+
+        let max = emotions
+            .iter()
+            .map(|(_, share)| *share)
+            .fold(0.0, f64::max)
+            .max(f64::EPSILON);
+        let color = RGBColor(66, 103, 210);
+        let mut shape: Vec<(f64, f64)> = emotions
+            .iter()
+            .enumerate()
+            .map(|(spoke, (_, share))| point(spoke, share / max))
+            .collect();
+        chart.draw_series(std::iter::once(Polygon::new(shape.clone(), color.mix(0.3))))?;
+        if let Some(first) = shape.first().copied() {
+            shape.push(first);
+        }
+        chart.draw_series(std::iter::once(PathElement::new(
+            shape,
+            color.stroke_width(2),
+        )))?;
+
+        let label_style =
+            TextStyle::from((FONT, 16).into_font()).pos(Pos::new(HPos::Center, VPos::Center));
+        for (spoke, (emotion, share)) in emotions.iter().enumerate() {
+            chart.draw_series(std::iter::once(Text::new(
+                format!("{} {:.0}%", emotion, share * 100.0),
+                point(spoke, 1.2),
+                label_style.clone(),
+            )))?;
+        }
+        root.present()?;
+    }
+
+    encode_png(&buffer, RADAR_SIZE, RADAR_SIZE)
+}
+
+fn encode_png(buffer: &[u8], width: u32, height: u32) -> ChartResult {
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png).write_image(buffer, width, height, ColorType::Rgb8)?;
+    Ok(png)
+}
@@ -0,0 +1,293 @@
+use crate::config::ChurnConfig;
+use crate::mongo::get_number;
+use crate::privacy::OptOuts;
+use crate::pseudonym::Pseudonymizer;
+use crate::util::local_now;
+use chrono::Duration;
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, Document};
+use mongodb::error::Error;
+use mongodb::options::UpdateOptions;
+use mongodb::Database;
+use std::collections::{HashMap, HashSet};
+
+const BASELINE_COLLECTION: &str = "user_baselines";
+
+// Weight of each older week in the baseline, about a seven-week half-life
+const WEEKLY_DECAY: f64 = 0.9;
+
+// Floor on the baseline variance, so that a user who was always positive isn't flagged for
+// one neutral week
+const MIN_VARIANCE: f64 = 0.1;
+
+// Only members with this many weeks of history have a baseline to fall from
+const MIN_BASELINE_WEEKS: i32 = 4;
+
+#[derive(Debug, Clone, Default)]
+pub struct AtRiskMember {
+    // The stored author ID, a pseudonym when pseudonymization is enabled
+    pub author_id: String,
+    // Average sentiment score (-1 to 1) of the baseline and of the past week
+    pub baseline: f64,
+    pub score: f64,
+    pub messages: i64,
+    // Standard score of the week's average against the baseline
+    pub z: f64,
+}
+
+struct Baseline {
+    mean: f64,
+    variance: f64,
+    weight: f64,
+    weeks: i32,
+    updated_at: Option<bson::DateTime>,
+}
+
+impl Baseline {
+    // Standard score of a week's average sentiment score over `count` messages
+    fn z(&self, score: f64, count: f64) -> f64 {
+        let standard_error = (self.variance.max(MIN_VARIANCE) / count).sqrt();
+        (score - self.mean) / standard_error
+    }
+
+    // Weighted merge of the decayed baseline and a week (parallel variance formula)
+    fn merge(&self, score: f64, variance: f64, count: f64) -> Baseline {
+        let old_weight = self.weight * WEEKLY_DECAY;
+        let weight = old_weight + count;
+        let delta = score - self.mean;
+        Baseline {
+            mean: self.mean + delta * count / weight,
+            variance: (old_weight * self.variance
+                + count * variance
+                + delta * delta * old_weight * count / weight)
+                / weight,
+            weight,
+            weeks: self.weeks + 1,
+            updated_at: None,
+        }
+    }
+}
+
+// Author IDs of opted-out users as they may be stored: the Discord ID and the pseudonym
+pub async fn opted_out_authors(
+    opt_outs: &OptOuts,
+    pseudonymizer: Option<&Pseudonymizer>,
+) -> HashSet<String> {
+    let mut authors = HashSet::new();
+    for user_id in opt_outs.read().await.iter() {
+        authors.insert(user_id.to_string());
+        if let Some(pseudonymizer) = pseudonymizer {
+            authors.insert(pseudonymizer.pseudonym(*user_id));
+        }
+    }
+    authors
+}
+
+// Compares each community member's sentiment over the past week with their baseline, then
+// folds the week into the baseline. Returns the members whose week was significantly more
+// negative than usual, most significant first. Baselines of `excluded` authors are deleted.
+pub async fn update_baselines(
+    db: &Database,
+    config: &ChurnConfig,
+    excluded: &HashSet<String>,
+) -> Result<Vec<AtRiskMember>, Error> {
+    let until = local_now();
+    let since = until - Duration::weeks(1);
+
+    let messages = db.collection::<Document>("messages");
+    let pipeline = vec![
+        doc! {
+            "$match": {
+                "createdAt": { "$gte": bson::DateTime::from_chrono(since) },
+                "role": { "$ne": "staff" },
+                "spam": { "$exists": false },
+                "sentiment": { "$exists": true },
+                "authorId": { "$exists": true },
+            }
+        },
+        doc! {
+            "$project": {
+                "authorId": 1,
+                "score": {
+                    "$switch": {
+                        "branches": [
+                            { "case": { "$eq": ["$sentiment", "positive"] }, "then": 1 },
+                            { "case": { "$eq": ["$sentiment", "negative"] }, "then": -1 },
+                        ],
+                        "default": 0,
+                    }
+                },
+            }
+        },
+        doc! {
+            "$group": {
+                "_id": "$authorId",
+                "messages": { "$sum": 1 },
+                "score": { "$avg": "$score" },
+                "deviation": { "$stdDevPop": "$score" },
+            }
+        },
+    ];
+    let weeks: Vec<Document> = messages
+        .aggregate(pipeline, None)
+        .await?
+        .try_collect()
+        .await?;
+
+    let collection = db.collection::<Document>(BASELINE_COLLECTION);
+    let mut baselines: HashMap<String, Baseline> = HashMap::new();
+    let mut cursor = collection.find(None, None).await?;
+    while let Some(document) = cursor.try_next().await? {
+        let Ok(author_id) = document.get_str("authorId") else {
+            continue;
+        };
+        baselines.insert(
+            author_id.to_string(),
+            Baseline {
+                mean: get_number(&document, "mean").unwrap_or_default(),
+                variance: get_number(&document, "variance").unwrap_or_default(),
+                weight: get_number(&document, "weight").unwrap_or_default(),
+                weeks: get_number(&document, "weeks").unwrap_or_default() as i32,
+                updated_at: document.get_datetime("updatedAt").ok().copied(),
+            },
+        );
+    }
+
+    // A second run within the same week doesn't count the week twice
+    let merged_after = bson::DateTime::from_chrono(until - Duration::days(6));
+    let options = UpdateOptions::builder().upsert(true).build();
+    let mut at_risk = Vec::new();
+    for week in weeks {
+        let Ok(author_id) = week.get_str("_id") else {
+            continue;
+        };
+        if excluded.contains(author_id) {
+            continue;
+        }
+        let baseline = baselines.get(author_id);
+        if baseline.is_some_and(|baseline| {
+            baseline
+                .updated_at
+                .is_some_and(|updated_at| updated_at > merged_after)
+        }) {
+            continue;
+        }
+
+        let count = get_number(&week, "messages").unwrap_or_default();
+        let score = get_number(&week, "score").unwrap_or_default();
+        let variance = get_number(&week, "deviation").unwrap_or_default().powi(2);
+
+        let mut z = None;
+        let mut flagged = false;
+        if let Some(baseline) = baseline {
+            if baseline.weeks >= MIN_BASELINE_WEEKS
+                && baseline.weight >= config.min_baseline_messages as f64
+                && count >= config.min_week_messages as f64
+            {
+                let week_z = baseline.z(score, count);
+                if week_z <= -config.z_threshold && baseline.mean - score >= config.min_drop {
+                    at_risk.push(AtRiskMember {
+                        author_id: author_id.to_string(),
+                        baseline: baseline.mean,
+                        score,
+                        messages: count as i64,
+                        z: week_z,
+                    });
+                    flagged = true;
+                }
+                z = Some(week_z);
+            }
+        }
+
+        let merged = match baseline {
+            Some(baseline) => baseline.merge(score, variance, count),
+            None => Baseline {
+                mean: score,
+                variance,
+                weight: count,
+                weeks: 1,
+                updated_at: None,
+            },
+        };
+
+        let mut last_week = doc! { "messages": count as i64, "score": score };
+        if let Some(z) = z {
+            last_week.insert("z", z);
+        }
+        collection
+            .update_one(
+                doc! { "authorId": author_id },
+                doc! {
+                    "$set": {
+                        "mean": merged.mean,
+                        "variance": merged.variance,
+                        "weight": merged.weight,
+                        "weeks": merged.weeks,
+                        "lastWeek": last_week,
+                        "atRisk": flagged,
+                        "updatedAt": bson::DateTime::from_chrono(until),
+                    },
+                    "$setOnInsert": { "createdAt": bson::DateTime::from_chrono(until) },
+                },
+                options.clone(),
+            )
+            .await?;
+    }
+
+    if !excluded.is_empty() {
+        let excluded: Vec<&String> = excluded.iter().collect();
+        collection
+            .delete_many(doc! { "authorId": { "$in": excluded } }, None)
+            .await?;
+    }
+
+    at_risk.sort_by(|a, b| a.z.total_cmp(&b.z));
+    Ok(at_risk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn baseline(mean: f64, variance: f64, weight: f64) -> Baseline {
+        Baseline {
+            mean,
+            variance,
+            weight,
+            weeks: MIN_BASELINE_WEEKS,
+            updated_at: None,
+        }
+    }
+
+    #[test]
+    fn z_is_the_drop_over_the_standard_error_of_the_week() {
+        // Variance 0.25 over 25 messages gives a standard error of 0.1
+        let z = baseline(0.5, 0.25, 100.0).z(0.2, 25.0);
+        assert!((z + 3.0).abs() < 1e-9, "{}", z);
+    }
+
+    #[test]
+    fn z_uses_the_variance_floor_for_steady_members() {
+        // Always positive: the floor of 0.1 over 10 messages gives a standard error of 0.1
+        let z = baseline(1.0, 0.0, 50.0).z(0.8, 10.0);
+        assert!((z + 2.0).abs() < 1e-9, "{}", z);
+    }
+
+    #[test]
+    fn merge_matches_pooling_the_decayed_baseline_with_the_week() {
+        // 10 messages at 1.0 decay to a weight of 9; the week adds 9 messages at 0.0
+        let merged = baseline(1.0, 0.0, 10.0).merge(0.0, 0.0, 9.0);
+        assert!((merged.weight - 18.0).abs() < 1e-9);
+        assert!((merged.mean - 0.5).abs() < 1e-9);
+        // Half the weight at 1.0 and half at 0.0
+        assert!((merged.variance - 0.25).abs() < 1e-9);
+        assert_eq!(merged.weeks, MIN_BASELINE_WEEKS + 1);
+    }
+
+    #[test]
+    fn merge_keeps_the_variance_of_identical_weeks() {
+        let merged = baseline(0.2, 0.36, 20.0).merge(0.2, 0.36, 20.0);
+        assert!((merged.mean - 0.2).abs() < 1e-9);
+        assert!((merged.variance - 0.36).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::config::EnvConfig;
+use crate::crypto::{reencrypt_messages, Keyring};
+use crate::export::{export_messages, ExportOptions, ALL_FIELDS};
+use crate::pseudonym::{load_pseudonymizer, Pseudonymizer};
+use chrono::NaiveDate;
+use mongodb::Database;
+use std::env;
+
+const COMMANDS: &[&str] = &[
+    "reidentify <pseudonym>",
+    "rotate-pseudonym-key",
+    "reencrypt",
+    "export",
+];
+
+// Runs a one-off maintenance command instead of the bot, e.g.
+// `discord-emotion-tracker reidentify u_3f2a...`.
+pub async fn run_command(args: &[String], config: &EnvConfig, db: &Database) {
+    let command = args.first().map(String::as_str).unwrap_or_default();
+
+    match command {
+        "reidentify" => reidentify(&args[1..], config).await,
+        "rotate-pseudonym-key" => rotate_pseudonym_key(config, db).await,
+        "reencrypt" => reencrypt(config, db).await,
+        "export" => export(&args[1..], config, db).await,
+        _ => {
+            println!("Unknown command: {}", command);
+            println!("Available commands: {}", COMMANDS.join(", "));
+        }
+    }
+}
+
+async fn pseudonymizer(config: &EnvConfig) -> Pseudonymizer {
+    load_pseudonymizer(config)
+        .await
+        .expect("Pseudonymization is not configured")
+}
+
+async fn reidentify(args: &[String], config: &EnvConfig) {
+    let pseudonym = args.first().expect("Usage: reidentify <pseudonym>");
+
+    match pseudonymizer(config).await.reidentify(pseudonym).await {
+        Ok(Some(author_id)) => println!("{} -> {}", pseudonym, author_id),
+        Ok(None) => println!("No identity found for {}", pseudonym),
+        Err(e) => println!("Error looking up pseudonym: {:?}", e),
+    }
+}
+
+// The new key is read from `NEW_PSEUDONYM_KEY` so that it doesn't end up in the shell
+// history. Update `pseudonymization.key` in the config once the rotation has finished.
+async fn rotate_pseudonym_key(config: &EnvConfig, db: &Database) {
+    let new_key = env::var("NEW_PSEUDONYM_KEY").expect("NEW_PSEUDONYM_KEY is not set");
+
+    match pseudonymizer(config).await.rotate_key(db, &new_key).await {
+        Ok(rotated) => println!(
+            "Rotated {} pseudonym(s). Update pseudonymization.key in the config before restarting.",
+            rotated
+        ),
+        Err(e) => println!("Error rotating pseudonyms: {:?}", e),
+    }
+}
+
+// Add the new key to `encryption.keys`, make it the `active_key`, then run this to move
+// every message onto it. The old key can be removed from the config afterwards.
+async fn reencrypt(config: &EnvConfig, db: &Database) {
+    let encryption = config
+        .encryption
+        .as_ref()
+        .expect("Encryption is not configured");
+    let keyring = Keyring::new(encryption);
+
+    match reencrypt_messages(db, &keyring).await {
+        Ok(reencrypted) => println!(
+            "Re-encrypted {} message(s) under key {}.",
+            reencrypted,
+            keyring.active_key()
+        ),
+        Err(e) => println!("Error re-encrypting messages: {}", e),
+    }
+}
+
+const EXPORT_USAGE: &str = "Usage: export --format csv|json|parquet --output <path> \
+[--from YYYY-MM-DD] [--to YYYY-MM-DD] [--channel <name>] [--sentiment <label>] \
+[--fields a,b,c] [--redact-text] [--include-spam]";
+
+async fn export(args: &[String], config: &EnvConfig, db: &Database) {
+    let options = match parse_export_options(args) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}", e);
+            println!("{}", EXPORT_USAGE);
+            println!("Fields: {}", ALL_FIELDS.join(","));
+            return;
+        }
+    };
+
+    let keyring = config.encryption.as_ref().map(Keyring::new);
+
+    match export_messages(db, keyring.as_ref(), &options).await {
+        Ok(exported) => println!("Exported {} message(s) to {}", exported, options.output),
+        Err(e) => println!("Error exporting messages: {}", e),
+    }
+}
+
+fn parse_export_options(args: &[String]) -> Result<ExportOptions, String> {
+    let parse_date = |name: &str| -> Result<Option<NaiveDate>, String> {
+        flag_value(args, name)
+            .map(|value| {
+                NaiveDate::parse_from_str(&value, "%Y-%m-%d")
+                    .map_err(|_| format!("Invalid date for {}: {}", name, value))
+            })
+            .transpose()
+    };
+
+    Ok(ExportOptions {
+        format: flag_value(args, "--format")
+            .ok_or("Missing --format")?
+            .parse()?,
+        output: flag_value(args, "--output").ok_or("Missing --output")?,
+        from: parse_date("--from")?,
+        to: parse_date("--to")?,
+        channel: flag_value(args, "--channel"),
+        sentiment: flag_value(args, "--sentiment"),
+        fields: flag_value(args, "--fields")
+            .map(|fields| fields.split(',').map(|f| f.trim().to_string()).collect())
+            .unwrap_or_default(),
+        redact_text: args.iter().any(|arg| arg == "--redact-text"),
+        include_spam: args.iter().any(|arg| arg == "--include-spam"),
+    })
+}
+
+fn flag_value(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|index| args.get(index + 1))
+        .cloned()
+}
@@ -0,0 +1,511 @@
+use crate::crypto::Keyring;
+use crate::mongo::read_messages;
+use crate::phrases::STOPWORDS;
+use crate::util::local_now;
+use chrono::Duration;
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::error::Error;
+use mongodb::options::FindOneOptions;
+use mongodb::Database;
+use std::collections::{HashMap, HashSet};
+
+const CLUSTER_COLLECTION: &str = "topic_clusters";
+
+// Top terms stored per cluster; the first few make up its label
+const CLUSTER_TERMS: usize = 8;
+const LABEL_TERMS: usize = 4;
+
+const MAX_ITERATIONS: usize = 25;
+
+// Clusters sharing less than this (Jaccard similarity of their top terms) with every cluster
+// of the previous run are new
+const NEW_CLUSTER_SIMILARITY: f64 = 0.25;
+
+// Clusters smaller than this are noise and aren't reported
+pub const MIN_CLUSTER_SIZE: i64 = 3;
+
+#[derive(Debug, Clone, Default)]
+pub struct TopicCluster {
+    pub id: ObjectId,
+    pub label: String,
+    pub terms: Vec<String>,
+    pub size: i64,
+    pub positive: i64,
+    pub negative: i64,
+    pub analyzed: i64,
+    pub is_new: bool,
+}
+
+impl TopicCluster {
+    // Share of the analyzed messages that are negative
+    pub fn negative_share(&self) -> f64 {
+        self.negative as f64 / self.analyzed.max(1) as f64
+    }
+}
+
+struct ClusterDocument {
+    id: ObjectId,
+    terms: Vec<String>,
+    sentiment: Option<String>,
+}
+
+// Clusters the community messages of the past week with TF-IDF vectors and spherical
+// k-means, stores the clusters in `topic_clusters` and each message's `clusterId`, and
+// returns the clusters largest first. Returns no clusters when there are too few messages.
+pub async fn cluster_weekly_messages(
+    db: &Database,
+    keyring: Option<&Keyring>,
+    max_clusters: usize,
+    min_messages: usize,
+) -> Result<Vec<TopicCluster>, Error> {
+    let until = local_now();
+    let since = until - Duration::weeks(1);
+
+    let filter = doc! {
+        "createdAt": { "$gte": bson::DateTime::from_chrono(since) },
+        "role": { "$ne": "staff" },
+        "spam": { "$exists": false },
+    };
+    let mut reader = read_messages(db, filter, keyring).await?;
+    let mut documents = Vec::new();
+    while let Some(message) = reader.next().await? {
+        let Some(id) = message.id else {
+            continue;
+        };
+        let terms = tokenize(&message.text);
+        if !terms.is_empty() {
+            documents.push(ClusterDocument {
+                id,
+                terms,
+                sentiment: message.analyzed,
+            });
+        }
+    }
+    if documents.len() < min_messages {
+        return Ok(Vec::new());
+    }
+
+    let (vectors, vocabulary) = tf_idf(&documents);
+    let k = ((documents.len() as f64 / 2.0).sqrt() as usize).clamp(2, max_clusters.max(2));
+    let (assignments, centroids) = k_means(&vectors, vocabulary.len(), k);
+
+    let previous = previous_cluster_terms(db, keyring).await?;
+    let run_at = bson::DateTime::from_chrono(until);
+    let collection = db.collection::<Document>(CLUSTER_COLLECTION);
+    let messages = db.collection::<Document>("messages");
+
+    let mut clusters = Vec::new();
+    for (index, centroid) in centroids.iter().enumerate() {
+        let members: Vec<&ClusterDocument> = documents
+            .iter()
+            .zip(&assignments)
+            .filter(|(_, assignment)| **assignment == Some(index))
+            .map(|(document, _)| document)
+            .collect();
+        if members.is_empty() {
+            continue;
+        }
+
+        let mut weighted: Vec<(usize, f64)> = centroid.iter().copied().enumerate().collect();
+        weighted.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let terms: Vec<String> = weighted
+            .iter()
+            .take(CLUSTER_TERMS)
+            .filter(|(_, weight)| *weight > 0.0)
+            .map(|(term, _)| vocabulary[*term].clone())
+            .collect();
+
+        let sentiment_count = |label: &str| {
+            members
+                .iter()
+                .filter(|member| member.sentiment.as_deref() == Some(label))
+                .count() as i64
+        };
+        let cluster = TopicCluster {
+            id: ObjectId::new(),
+            label: terms
+                .iter()
+                .take(LABEL_TERMS)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", "),
+            is_new: is_new(&previous, &terms),
+            terms,
+            size: members.len() as i64,
+            positive: sentiment_count("positive"),
+            negative: sentiment_count("negative"),
+            analyzed: members
+                .iter()
+                .filter(|member| member.sentiment.is_some())
+                .count() as i64,
+        };
+
+        let mut document = doc! {
+            "_id": cluster.id,
+            "runAt": run_at,
+            "since": bson::DateTime::from_chrono(since),
+            "until": run_at,
+            "size": cluster.size,
+            "positive": cluster.positive,
+            "negative": cluster.negative,
+            "analyzed": cluster.analyzed,
+            "isNew": cluster.is_new,
+        };
+        // The terms come from message text, so they are encrypted like the messages
+        match keyring {
+            Some(keyring) => match seal_terms(keyring, &cluster) {
+                Ok(sealed) => document.extend(sealed),
+                Err(e) => println!("Error encrypting cluster terms: {:?}", e),
+            },
+            None => document.extend(doc! { "label": &cluster.label, "terms": &cluster.terms }),
+        }
+        collection.insert_one(document, None).await?;
+        let ids: Vec<ObjectId> = members.iter().map(|member| member.id).collect();
+        messages
+            .update_many(
+                doc! { "_id": { "$in": ids } },
+                doc! { "$set": { "clusterId": cluster.id } },
+                None,
+            )
+            .await?;
+
+        clusters.push(cluster);
+    }
+
+    clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.size));
+    Ok(clusters)
+}
+
+fn seal_terms(
+    keyring: &Keyring,
+    cluster: &TopicCluster,
+) -> Result<Document, Box<dyn std::error::Error>> {
+    let terms = cluster
+        .terms
+        .iter()
+        .map(|term| keyring.encrypt("terms", term))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(doc! {
+        "label": keyring.encrypt("label", &cluster.label)?,
+        "terms": terms,
+        "keyId": keyring.active_key(),
+    })
+}
+
+// Top terms of the clusters from the latest earlier run. Encrypted terms that can't be
+// decrypted are left out.
+async fn previous_cluster_terms(
+    db: &Database,
+    keyring: Option<&Keyring>,
+) -> Result<Vec<Vec<String>>, Error> {
+    let collection = db.collection::<Document>(CLUSTER_COLLECTION);
+    let options = FindOneOptions::builder().sort(doc! { "runAt": -1 }).build();
+    let Some(run_at) = collection
+        .find_one(doc! {}, options)
+        .await?
+        .and_then(|latest| latest.get_datetime("runAt").ok().copied())
+    else {
+        return Ok(Vec::new());
+    };
+
+    let clusters: Vec<Document> = collection
+        .find(doc! { "runAt": run_at }, None)
+        .await?
+        .try_collect()
+        .await?;
+    Ok(clusters
+        .iter()
+        .map(|cluster| {
+            let key_id = cluster.get_str("keyId").ok();
+            cluster
+                .get_array("terms")
+                .map(|terms| {
+                    terms
+                        .iter()
+                        .filter_map(|term| term.as_str())
+                        .filter_map(|term| match (key_id, keyring) {
+                            (None, _) => Some(term.to_string()),
+                            (Some(key_id), Some(keyring)) => {
+                                keyring.decrypt(key_id, "terms", term).ok()
+                            }
+                            (Some(_), None) => None,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default()
+        })
+        .collect())
+}
+
+// The first run has nothing to compare with, so none of its clusters are new
+fn is_new(previous: &[Vec<String>], terms: &[String]) -> bool {
+    !previous.is_empty()
+        && previous
+            .iter()
+            .all(|previous| similarity(previous, terms) < NEW_CLUSTER_SIMILARITY)
+}
+
+fn similarity(a: &[String], b: &[String]) -> f64 {
+    let a: HashSet<&String> = a.iter().collect();
+    let b: HashSet<&String> = b.iter().collect();
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        // Redaction and link placeholders
+        .filter(|token| !token.starts_with('['))
+        .map(|token| {
+            token
+                .trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .filter(|word| {
+            word.chars().count() >= 3
+                && word.chars().all(char::is_alphabetic)
+                && !STOPWORDS.contains(&word.as_str())
+        })
+        .collect()
+}
+
+// L2-normalized sparse TF-IDF vectors. Terms in a single message or in more than half of
+// them say nothing about how messages group, so they are left out of the vocabulary.
+fn tf_idf(documents: &[ClusterDocument]) -> (Vec<Vec<(usize, f64)>>, Vec<String>) {
+    let mut document_frequency: HashMap<&str, usize> = HashMap::new();
+    for document in documents {
+        let unique: HashSet<&str> = document.terms.iter().map(String::as_str).collect();
+        for term in unique {
+            *document_frequency.entry(term).or_default() += 1;
+        }
+    }
+
+    let total = documents.len() as f64;
+    let mut vocabulary: Vec<String> = document_frequency
+        .iter()
+        .filter(|(_, frequency)| **frequency >= 2 && **frequency as f64 <= total / 2.0)
+        .map(|(term, _)| term.to_string())
+        .collect();
+    vocabulary.sort();
+    let index: HashMap<&str, usize> = vocabulary
+        .iter()
+        .enumerate()
+        .map(|(index, term)| (term.as_str(), index))
+        .collect();
+
+    let vectors = documents
+        .iter()
+        .map(|document| {
+            let mut counts: HashMap<usize, f64> = HashMap::new();
+            for term in &document.terms {
+                if let Some(term_index) = index.get(term.as_str()) {
+                    *counts.entry(*term_index).or_default() += 1.0;
+                }
+            }
+            let mut vector: Vec<(usize, f64)> = counts
+                .into_iter()
+                .map(|(term_index, count)| {
+                    let idf =
+                        (total / document_frequency[vocabulary[term_index].as_str()] as f64).ln();
+                    (term_index, count / document.terms.len() as f64 * idf)
+                })
+                .collect();
+            let norm = vector
+                .iter()
+                .map(|(_, weight)| weight * weight)
+                .sum::<f64>()
+                .sqrt();
+            if norm > 0.0 {
+                vector.iter_mut().for_each(|(_, weight)| *weight /= norm);
+            }
+            vector
+        })
+        .collect();
+
+    (vectors, vocabulary)
+}
+
+fn dot(vector: &[(usize, f64)], centroid: &[f64]) -> f64 {
+    vector
+        .iter()
+        .map(|(index, weight)| weight * centroid[*index])
+        .sum()
+}
+
+// Spherical k-means (cosine similarity) with deterministic farthest-first initialization.
+// Messages without any vocabulary term are left unassigned.
+fn k_means(
+    vectors: &[Vec<(usize, f64)>],
+    dimensions: usize,
+    k: usize,
+) -> (Vec<Option<usize>>, Vec<Vec<f64>>) {
+    let to_dense = |vector: &[(usize, f64)]| {
+        let mut dense = vec![0.0; dimensions];
+        for (index, weight) in vector {
+            dense[*index] = *weight;
+        }
+        dense
+    };
+
+    let candidates: Vec<usize> = (0..vectors.len())
+        .filter(|index| !vectors[*index].is_empty())
+        .collect();
+    let Some(first) = candidates
+        .iter()
+        .copied()
+        .max_by_key(|index| vectors[*index].len())
+    else {
+        return (vec![None; vectors.len()], Vec::new());
+    };
+
+    let mut centroids = vec![to_dense(&vectors[first])];
+    while centroids.len() < k.min(candidates.len()) {
+        let farthest = candidates
+            .iter()
+            .copied()
+            .min_by(|a, b| {
+                let closest = |index: usize| {
+                    centroids
+                        .iter()
+                        .map(|centroid| dot(&vectors[index], centroid))
+                        .fold(f64::MIN, f64::max)
+                };
+                closest(*a).total_cmp(&closest(*b))
+            })
+            .unwrap();
+        centroids.push(to_dense(&vectors[farthest]));
+    }
+
+    let mut assignments: Vec<Option<usize>> = vec![None; vectors.len()];
+    for _ in 0..MAX_ITERATIONS {
+        let mut changed = false;
+        for index in &candidates {
+            let best = (0..centroids.len()).max_by(|a, b| {
+                dot(&vectors[*index], &centroids[*a])
+                    .total_cmp(&dot(&vectors[*index], &centroids[*b]))
+            });
+            if assignments[*index] != best {
+                assignments[*index] = best;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+
+        // Empty clusters keep their previous centroid
+        for (cluster, centroid) in centroids.iter_mut().enumerate() {
+            let mut sum = vec![0.0; dimensions];
+            let mut members = 0;
+            for index in &candidates {
+                if assignments[*index] == Some(cluster) {
+                    for (term, weight) in &vectors[*index] {
+                        sum[*term] += weight;
+                    }
+                    members += 1;
+                }
+            }
+            let norm = sum.iter().map(|weight| weight * weight).sum::<f64>().sqrt();
+            if members > 0 && norm > 0.0 {
+                *centroid = sum.into_iter().map(|weight| weight / norm).collect();
+            }
+        }
+    }
+
+    (assignments, centroids)
+}
+
+// New clusters big enough to report
+pub fn emerging_clusters(clusters: &[TopicCluster]) -> Vec<&TopicCluster> {
+    clusters
+        .iter()
+        .filter(|cluster| cluster.is_new && cluster.size >= MIN_CLUSTER_SIZE)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(terms: &[&str]) -> Vec<String> {
+        terms.iter().map(|term| term.to_string()).collect()
+    }
+
+    #[test]
+    fn clusters_are_only_new_against_a_previous_run() {
+        let cluster = terms(&["wallet", "sync", "stuck", "ledger"]);
+        assert!(!is_new(&[], &cluster));
+
+        let previous = vec![terms(&["staking", "rewards", "delayed", "apr"])];
+        assert!(is_new(&previous, &cluster));
+
+        let previous = vec![terms(&["wallet", "sync", "slow", "ledger"])];
+        assert!(!is_new(&previous, &cluster));
+    }
+
+    fn document(words: &[&str]) -> ClusterDocument {
+        ClusterDocument {
+            id: ObjectId::new(),
+            terms: terms(words),
+            sentiment: None,
+        }
+    }
+
+    #[test]
+    fn tokenize_keeps_alphabetic_content_words() {
+        assert_eq!(
+            tokenize("The [link] WALLET sync is b0rked, try again!! 2nd time"),
+            terms(&["wallet", "sync", "try", "time"])
+        );
+    }
+
+    fn corpus() -> Vec<ClusterDocument> {
+        vec![
+            document(&["wallet", "sync", "broken", "bridge"]),
+            document(&["wallet", "sync", "failing", "bridge"]),
+            document(&["wallet", "sync", "stuck", "bridge"]),
+            document(&["staking", "rewards", "missing", "bridge"]),
+            document(&["staking", "rewards", "delayed"]),
+            document(&["staking", "rewards", "late"]),
+            document(&["hello"]),
+        ]
+    }
+
+    #[test]
+    fn tf_idf_leaves_out_rare_and_common_terms() {
+        let (vectors, vocabulary) = tf_idf(&corpus());
+
+        // "bridge" is in more than half of the messages, the rest in only one
+        assert_eq!(vocabulary, terms(&["rewards", "staking", "sync", "wallet"]));
+        assert!(vectors[6].is_empty());
+        for vector in &vectors[..6] {
+            let norm: f64 = vector.iter().map(|(_, weight)| weight * weight).sum();
+            assert!((norm - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn k_means_separates_the_topics_deterministically() {
+        let (vectors, vocabulary) = tf_idf(&corpus());
+        let (assignments, centroids) = k_means(&vectors, vocabulary.len(), 2);
+
+        // Farthest-first starts from the last of the largest vectors
+        let staking = Some(0);
+        let wallet = Some(1);
+        assert_eq!(
+            assignments,
+            vec![wallet, wallet, wallet, staking, staking, staking, None]
+        );
+        let half = 1.0 / 2f64.sqrt();
+        assert_eq!(centroids[1], vec![0.0, 0.0, half, half]);
+
+        assert_eq!(
+            k_means(&vectors, vocabulary.len(), 2),
+            (assignments, centroids)
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use crate::charts::{sentiment_charts, Chart};
+use crate::discord::{
+    channel_health_embed, get_channel_info, privacy_deletion_embed, send_embed_to_user_http,
+    sentiment_embed, support_stats_embed, Handler,
+};
+use crate::health::{channel_health_history, local_midnight};
+use crate::privacy::{delete_user_data, opt_in, opt_out};
+use crate::report::{channel_sentiment, daily_sentiment, emotion_breakdown, sentiment_counts};
+use crate::support::support_stats;
+use crate::util::local_now;
+use chrono::Duration;
+use serenity::builder::{CreateEmbed, EditInteractionResponse};
+use serenity::http::multipart::Multipart;
+use serenity::http::request::RequestBuilder;
+use serenity::http::routing::RouteInfo;
+use serenity::json::{hashmap_to_json_map, Value};
+use serenity::model::application::command::CommandOptionType;
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::model::application::interaction::InteractionResponseType;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId};
+use serenity::model::permissions::Permissions;
+use serenity::prelude::*;
+
+// Registers the slash commands for the guild. `set_application_commands` overwrites the
+// existing set, so every command has to be declared here.
+pub async fn register_commands(ctx: &Context, guild_id: GuildId) -> Result<(), serenity::Error> {
+    guild_id
+        .set_application_commands(&ctx.http, |commands| {
+            commands.create_application_command(|command| {
+                command
+                    .name("privacy")
+                    .description("Manage how the emotion tracker handles your messages")
+                    .create_option(|option| {
+                        option
+                            .name("optout")
+                            .description("Stop storing and analyzing your messages")
+                            .kind(CommandOptionType::SubCommand)
+                    })
+                    .create_option(|option| {
+                        option
+                            .name("optin")
+                            .description("Allow your messages to be analyzed again")
+                            .kind(CommandOptionType::SubCommand)
+                    })
+                    .create_option(|option| {
+                        option
+                            .name("delete-my-data")
+                            .description("Delete every stored message you have written")
+                            .kind(CommandOptionType::SubCommand)
+                    })
+            });
+            commands.create_application_command(|command| {
+                command
+                    .name("support-stats")
+                    .description("Show support response times and unanswered questions")
+                    .default_member_permissions(Permissions::MANAGE_MESSAGES)
+                    .create_option(|option| {
+                        option
+                            .name("days")
+                            .description("Number of days to include (default 7)")
+                            .kind(CommandOptionType::Integer)
+                            .min_int_value(1)
+                            .max_int_value(21)
+                    })
+            });
+            commands.create_application_command(|command| {
+                command
+                    .name("sentiment")
+                    .description("Show community sentiment with charts")
+                    .create_option(|option| {
+                        option
+                            .name("days")
+                            .description("Number of days to include (default 7)")
+                            .kind(CommandOptionType::Integer)
+                            .min_int_value(1)
+                            .max_int_value(21)
+                    })
+            });
+            commands.create_application_command(|command| {
+                command
+                    .name("health")
+                    .description("Show channel health scores")
+                    .default_member_permissions(Permissions::MANAGE_MESSAGES)
+                    .create_option(|option| {
+                        option
+                            .name("channel")
+                            .description("Daily health of a channel over the last week")
+                            .kind(CommandOptionType::SubCommand)
+                            .create_sub_option(|sub_option| {
+                                sub_option
+                                    .name("channel")
+                                    .description("Channel to show (default: this channel)")
+                                    .kind(CommandOptionType::Channel)
+                            })
+                    })
+            })
+        })
+        .await?;
+
+    Ok(())
+}
+
+pub async fn handle_command(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    handler: &Handler,
+) {
+    let result = match command.data.name.as_str() {
+        "privacy" => respond_privacy(ctx, command, handler).await,
+        "support-stats" => match handle_support_stats(command, handler).await {
+            Ok(embed) => respond_embed(ctx, command, embed).await,
+            Err(content) => respond_ephemeral(ctx, command, &content).await,
+        },
+        "sentiment" => respond_sentiment(ctx, command, handler).await,
+        "health" => match handle_health(ctx, command, handler).await {
+            Ok(embed) => respond_embed(ctx, command, embed).await,
+            Err(content) => respond_ephemeral(ctx, command, &content).await,
+        },
+        _ => respond_ephemeral(ctx, command, "Unknown command.").await,
+    };
+
+    if let Err(e) = result {
+        println!("Error responding to /{}: {:?}", command.data.name, e);
+    }
+}
+
+async fn handle_support_stats(
+    command: &ApplicationCommandInteraction,
+    handler: &Handler,
+) -> Result<CreateEmbed, String> {
+    let days = integer_option(command, "days").unwrap_or(7);
+    let since = local_now() - Duration::days(days);
+
+    match support_stats(&handler.db, since).await {
+        Ok(stats) => Ok(support_stats_embed(&stats, days)),
+        Err(e) => {
+            println!("Error computing support stats: {:?}", e);
+            Err("Something went wrong while computing support statistics.".to_string())
+        }
+    }
+}
+
+// The aggregations and chart rendering can take longer than Discord's 3 second response
+// window, so the reply is deferred and filled in afterwards
+async fn respond_sentiment(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    handler: &Handler,
+) -> Result<(), serenity::Error> {
+    defer(ctx, command, false).await?;
+    match handle_sentiment(command, handler).await {
+        Ok((embed, charts)) => edit_charts(ctx, command, embed, charts).await,
+        Err(content) => edit_content(ctx, command, &content).await,
+    }
+}
+
+async fn handle_sentiment(
+    command: &ApplicationCommandInteraction,
+    handler: &Handler,
+) -> Result<(CreateEmbed, Vec<Chart>), String> {
+    let days = integer_option(command, "days").unwrap_or(7);
+    let until = local_now();
+    let since = until - Duration::days(days);
+
+    let db = &handler.db;
+    let result = async {
+        let counts = sentiment_counts(db, since).await?;
+        let daily = daily_sentiment(db, since, until).await?;
+        let channels = channel_sentiment(db, since).await?;
+        let (emotion_messages, emotions) = emotion_breakdown(db, since).await?;
+        Ok::<_, mongodb::error::Error>((counts, daily, channels, emotion_messages, emotions))
+    }
+    .await;
+
+    match result {
+        Ok((counts, daily, channels, emotion_messages, emotions)) => {
+            let charts = if handler.charts {
+                sentiment_charts(&daily, &channels, &emotions)
+            } else {
+                Vec::new()
+            };
+            let mut embed = sentiment_embed(&counts, &emotions, emotion_messages, days);
+            if let Some(chart) = charts.first() {
+                embed.image(chart.url());
+            }
+            Ok((embed, charts))
+        }
+        Err(e) => {
+            println!("Error computing sentiment: {:?}", e);
+            Err("Something went wrong while computing sentiment.".to_string())
+        }
+    }
+}
+
+async fn handle_health(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    handler: &Handler,
+) -> Result<CreateEmbed, String> {
+    let Some(subcommand) = command.data.options.first() else {
+        return Err("Unknown health command.".to_string());
+    };
+    if subcommand.name != "channel" {
+        return Err("Unknown health command.".to_string());
+    }
+
+    // Health is stored per channel name; threads count toward their parent channel
+    let channel_id = subcommand
+        .options
+        .iter()
+        .find(|option| option.name == "channel")
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+        .and_then(|id| id.parse::<u64>().ok())
+        .map(ChannelId)
+        .unwrap_or(command.channel_id);
+    let Some((channel, _)) = get_channel_info(ctx, channel_id).await else {
+        return Err("Couldn't find that channel.".to_string());
+    };
+
+    let since = local_midnight(local_now()) - Duration::days(7);
+    match channel_health_history(&handler.db, &channel, since).await {
+        Ok(days) => Ok(channel_health_embed(&channel, &days)),
+        Err(e) => {
+            println!("Error loading channel health: {:?}", e);
+            Err("Something went wrong while loading channel health.".to_string())
+        }
+    }
+}
+
+fn integer_option(command: &ApplicationCommandInteraction, name: &str) -> Option<i64> {
+    command
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_i64())
+}
+
+// Deleting data runs over every user data collection, which can outlast Discord's response
+// window, so privacy replies are deferred as ephemeral and filled in afterwards
+async fn respond_privacy(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    handler: &Handler,
+) -> Result<(), serenity::Error> {
+    defer(ctx, command, true).await?;
+    let content = handle_privacy(ctx, command, handler).await;
+    edit_content(ctx, command, &content).await
+}
+
+async fn handle_privacy(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    handler: &Handler,
+) -> String {
+    let db = &handler.db;
+    let opt_outs = &handler.opt_outs;
+    let user = &command.user;
+    let subcommand = command
+        .data
+        .options
+        .first()
+        .map(|option| option.name.as_str())
+        .unwrap_or_default();
+
+    match subcommand {
+        "optout" => match opt_out(db, opt_outs, user.id.0).await {
+            Ok(_) => "You have opted out. Your messages will no longer be stored or analyzed."
+                .to_string(),
+            Err(e) => {
+                println!("Error opting out user {}: {:?}", user.id, e);
+                "Something went wrong while opting you out. Please try again later.".to_string()
+            }
+        },
+        "optin" => match opt_in(db, opt_outs, user.id.0).await {
+            Ok(_) => "You have opted back in. Your messages will be analyzed again.".to_string(),
+            Err(e) => {
+                println!("Error opting in user {}: {:?}", user.id, e);
+                "Something went wrong while opting you in. Please try again later.".to_string()
+            }
+        },
+        "delete-my-data" => {
+            match delete_user_data(db, handler.pseudonymizer.as_ref(), user.id.0).await {
+                Ok(deleted_count) => {
+                    let embed = privacy_deletion_embed(deleted_count);
+                    if let Err(e) = send_embed_to_user_http(&ctx.http, user.id.0, embed).await {
+                        println!("Error sending deletion confirmation DM: {:?}", e);
+                    }
+                    format!(
+                    "Deleted {} stored document(s). Messages stored before author IDs were recorded can't be traced back to you and expire with the three-week retention. Use `/privacy optout` to stop future collection.",
+                    deleted_count
+                )
+                }
+                Err(e) => {
+                    println!("Error deleting data for user {}: {:?}", user.id, e);
+                    "Something went wrong while deleting your data. Please try again later."
+                        .to_string()
+                }
+            }
+        }
+        _ => "Unknown privacy command.".to_string(),
+    }
+}
+
+async fn respond_ephemeral(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    content: &str,
+) -> Result<(), serenity::Error> {
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|data| data.content(content).ephemeral(true))
+        })
+        .await
+}
+
+async fn respond_embed(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    embed: CreateEmbed,
+) -> Result<(), serenity::Error> {
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|data| data.set_embed(embed))
+        })
+        .await
+}
+
+async fn defer(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    ephemeral: bool,
+) -> Result<(), serenity::Error> {
+    command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                .interaction_response_data(|data| data.ephemeral(ephemeral))
+        })
+        .await
+}
+
+async fn edit_content(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    content: &str,
+) -> Result<(), serenity::Error> {
+    command
+        .edit_original_interaction_response(&ctx.http, |response| response.content(content))
+        .await?;
+    Ok(())
+}
+
+// `edit_original_interaction_response` can't upload files in this serenity version, so
+// the edit is sent as a multipart request on the same route
+async fn edit_charts(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    embed: CreateEmbed,
+    charts: Vec<Chart>,
+) -> Result<(), serenity::Error> {
+    let mut response = EditInteractionResponse::default();
+    response.set_embed(embed);
+
+    let mut request = RequestBuilder::new(RouteInfo::EditOriginalInteractionResponse {
+        application_id: command.application_id.0,
+        interaction_token: &command.token,
+    });
+    request.multipart(Some(Multipart {
+        files: charts.into_iter().map(|chart| chart.attachment()).collect(),
+        fields: Vec::new(),
+        payload_json: Some(Value::from(hashmap_to_json_map(response.0))),
+    }));
+    ctx.http.fire::<Message>(request.build()).await?;
+    Ok(())
+}
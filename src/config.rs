@@ -1,6 +1,12 @@
+use crate::emoji::Polarity;
+use crate::emotion::EMOTIONS;
+use crate::toxicity::ToxicityCategory;
+use regex::Regex;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::env;
 use std::fs;
+use std::net::SocketAddr;
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
@@ -16,6 +22,537 @@ pub struct EnvConfig {
     pub aws_access_key_id: Option<String>,
     pub aws_secret_access_key: Option<String>,
     pub aws_region: Option<String>,
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+    pub pseudonymization: Option<PseudonymConfig>,
+    pub encryption: Option<EncryptionConfig>,
+    #[serde(default)]
+    pub sessions: SessionConfig,
+    #[serde(default)]
+    pub reports: ReportConfig,
+    #[serde(default)]
+    pub reactions: ReactionConfig,
+    #[serde(default)]
+    pub media: MediaConfig,
+    #[serde(default)]
+    pub links: LinkConfig,
+    #[serde(default)]
+    pub spam: SpamConfig,
+    #[serde(default)]
+    pub toxicity: ToxicityConfig,
+    #[serde(default)]
+    pub emotions: EmotionConfig,
+    #[serde(default)]
+    pub phrases: PhraseConfig,
+    // Topic taxonomy messages are tagged with
+    #[serde(default)]
+    pub topics: Vec<TopicRule>,
+    #[serde(default)]
+    pub aspects: AspectConfig,
+    #[serde(default)]
+    pub clustering: ClusteringConfig,
+    #[serde(default)]
+    pub watchlists: Vec<WatchlistRule>,
+    #[serde(default)]
+    pub churn: ChurnConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub anomalies: AnomalyConfig,
+    #[serde(default)]
+    pub announcements: AnnouncementConfig,
+    #[serde(default)]
+    pub charts: ChartConfig,
+    #[serde(default)]
+    pub dashboard: DashboardConfig,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RedactionConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    // Additional patterns redacted as `[NAME]`, e.g. internal order IDs
+    #[serde(default)]
+    pub custom_patterns: Vec<CustomPattern>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CustomPattern {
+    pub name: String,
+    pub pattern: String,
+}
+
+// When present, authors are stored as keyed HMAC pseudonyms instead of usernames. The
+// pseudonym -> author ID map lives in `identity_database`, which analysts shouldn't be
+// granted access to.
+#[derive(Debug, Deserialize)]
+pub struct PseudonymConfig {
+    pub key: String,
+    #[serde(default = "default_identity_database")]
+    pub identity_database: String,
+}
+
+// When present, `text`, `korean` and the phrases derived from them are encrypted with the
+// key named by `active_key`.
+// Older keys stay listed so that documents encrypted under them can still be read.
+#[derive(Debug, Deserialize)]
+pub struct EncryptionConfig {
+    pub active_key: String,
+    pub keys: Vec<EncryptionKey>,
+}
+
+// A base64-encoded 256-bit key, given inline or read from a secret file
+#[derive(Debug, Deserialize)]
+pub struct EncryptionKey {
+    pub id: String,
+    pub key: Option<String>,
+    pub key_file: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SessionConfig {
+    // Messages further apart than this start a new conversation session, unless they reply
+    // to a message in an earlier session
+    #[serde(default = "default_session_gap_minutes")]
+    pub gap_minutes: i64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            gap_minutes: default_session_gap_minutes(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportConfig {
+    // Channel the weekly digest is posted to; defaults to the notify channel
+    #[serde(default = "default_notify_channel_id")]
+    pub channel_id: u64,
+    // Cron expression in Seoul time
+    #[serde(default = "default_report_schedule")]
+    pub schedule: String,
+}
+
+impl Default for ReportConfig {
+    fn default() -> Self {
+        ReportConfig {
+            channel_id: default_notify_channel_id(),
+            schedule: default_report_schedule(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct ReactionConfig {
+    // Polarity of custom guild emojis by name, e.g. `pepe_sad: negative`
+    #[serde(default)]
+    pub custom_emoji: HashMap<String, Polarity>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct MediaConfig {
+    // Append link preview and embed titles/descriptions to the text sent for sentiment
+    // analysis; they are stored either way
+    #[serde(default)]
+    pub analyze_embed_text: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LinkConfig {
+    // Known scam/phishing domains; subdomains are blocked too
+    #[serde(default)]
+    pub blocklist: Vec<String>,
+    // Additional blocked domains, one per line, for lists too long to keep in the config
+    pub blocklist_file: Option<String>,
+    // Channel moderation alerts are posted to; defaults to the notify channel
+    #[serde(default = "default_notify_channel_id")]
+    pub alert_channel_id: u64,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        LinkConfig {
+            blocklist: Vec::new(),
+            blocklist_file: None,
+            alert_channel_id: default_notify_channel_id(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct SpamConfig {
+    pub enabled: bool,
+    // Sliding window the thresholds below apply to
+    pub window_seconds: i64,
+    // Copies of the same or nearly the same text, across all users
+    pub duplicate_threshold: usize,
+    // Messages from a single user
+    pub flood_messages: usize,
+    // Accounts created or joined within this many days count as new
+    pub new_account_days: i64,
+    // Distinct new accounts posting
+    pub new_account_burst: usize,
+    pub alert_cooldown_minutes: i64,
+    // Channel moderation alerts are posted to; defaults to the notify channel
+    pub alert_channel_id: u64,
+}
+
+impl Default for SpamConfig {
+    fn default() -> Self {
+        SpamConfig {
+            enabled: true,
+            window_seconds: 60,
+            duplicate_threshold: 3,
+            flood_messages: 10,
+            new_account_days: 7,
+            new_account_burst: 5,
+            alert_cooldown_minutes: 10,
+            alert_channel_id: default_notify_channel_id(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ToxicityProviderKind {
+    // Keyword/profanity list, no external calls
+    #[default]
+    Offline,
+    // AWS Comprehend toxic content detection
+    Comprehend,
+    Disabled,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ToxicityConfig {
+    pub provider: ToxicityProviderKind,
+    // Scores (0 to 1) at or above which a moderator alert is posted; categories without a
+    // threshold never alert, so alerting is off until thresholds are configured
+    pub alert_thresholds: HashMap<ToxicityCategory, f64>,
+    // Terms added to the offline list, by category
+    pub extra_terms: HashMap<ToxicityCategory, Vec<String>>,
+    pub alert_channel_id: u64,
+}
+
+impl Default for ToxicityConfig {
+    fn default() -> Self {
+        ToxicityConfig {
+            provider: ToxicityProviderKind::default(),
+            alert_thresholds: HashMap::new(),
+            extra_terms: HashMap::new(),
+            alert_channel_id: default_notify_channel_id(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmotionProviderKind {
+    // Built-in word lexicon, no external calls
+    #[default]
+    Offline,
+    Disabled,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct EmotionConfig {
+    pub provider: EmotionProviderKind,
+    // Words added to the lexicon, by emotion (joy, anger, fear, sadness, surprise, frustration)
+    pub extra_terms: HashMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PhraseProviderKind {
+    // RAKE keyword extraction, no external calls; extracts no entities
+    #[default]
+    Offline,
+    // AWS Comprehend key phrase and entity detection
+    Comprehend,
+    Disabled,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct PhraseConfig {
+    pub provider: PhraseProviderKind,
+    // Key phrases kept per message
+    pub max_phrases: usize,
+}
+
+impl Default for PhraseConfig {
+    fn default() -> Self {
+        PhraseConfig {
+            provider: PhraseProviderKind::default(),
+            max_phrases: 5,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AspectProviderKind {
+    // Clause splitting with the emotion lexicon; only finds the configured topics
+    #[default]
+    Offline,
+    // AWS Comprehend targeted sentiment
+    Comprehend,
+    Disabled,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct AspectConfig {
+    pub provider: AspectProviderKind,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ClusteringConfig {
+    pub enabled: bool,
+    // Cron expression in Seoul time
+    pub schedule: String,
+    // Channel the emerging topics embed is posted to; defaults to the notify channel
+    pub channel_id: u64,
+    pub max_clusters: usize,
+    // Weeks with fewer messages aren't clustered
+    pub min_messages: usize,
+}
+
+impl Default for ClusteringConfig {
+    fn default() -> Self {
+        ClusteringConfig {
+            enabled: true,
+            schedule: "0 0 9 * * MON".to_string(),
+            channel_id: default_notify_channel_id(),
+            max_clusters: 8,
+            min_messages: 30,
+        }
+    }
+}
+
+// A message is tagged with the topic when any keyword (a whole word or phrase, any case) or
+// regex pattern matches its text or key phrases
+#[derive(Debug, Deserialize)]
+pub struct TopicRule {
+    pub name: String,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default)]
+    pub patterns: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ChurnConfig {
+    pub enabled: bool,
+    // Cron expression in Seoul time
+    pub schedule: String,
+    // Channel the at-risk members report is posted to; defaults to the notify channel
+    pub channel_id: u64,
+    // Members need this many analyzed messages in their baseline to be flagged
+    pub min_baseline_messages: usize,
+    // ...and this many in the past week
+    pub min_week_messages: usize,
+    // How many standard errors below the baseline the week's average must fall
+    pub z_threshold: f64,
+    // Minimum drop of the average sentiment score (-1 to 1)
+    pub min_drop: f64,
+}
+
+impl Default for ChurnConfig {
+    fn default() -> Self {
+        ChurnConfig {
+            enabled: true,
+            schedule: "0 30 9 * * MON".to_string(),
+            channel_id: default_notify_channel_id(),
+            min_baseline_messages: 20,
+            min_week_messages: 5,
+            z_threshold: 2.33,
+            min_drop: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    pub enabled: bool,
+    // Cron expression in Seoul time; each run scores the previous day
+    pub schedule: String,
+    // Messages with any toxicity score at or above this count as toxic
+    pub toxicity_threshold: f64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            enabled: true,
+            schedule: "0 5 0 * * *".to_string(),
+            toxicity_threshold: 0.5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AnomalyConfig {
+    pub enabled: bool,
+    // Cron expression in Seoul time; each run checks the previous hour
+    pub schedule: String,
+    // Channel anomaly alerts are posted to; defaults to the notify channel
+    pub alert_channel_id: u64,
+    // Robust standard deviations from the seasonal median
+    pub threshold: f64,
+    // Surges need this many messages in the hour, silences this many expected
+    pub min_messages: i64,
+    // Negative share of a surge's analyzed messages that makes it a likely incident
+    pub incident_negative_share: f64,
+    // Hours before the same channel alerts the same kind of anomaly again
+    pub cooldown_hours: i64,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        AnomalyConfig {
+            enabled: true,
+            schedule: "0 5 * * * *".to_string(),
+            alert_channel_id: default_notify_channel_id(),
+            threshold: 4.0,
+            min_messages: 10,
+            incident_negative_share: 0.4,
+            cooldown_hours: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AnnouncementConfig {
+    // Channels whose posts are recorded as announcements instead of being analyzed
+    pub channel_ids: Vec<u64>,
+    // Windows compared before and after each announcement; the impact report is posted
+    // once the after window has passed
+    pub before_hours: i64,
+    pub after_hours: i64,
+    // Cron expression in Seoul time for checking for due impact reports
+    pub schedule: String,
+    // Channel impact reports are posted to; defaults to the notify channel
+    pub report_channel_id: u64,
+}
+
+impl Default for AnnouncementConfig {
+    fn default() -> Self {
+        AnnouncementConfig {
+            channel_ids: vec![537522976963166218],
+            before_hours: 24,
+            after_hours: 24,
+            schedule: "0 */10 * * * *".to_string(),
+            report_channel_id: default_notify_channel_id(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ChartConfig {
+    // Attach PNG charts to the weekly digest and `/sentiment`
+    pub enabled: bool,
+    // TrueType font chart text is drawn with; charts are left out when it can't be loaded
+    pub font_path: String,
+}
+
+impl Default for ChartConfig {
+    fn default() -> Self {
+        ChartConfig {
+            enabled: true,
+            font_path: "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DashboardConfig {
+    // Serve the web dashboard and its JSON API
+    pub enabled: bool,
+    pub bind_address: String,
+    // Accepted as a bearer token or `token` query parameter
+    pub token: Option<String>,
+    // HTTP basic auth credentials, accepted alongside or instead of the token
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Default for DashboardConfig {
+    fn default() -> Self {
+        DashboardConfig {
+            enabled: false,
+            bind_address: "0.0.0.0:8080".to_string(),
+            token: None,
+            username: None,
+            password: None,
+        }
+    }
+}
+
+// Messages matching any keyword (a whole word or phrase, any case) or regex pattern send an
+// alert to the rule's channel and/or DMs
+#[derive(Debug, Deserialize)]
+pub struct WatchlistRule {
+    pub name: String,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    // Limits the rule to one guild and to these channels; unset matches everywhere
+    pub guild_id: Option<u64>,
+    #[serde(default)]
+    pub channels: Vec<u64>,
+    // Defaults to the notify channel when neither a channel nor DM users are set
+    pub alert_channel_id: Option<u64>,
+    #[serde(default)]
+    pub dm_users: Vec<u64>,
+    #[serde(default = "default_watchlist_cooldown_minutes")]
+    pub cooldown_minutes: i64,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        RedactionConfig {
+            enabled: true,
+            custom_patterns: Vec::new(),
+        }
+    }
+}
+
+fn default_identity_database() -> String {
+    "discord-identities".to_string()
+}
+
+fn default_session_gap_minutes() -> i64 {
+    30
+}
+
+pub fn default_notify_channel_id() -> u64 {
+    1054296641651347486
+}
+
+fn default_watchlist_cooldown_minutes() -> i64 {
+    5
+}
+
+fn default_report_schedule() -> String {
+    "0 0 10 * * MON".to_string()
+}
+
+fn default_true() -> bool {
+    true
 }
 
 impl Config {
@@ -31,9 +568,56 @@ impl Config {
 pub fn load_config(file_path: &str) -> Config {
     let contents = fs::read_to_string(file_path).expect("Something went wrong reading the file");
     let config: Config = serde_yaml::from_str(&contents).expect("Error while parsing YAML config");
+    for (environment, env_config) in [
+        ("development", &config.development),
+        ("production", &config.production),
+    ] {
+        if let Err(e) = env_config.validate() {
+            panic!("Invalid {} config: {}", environment, e);
+        }
+    }
     config
 }
 
+impl EnvConfig {
+    // Checks the patterns and terms rules supply, so that a bad one is reported by rule name
+    // when the config is loaded rather than when a component compiles it
+    pub fn validate(&self) -> Result<(), String> {
+        for custom in &self.redaction.custom_patterns {
+            Regex::new(&custom.pattern)
+                .map_err(|e| format!("redaction pattern {}: {}", custom.name, e))?;
+        }
+        for rule in &self.topics {
+            for pattern in &rule.patterns {
+                Regex::new(pattern).map_err(|e| format!("topic {}: {}", rule.name, e))?;
+            }
+        }
+        for rule in &self.watchlists {
+            for pattern in &rule.patterns {
+                Regex::new(pattern).map_err(|e| format!("watchlist {}: {}", rule.name, e))?;
+            }
+        }
+        for emotion in self.emotions.extra_terms.keys() {
+            if !EMOTIONS.contains(&emotion.as_str()) {
+                return Err(format!("emotions.extra_terms: unknown emotion {}", emotion));
+            }
+        }
+        if self.dashboard.enabled {
+            let dashboard = &self.dashboard;
+            if dashboard.token.is_none()
+                && (dashboard.username.is_none() || dashboard.password.is_none())
+            {
+                return Err("dashboard: a token or a username and password is required".to_string());
+            }
+            dashboard
+                .bind_address
+                .parse::<SocketAddr>()
+                .map_err(|e| format!("dashboard.bind_address {}: {}", dashboard.bind_address, e))?;
+        }
+        Ok(())
+    }
+}
+
 pub fn set_env_variables(config: &EnvConfig) {
     env::set_var("DISCORD_TOKEN", &config.discord_token);
     env::set_var("MONGO_URI", &config.mongo_uri);
@@ -48,3 +632,73 @@ pub fn set_env_variables(config: &EnvConfig) {
         env::set_var("AWS_REGION", aws_region);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env_config(extra: &str) -> EnvConfig {
+        let yaml = format!(
+            "discord_token: token\nmongo_uri: mongodb://localhost\ndiscord_guild: \"1\"\n{}",
+            extra
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn sample_config_is_valid() {
+        let config: Config = serde_yaml::from_str(include_str!("../config.sample.yaml")).unwrap();
+        assert_eq!(config.development.validate(), Ok(()));
+        assert_eq!(config.production.validate(), Ok(()));
+    }
+
+    #[test]
+    fn names_the_rule_with_an_invalid_pattern() {
+        let config = env_config("watchlists:\n  - name: launch\n    patterns: [\"(unclosed\"]\n");
+        let error = config.validate().unwrap_err();
+        assert!(error.starts_with("watchlist launch:"), "{}", error);
+
+        let config = env_config(
+            "redaction:\n  custom_patterns:\n    - name: order_id\n      pattern: \"[a-\"\n",
+        );
+        let error = config.validate().unwrap_err();
+        assert!(
+            error.starts_with("redaction pattern order_id:"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_emotions() {
+        let config = env_config("emotions:\n  extra_terms:\n    boredom: [meh]\n");
+        assert_eq!(
+            config.validate(),
+            Err("emotions.extra_terms: unknown emotion boredom".to_string())
+        );
+    }
+
+    #[test]
+    fn checks_the_dashboard_only_when_enabled() {
+        let config = env_config("dashboard:\n  bind_address: nowhere\n");
+        assert_eq!(config.validate(), Ok(()));
+
+        let config = env_config("dashboard:\n  enabled: true\n  username: admin\n");
+        assert_eq!(
+            config.validate(),
+            Err("dashboard: a token or a username and password is required".to_string())
+        );
+
+        let config =
+            env_config("dashboard:\n  enabled: true\n  token: secret\n  bind_address: nowhere\n");
+        let error = config.validate().unwrap_err();
+        assert!(
+            error.starts_with("dashboard.bind_address nowhere:"),
+            "{}",
+            error
+        );
+
+        let config = env_config("dashboard:\n  enabled: true\n  token: secret\n");
+        assert_eq!(config.validate(), Ok(()));
+    }
+}
@@ -0,0 +1,337 @@
+use crate::config::EncryptionConfig;
+use crate::media::EmbedInfo;
+use crate::mongo::Message;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::stream::TryStreamExt;
+use mongodb::bson::{self, doc};
+use mongodb::Database;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+const NONCE_LEN: usize = 12;
+
+// AES-256-GCM keys by ID. New documents are encrypted with the active key; documents
+// record the ID of the key they were encrypted with so that keys can be rotated.
+pub struct Keyring {
+    active_key: String,
+    ciphers: HashMap<String, Aes256Gcm>,
+}
+
+impl Keyring {
+    pub fn new(config: &EncryptionConfig) -> Keyring {
+        let mut ciphers = HashMap::new();
+        for key in &config.keys {
+            let encoded = match (&key.key, &key.key_file) {
+                (Some(key), _) => key.clone(),
+                (None, Some(path)) => fs::read_to_string(path)
+                    .expect("Something went wrong reading the encryption key file"),
+                (None, None) => panic!("Encryption key {} has no key or key_file", key.id),
+            };
+            let bytes = STANDARD
+                .decode(encoded.trim())
+                .expect("Encryption keys must be base64 encoded");
+            assert_eq!(bytes.len(), 32, "Encryption keys must be 256 bits");
+
+            let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&bytes));
+            ciphers.insert(key.id.clone(), cipher);
+        }
+
+        assert!(
+            ciphers.contains_key(&config.active_key),
+            "The active encryption key is not listed in keys"
+        );
+
+        Keyring {
+            active_key: config.active_key.clone(),
+            ciphers,
+        }
+    }
+
+    pub fn active_key(&self) -> &str {
+        &self.active_key
+    }
+
+    // Encrypts the message text, its translation and everything derived from the text
+    // in place under the active key. Each value's field name is bound as associated data
+    // so that ciphertexts can't be swapped between fields. Taxonomy topics, sentiment and
+    // other labels stay in plaintext for the aggregates.
+    pub fn encrypt_message(&self, message: &mut Message) -> Result<(), Box<dyn Error>> {
+        let cipher = &self.ciphers[&self.active_key];
+
+        seal_message(message, |field, value| encrypt_field(cipher, field, value))?;
+        message.key_id = Some(self.active_key.clone());
+
+        Ok(())
+    }
+
+    // Decrypts the fields `encrypt_message` encrypts in place. Plaintext documents are
+    // left untouched.
+    pub fn decrypt_message(&self, message: &mut Message) -> Result<(), Box<dyn Error>> {
+        let Some(key_id) = message.key_id.take() else {
+            return Ok(());
+        };
+        let cipher = self.cipher(&key_id)?;
+
+        seal_message(message, |field, value| decrypt_field(cipher, field, value))
+    }
+
+    // Encrypts embeds recorded after the message was stored, e.g. link previews
+    pub fn encrypt_embeds(&self, embeds: &mut [EmbedInfo]) -> Result<(), Box<dyn Error>> {
+        let cipher = &self.ciphers[&self.active_key];
+        seal_embeds(embeds, |field, value| encrypt_field(cipher, field, value))
+    }
+
+    // Encrypts a single value under the active key, for documents other than messages
+    pub fn encrypt(&self, field: &str, plaintext: &str) -> Result<String, Box<dyn Error>> {
+        encrypt_field(&self.ciphers[&self.active_key], field, plaintext)
+    }
+
+    pub fn decrypt(
+        &self,
+        key_id: &str,
+        field: &str,
+        sealed: &str,
+    ) -> Result<String, Box<dyn Error>> {
+        decrypt_field(self.cipher(key_id)?, field, sealed)
+    }
+
+    fn cipher(&self, key_id: &str) -> Result<&Aes256Gcm, Box<dyn Error>> {
+        self.ciphers
+            .get(key_id)
+            .ok_or_else(|| format!("Unknown encryption key: {}", key_id).into())
+    }
+}
+
+// Applies `seal` to every free-text field of a message: the text and translation, the
+// key phrases, entities and aspects extracted from it, and what its attachments and
+// embeds display
+fn seal_message(
+    message: &mut Message,
+    mut seal: impl FnMut(&str, &str) -> Result<String, Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    message.text = seal("text", &message.text)?;
+    if let Some(korean) = &message.korean {
+        message.korean = Some(seal("korean", korean)?);
+    }
+    for phrase in &mut message.key_phrases {
+        *phrase = seal("keyPhrases", phrase)?;
+    }
+    for entity in &mut message.entities {
+        entity.text = seal("entities.text", &entity.text)?;
+    }
+    for aspect in &mut message.aspects {
+        aspect.aspect = seal("aspects.aspect", &aspect.aspect)?;
+    }
+    for attachment in &mut message.attachments {
+        attachment.filename = seal("attachments.filename", &attachment.filename)?;
+    }
+    seal_embeds(&mut message.embeds, seal)
+}
+
+fn seal_embeds(
+    embeds: &mut [EmbedInfo],
+    mut seal: impl FnMut(&str, &str) -> Result<String, Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    for embed in embeds {
+        if let Some(title) = &embed.title {
+            embed.title = Some(seal("embeds.title", title)?);
+        }
+        if let Some(description) = &embed.description {
+            embed.description = Some(seal("embeds.description", description)?);
+        }
+    }
+    Ok(())
+}
+
+// Re-encrypts every message that isn't encrypted under the active key, including
+// plaintext documents saved before encryption was enabled. Returns the number rewritten.
+pub async fn reencrypt_messages(db: &Database, keyring: &Keyring) -> Result<u64, Box<dyn Error>> {
+    let collection = db.collection::<Message>("messages");
+    let mut cursor = collection
+        .find(doc! { "keyId": { "$ne": keyring.active_key() } }, None)
+        .await?;
+
+    let mut reencrypted = 0;
+    while let Some(mut message) = cursor.try_next().await? {
+        let Some(id) = message.id else {
+            continue;
+        };
+        if message.key_id.as_deref() == Some(keyring.active_key()) {
+            continue;
+        }
+
+        keyring.decrypt_message(&mut message)?;
+        keyring.encrypt_message(&mut message)?;
+
+        let mut update = doc! {
+            "text": &message.text,
+            "keyPhrases": &message.key_phrases,
+            "entities": bson::to_bson(&message.entities)?,
+            "aspects": bson::to_bson(&message.aspects)?,
+            "attachments": bson::to_bson(&message.attachments)?,
+            "embeds": bson::to_bson(&message.embeds)?,
+            "keyId": keyring.active_key(),
+        };
+        if let Some(korean) = &message.korean {
+            update.insert("korean", korean);
+        }
+        collection
+            .update_one(doc! { "_id": id }, doc! { "$set": update }, None)
+            .await?;
+        reencrypted += 1;
+    }
+
+    Ok(reencrypted)
+}
+
+fn encrypt_field(
+    cipher: &Aes256Gcm,
+    field: &str,
+    plaintext: &str,
+) -> Result<String, Box<dyn Error>> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let payload = Payload {
+        msg: plaintext.as_bytes(),
+        aad: field.as_bytes(),
+    };
+    let ciphertext = cipher
+        .encrypt(&nonce, payload)
+        .map_err(|_| format!("Failed to encrypt {}", field))?;
+
+    // Stored as base64(nonce || ciphertext)
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(STANDARD.encode(sealed))
+}
+
+fn decrypt_field(cipher: &Aes256Gcm, field: &str, sealed: &str) -> Result<String, Box<dyn Error>> {
+    let sealed = STANDARD.decode(sealed)?;
+    if sealed.len() < NONCE_LEN {
+        return Err(format!("Encrypted {} is too short", field).into());
+    }
+
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let payload = Payload {
+        msg: ciphertext,
+        aad: field.as_bytes(),
+    };
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), payload)
+        .map_err(|_| format!("Failed to decrypt {}", field))?;
+
+    Ok(String::from_utf8(plaintext)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EncryptionKey;
+    use crate::phrases::EntityInfo;
+
+    fn keyring(active_key: &str, ids: &[&str]) -> Keyring {
+        let keys = ids
+            .iter()
+            .map(|id| EncryptionKey {
+                id: id.to_string(),
+                // A distinct key per ID
+                key: Some(STANDARD.encode([id.as_bytes()[0]; 32])),
+                key_file: None,
+            })
+            .collect();
+        Keyring::new(&EncryptionConfig {
+            active_key: active_key.to_string(),
+            keys,
+        })
+    }
+
+    fn message() -> Message {
+        Message {
+            text: "the new patch broke my wallet".to_string(),
+            korean: Some("새 패치가 지갑을 망가뜨렸어요".to_string()),
+            key_phrases: vec!["new patch".to_string()],
+            entities: vec![EntityInfo {
+                text: "wallet".to_string(),
+                kind: "COMMERCIAL_ITEM".to_string(),
+            }],
+            embeds: vec![EmbedInfo {
+                title: Some("Patch notes".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn round_trips_every_free_text_field() {
+        let keyring = keyring("a", &["a"]);
+        let mut encrypted = message();
+        keyring.encrypt_message(&mut encrypted).unwrap();
+
+        assert_eq!(encrypted.key_id.as_deref(), Some("a"));
+        assert_ne!(encrypted.text, message().text);
+        assert_ne!(encrypted.korean, message().korean);
+        assert_ne!(encrypted.key_phrases, message().key_phrases);
+        assert_ne!(encrypted.entities[0].text, "wallet");
+        assert_eq!(encrypted.entities[0].kind, "COMMERCIAL_ITEM");
+        assert_ne!(encrypted.embeds[0].title.as_deref(), Some("Patch notes"));
+
+        keyring.decrypt_message(&mut encrypted).unwrap();
+        assert_eq!(encrypted.key_id, None);
+        assert_eq!(encrypted.text, message().text);
+        assert_eq!(encrypted.korean, message().korean);
+        assert_eq!(encrypted.key_phrases, message().key_phrases);
+        assert_eq!(encrypted.entities[0].text, "wallet");
+        assert_eq!(encrypted.embeds[0].title.as_deref(), Some("Patch notes"));
+    }
+
+    #[test]
+    fn ciphertexts_are_bound_to_their_field() {
+        let keyring = keyring("a", &["a"]);
+        let mut encrypted = message();
+        keyring.encrypt_message(&mut encrypted).unwrap();
+
+        encrypted.korean = Some(encrypted.text.clone());
+        assert!(keyring.decrypt_message(&mut encrypted).is_err());
+    }
+
+    #[test]
+    fn reads_documents_encrypted_under_a_rotated_key() {
+        let mut encrypted = message();
+        keyring("a", &["a"])
+            .encrypt_message(&mut encrypted)
+            .unwrap();
+
+        let rotated = keyring("b", &["a", "b"]);
+        rotated.decrypt_message(&mut encrypted).unwrap();
+        assert_eq!(encrypted.text, message().text);
+
+        rotated.encrypt_message(&mut encrypted).unwrap();
+        assert_eq!(encrypted.key_id.as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn rejects_unknown_key_ids() {
+        let mut encrypted = message();
+        keyring("a", &["a"])
+            .encrypt_message(&mut encrypted)
+            .unwrap();
+
+        let error = keyring("b", &["b"])
+            .decrypt_message(&mut encrypted)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Unknown encryption key: a");
+    }
+
+    #[test]
+    fn leaves_plaintext_documents_untouched() {
+        let mut plaintext = message();
+        keyring("a", &["a"])
+            .decrypt_message(&mut plaintext)
+            .unwrap();
+        assert_eq!(plaintext.text, message().text);
+    }
+}
@@ -0,0 +1,429 @@
+use crate::config::DashboardConfig;
+use crate::crypto::Keyring;
+use crate::export::start_of_day;
+use crate::mongo::{get_number, read_messages_with, Message};
+use crate::report::SENTIMENT_LABELS;
+use crate::topics::topic_sentiment_matching;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{Duration, NaiveDate};
+use futures::stream::TryStreamExt;
+use hyper::header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE, WWW_AUTHENTICATE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use mongodb::bson::{doc, Document};
+use mongodb::options::FindOptions;
+use mongodb::Database;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+// Single-page dashboard; it only talks to the JSON API below
+const DASHBOARD_PAGE: &str = include_str!("dashboard.html");
+
+const DEFAULT_PAGE_SIZE: u64 = 50;
+const MAX_PAGE_SIZE: u64 = 200;
+
+// One channel's messages on one day, per sentiment label and in "total"
+type DayCounts = BTreeMap<&'static str, i64>;
+
+struct Dashboard {
+    db: Database,
+    keyring: Option<Keyring>,
+    config: DashboardConfig,
+}
+
+// Filters shared by the API endpoints. Dates are inclusive days in the UTC+9 timestamps
+// messages are stored with.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct MessageQuery {
+    from: Option<String>,
+    to: Option<String>,
+    channel: Option<String>,
+    sentiment: Option<String>,
+    topic: Option<String>,
+    page: u64,
+    #[serde(rename = "pageSize")]
+    page_size: Option<u64>,
+}
+
+impl MessageQuery {
+    // Community messages matching the filters; spam and staff messages are never shown
+    fn filter(&self) -> Result<Document, String> {
+        let mut filter = doc! {
+            "role": { "$ne": "staff" },
+            "spam": { "$exists": false },
+        };
+
+        let mut created_at = doc! {};
+        if let Some(from) = parse_date(&self.from)? {
+            created_at.insert("$gte", start_of_day(from));
+        }
+        if let Some(to) = parse_date(&self.to)? {
+            created_at.insert("$lt", start_of_day(to + Duration::days(1)));
+        }
+        if !created_at.is_empty() {
+            filter.insert("createdAt", created_at);
+        }
+        if let Some(channel) = non_empty(&self.channel) {
+            filter.insert("channel", channel);
+        }
+        if let Some(sentiment) = non_empty(&self.sentiment) {
+            if !SENTIMENT_LABELS.contains(&sentiment) {
+                return Err(format!("Unknown sentiment: {}", sentiment));
+            }
+            filter.insert("sentiment", sentiment);
+        }
+        if let Some(topic) = non_empty(&self.topic) {
+            filter.insert("topics", topic);
+        }
+
+        Ok(filter)
+    }
+}
+
+// Browsers submit empty form fields as empty strings
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().filter(|value| !value.is_empty())
+}
+
+fn parse_date(value: &Option<String>) -> Result<Option<NaiveDate>, String> {
+    non_empty(value)
+        .map(|date| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| format!("Invalid date (expected YYYY-MM-DD): {}", date))
+        })
+        .transpose()
+}
+
+// Serves the dashboard and its API on `config.bind_address` until the process exits. The
+// credentials and bind address are checked by `EnvConfig::validate` at startup.
+pub async fn start_dashboard(db: Database, keyring: Option<Keyring>, config: DashboardConfig) {
+    let address: SocketAddr = config
+        .bind_address
+        .parse()
+        .expect("Failed to parse the dashboard bind address");
+
+    let dashboard = Arc::new(Dashboard {
+        db,
+        keyring,
+        config,
+    });
+    let make_service = make_service_fn(move |_| {
+        let dashboard = dashboard.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let dashboard = dashboard.clone();
+                async move { Ok::<_, Infallible>(dashboard.handle(request).await) }
+            }))
+        }
+    });
+
+    println!("[Dashboard] Listening on http://{}", address);
+    if let Err(e) = Server::bind(&address).serve(make_service).await {
+        println!("Dashboard server error: {:?}", e);
+    }
+}
+
+impl Dashboard {
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        // Requests are authenticated before their filters are looked at
+        if !self.is_authorized(&request) {
+            return self.unauthorized();
+        }
+        let query: MessageQuery =
+            match serde_urlencoded::from_str(request.uri().query().unwrap_or("")) {
+                Ok(query) => query,
+                Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+            };
+        if request.method() != Method::GET {
+            return error_response(StatusCode::METHOD_NOT_ALLOWED, "Only GET is supported");
+        }
+
+        let result = match request.uri().path() {
+            "/" => {
+                return Response::builder()
+                    .header(CONTENT_TYPE, "text/html; charset=utf-8")
+                    .body(Body::from(DASHBOARD_PAGE))
+                    .unwrap()
+            }
+            "/api/channels" => self.channels().await,
+            "/api/messages" => self.messages(&query).await,
+            "/api/timeline" => self.timeline(&query).await,
+            "/api/topics" => self.topics(&query).await,
+            _ => return error_response(StatusCode::NOT_FOUND, "Not found"),
+        };
+
+        match result {
+            Ok(body) => json_response(StatusCode::OK, &body),
+            Err(ApiError::BadRequest(message)) => error_response(StatusCode::BAD_REQUEST, &message),
+            Err(ApiError::Database(e)) => {
+                println!("Error serving {}: {:?}", request.uri().path(), e);
+                error_response(StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+            }
+        }
+    }
+
+    // Accepts the token as a bearer token or `token` query parameter, or basic auth
+    fn is_authorized(&self, request: &Request<Body>) -> bool {
+        if let (Some(token), Some(given)) = (&self.config.token, query_token(request)) {
+            if constant_time_eq(token, &given) {
+                return true;
+            }
+        }
+
+        let Some(header) = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return false;
+        };
+        if let (Some(token), Some(given)) = (&self.config.token, header.strip_prefix("Bearer ")) {
+            return constant_time_eq(token, given.trim());
+        }
+        if let (Some(username), Some(password), Some(encoded)) = (
+            &self.config.username,
+            &self.config.password,
+            header.strip_prefix("Basic "),
+        ) {
+            let Some(credentials) = STANDARD
+                .decode(encoded.trim())
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+            else {
+                return false;
+            };
+            let Some((given_username, given_password)) = credentials.split_once(':') else {
+                return false;
+            };
+            // Both are compared so that the time taken doesn't reveal which one was wrong
+            let username_matches = constant_time_eq(username, given_username);
+            let password_matches = constant_time_eq(password, given_password);
+            return username_matches && password_matches;
+        }
+
+        false
+    }
+
+    fn unauthorized(&self) -> Response<Body> {
+        let mut response = error_response(StatusCode::UNAUTHORIZED, "Unauthorized");
+        // Lets browsers prompt for the credentials
+        if self.config.username.is_some() {
+            response.headers_mut().insert(
+                WWW_AUTHENTICATE,
+                "Basic realm=\"Emotion Tracker\", charset=\"UTF-8\""
+                    .parse()
+                    .unwrap(),
+            );
+        }
+        response
+    }
+
+    async fn channels(&self) -> Result<Value, ApiError> {
+        let collection = self.db.collection::<Document>("messages");
+        let mut channels: Vec<String> = collection
+            .distinct("channel", doc! { "role": { "$ne": "staff" } }, None)
+            .await?
+            .into_iter()
+            .filter_map(|channel| channel.as_str().map(|channel| channel.to_string()))
+            .collect();
+        channels.sort();
+
+        Ok(json!({ "channels": channels }))
+    }
+
+    // One page of matching messages, newest first, with the Korean translation alongside
+    async fn messages(&self, query: &MessageQuery) -> Result<Value, ApiError> {
+        let filter = query.filter()?;
+        let page_size = query
+            .page_size
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+
+        let collection = self.db.collection::<Document>("messages");
+        let total = collection.count_documents(filter.clone(), None).await?;
+        let skip = query
+            .page
+            .checked_mul(page_size)
+            .filter(|skip| *skip <= i64::MAX as u64)
+            .ok_or_else(|| "page is out of range".to_string())?;
+        let options = FindOptions::builder()
+            .sort(doc! { "createdAt": -1 })
+            .skip(skip)
+            .limit(page_size as i64)
+            .build();
+        let mut reader =
+            read_messages_with(&self.db, filter, options, self.keyring.as_ref()).await?;
+
+        let mut messages = Vec::new();
+        while let Some(message) = reader.next().await? {
+            messages.push(message_json(&message));
+        }
+
+        Ok(json!({
+            "page": query.page,
+            "pageSize": page_size,
+            "total": total,
+            "messages": messages,
+        }))
+    }
+
+    // Daily message counts per sentiment label for each channel, busiest channel first
+    async fn timeline(&self, query: &MessageQuery) -> Result<Value, ApiError> {
+        let collection = self.db.collection::<Document>("messages");
+        let pipeline = vec![
+            doc! { "$match": query.filter()? },
+            doc! {
+                "$group": {
+                    "_id": {
+                        "channel": "$channel",
+                        "date": { "$dateToString": { "format": "%Y-%m-%d", "date": "$createdAt" } },
+                        "sentiment": "$sentiment",
+                    },
+                    "count": { "$sum": 1 },
+                }
+            },
+        ];
+
+        // Unanalyzed messages count toward the total only
+        let mut channels: BTreeMap<String, BTreeMap<String, DayCounts>> = BTreeMap::new();
+        let mut cursor = collection.aggregate(pipeline, None).await?;
+        while let Some(document) = cursor.try_next().await? {
+            let Ok(group) = document.get_document("_id") else {
+                continue;
+            };
+            let (Ok(channel), Ok(date)) = (group.get_str("channel"), group.get_str("date")) else {
+                continue;
+            };
+            let label = group
+                .get_str("sentiment")
+                .ok()
+                .and_then(|label| SENTIMENT_LABELS.iter().find(|known| **known == label))
+                .copied();
+            let count = get_number(&document, "count").unwrap_or_default() as i64;
+
+            let day = channels
+                .entry(channel.to_string())
+                .or_default()
+                .entry(date.to_string())
+                .or_default();
+            *day.entry("total").or_default() += count;
+            if let Some(label) = label {
+                *day.entry(label).or_default() += count;
+            }
+        }
+
+        let mut channels: Vec<(String, BTreeMap<String, DayCounts>)> =
+            channels.into_iter().collect();
+        channels.sort_by_key(|(_, days)| {
+            std::cmp::Reverse(days.values().map(|day| day["total"]).sum::<i64>())
+        });
+        let channels: Vec<Value> = channels
+            .into_iter()
+            .map(|(channel, days)| {
+                let days: Vec<Value> = days
+                    .into_iter()
+                    .map(|(date, counts)| {
+                        let mut day = json!({ "date": date, "total": counts["total"] });
+                        for label in SENTIMENT_LABELS {
+                            day[*label] = json!(counts.get(label).copied().unwrap_or_default());
+                        }
+                        day
+                    })
+                    .collect();
+                json!({ "channel": channel, "days": days })
+            })
+            .collect();
+
+        Ok(json!({ "channels": channels }))
+    }
+
+    async fn topics(&self, query: &MessageQuery) -> Result<Value, ApiError> {
+        let topics = topic_sentiment_matching(&self.db, query.filter()?).await?;
+        let topics: Vec<Value> = topics
+            .iter()
+            .map(|topic| {
+                json!({
+                    "topic": topic.topic,
+                    "messages": topic.messages,
+                    "positive": topic.positive,
+                    "negative": topic.negative,
+                    "score": topic.score,
+                })
+            })
+            .collect();
+
+        Ok(json!({ "topics": topics }))
+    }
+}
+
+enum ApiError {
+    BadRequest(String),
+    Database(mongodb::error::Error),
+}
+
+impl From<String> for ApiError {
+    fn from(message: String) -> Self {
+        ApiError::BadRequest(message)
+    }
+}
+
+impl From<mongodb::error::Error> for ApiError {
+    fn from(e: mongodb::error::Error) -> Self {
+        ApiError::Database(e)
+    }
+}
+
+// Authors aren't exposed; the dashboard is for browsing what the community says
+fn message_json(message: &Message) -> Value {
+    json!({
+        "id": message.id.map(|id| id.to_hex()),
+        "createdAt": message.created_at.format("%Y-%m-%d %H:%M").to_string(),
+        "channel": message.channel,
+        "thread": message.thread.as_ref().map(|thread| thread.name.clone()),
+        "text": message.text,
+        "korean": message.korean,
+        "sentiment": message.analyzed,
+        "emotion": message.emotions.map(|emotions| emotions.dominant()),
+        "topics": message.topics,
+        "keyPhrases": message.key_phrases,
+    })
+}
+
+fn json_response(status: StatusCode, body: &Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .header(CACHE_CONTROL, "no-store")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, &json!({ "error": message }))
+}
+
+// The `token` query parameter, read on its own so that authentication doesn't depend on
+// the rest of the query being valid
+fn query_token(request: &Request<Body>) -> Option<String> {
+    let pairs: Vec<(String, String)> = serde_urlencoded::from_str(request.uri().query()?).ok()?;
+    pairs
+        .into_iter()
+        .find(|(name, _)| name == "token")
+        .map(|(_, value)| value)
+}
+
+// Compares secrets without returning at the first differing byte
+fn constant_time_eq(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
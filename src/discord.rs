@@ -1,109 +1,759 @@
+use crate::announcements::{record_announcement, ImpactReport, WindowStats};
+use crate::anomaly::{AnomalyKind, VolumeAnomaly};
+use crate::aspects::{load_aspect_analyzer, AspectAnalyzer};
+use crate::charts::register_chart_font;
+use crate::churn::AtRiskMember;
+use crate::clustering::{emerging_clusters, TopicCluster};
+use crate::commands::{handle_command, register_commands};
+use crate::config::{default_notify_channel_id, EnvConfig};
+use crate::crypto::Keyring;
+use crate::emoji::Polarity;
+use crate::emotion::{load_emotion_classifier, EmotionClassifier};
+use crate::health::{ChannelHealth, ChannelHealthSummary};
+use crate::links::LinkProcessor;
+use crate::media::{embed_text, is_issue_report, record_embeds, AttachmentInfo, EmbedInfo};
 use crate::mongo::{save_message, Message};
 use crate::monitor::{monitor_memory_stats, send_signal_alert, MemoryStats};
+use crate::phrases::{load_phrase_extractor, ExtractedPhrases, PhraseExtractor};
+use crate::privacy::{is_opted_out, load_opt_outs, OptOuts};
+use crate::pseudonym::{load_pseudonymizer, Pseudonymizer};
+use crate::reactions::{classify_reaction, record_reaction, ReactionSentiment};
+use crate::report::WeeklyReport;
+use crate::scheduler::{
+    start_announcement_scheduler, start_anomaly_scheduler, start_churn_scheduler,
+    start_clustering_scheduler, start_health_scheduler, start_report_scheduler,
+};
 use crate::sentiment::analyze_sentiment;
+use crate::sessions::{record_session_message, SessionMessage};
+use crate::spam::{mark_spam, SpamDetector, SpamReason, SpamVerdict};
+use crate::support::ChannelSupportStats;
+use crate::threads::{record_thread_message, ThreadInfo};
+use crate::topics::{TopicSentiment, TopicTagger};
+use crate::toxicity::{
+    load_toxicity_classifier, ToxicityCategory, ToxicityClassifier, ToxicityScores,
+};
 use crate::translate::translate_to_ko;
 use crate::util::{
-    filter_guild, has_minimum_word_count, remove_urls, replace_mentions, should_ignore_channel,
-    should_ignore_user, should_not_ignore_guild,
+    count_words, filter_guild, has_minimum_word_count, is_question, is_staff, local_now,
+    normalize_text, replace_mentions, should_ignore_channel, should_not_ignore_guild, Redactor,
 };
-use chrono::{Duration, Utc};
+use crate::watchlist::{record_hit, Watchlist, WatchlistHit};
+use chrono::{Duration, TimeZone, Utc};
 use mongodb::Database;
+use std::collections::HashMap;
+use std::env;
 
 use serenity::builder::CreateEmbed;
+use serenity::http::Http;
 use serenity::utils::Color;
 use serenity::{
     async_trait,
     model::{
+        application::interaction::Interaction,
         channel::Channel,
+        channel::ChannelType,
+        channel::GuildChannel,
         channel::Message as DiscordMessage,
+        channel::Reaction,
+        event::MessageUpdateEvent,
         gateway::Ready,
-        id::{ChannelId, UserId},
+        id::{ChannelId, GuildId, UserId},
     },
     prelude::*,
 };
 
-struct Handler {
-    db: Database,
+pub struct Handler {
+    pub db: Database,
+    pub opt_outs: OptOuts,
+    pub redactor: Redactor,
+    pub pseudonymizer: Option<Pseudonymizer>,
+    pub keyring: Option<Keyring>,
+    pub session_gap: Duration,
+    pub custom_emoji: HashMap<String, Polarity>,
+    pub analyze_embed_text: bool,
+    pub links: LinkProcessor,
+    pub link_alert_channel: ChannelId,
+    pub spam: SpamDetector,
+    pub spam_alert_channel: ChannelId,
+    pub toxicity: Option<Box<dyn ToxicityClassifier>>,
+    pub toxicity_thresholds: HashMap<ToxicityCategory, f64>,
+    pub toxicity_alert_channel: ChannelId,
+    pub emotions: Option<Box<dyn EmotionClassifier>>,
+    pub phrases: Option<Box<dyn PhraseExtractor>>,
+    pub topics: TopicTagger,
+    pub aspects: Option<Box<dyn AspectAnalyzer>>,
+    pub watchlist: Watchlist,
+    pub announcement_channels: Vec<u64>,
+    // Whether the chart font loaded, so that `/sentiment` can attach charts
+    pub charts: bool,
+}
+
+// What moderation found out about a message before it is analyzed and stored
+struct Screening {
+    is_staff: bool,
+    spam: Option<SpamVerdict>,
+    toxicity: Option<ToxicityScores>,
+    channel_name: String,
+    thread: Option<ThreadInfo>,
+}
+
+// The redacted content and analysis of a message, before encryption
+struct MessageAnalysis {
+    content: String,
+    sentiment: Option<String>,
+    korean: Option<String>,
 }
 
 #[async_trait]
 impl EventHandler for Handler {
-    async fn ready(&self, _: Context, ready: Ready) {
+    async fn ready(&self, ctx: Context, ready: Ready) {
         println!("{} is connected", ready.user.name);
+
+        // Register the slash commands for the configured guild
+        let guild_id = env::var("DISCORD_GUILD")
+            .ok()
+            .and_then(|id| id.parse::<u64>().ok());
+        if let Some(guild_id) = guild_id {
+            if let Err(e) = register_commands(&ctx, GuildId(guild_id)).await {
+                println!("Error registering slash commands: {:?}", e);
+            }
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if let Interaction::ApplicationCommand(command) = interaction {
+            handle_command(&ctx, &command, self).await;
+        }
+    }
+
+    async fn message_update(&self, _: Context, event: MessageUpdateEvent) {
+        let guild = env::var("DISCORD_GUILD").unwrap_or_default();
+        if event.guild_id.map(|id| id.to_string()) != Some(guild) {
+            return;
+        }
+        let Some(embeds) = event.embeds.filter(|embeds| !embeds.is_empty()) else {
+            return;
+        };
+
+        let mut embeds: Vec<EmbedInfo> = embeds
+            .iter()
+            .map(|embed| EmbedInfo::new(embed, &self.redactor))
+            .collect();
+        if let Some(keyring) = &self.keyring {
+            if let Err(e) = keyring.encrypt_embeds(&mut embeds) {
+                println!("Error encrypting embeds: {:?}", e);
+                return;
+            }
+        }
+        let key_id = self.keyring.as_ref().map(|keyring| keyring.active_key());
+        if let Err(e) = record_embeds(&self.db, &event.id.to_string(), &embeds, key_id).await {
+            println!("Error recording embeds: {:?}", e);
+        }
+    }
+
+    async fn reaction_add(&self, _: Context, reaction: Reaction) {
+        self.track_reaction(&reaction, 1).await;
+    }
+
+    async fn reaction_remove(&self, _: Context, reaction: Reaction) {
+        self.track_reaction(&reaction, -1).await;
     }
 
     async fn message(&self, ctx: Context, msg: DiscordMessage) {
+        // Announcements aren't analyzed, but are recorded as events for impact reports. They
+        // may be posted by bots or crossposted, so this comes before the bot check.
+        if self.announcement_channels.contains(&msg.channel_id.0) && filter_guild(&msg) {
+            if let Err(e) = record_announcement(
+                &self.db,
+                &msg.id.to_string(),
+                &msg.channel_id.to_string(),
+                &msg.content,
+                &msg.link(),
+                local_now(),
+            )
+            .await
+            {
+                println!("Error recording announcement: {:?}", e);
+            }
+            return;
+        }
+
         // Skip processing messages from bots
         if msg.author.bot
-            || should_ignore_user(&msg)
-            || should_ignore_channel(&msg)
-            || !has_minimum_word_count(&msg, 5)
+            || should_ignore_channel(msg.channel_id)
             || should_not_ignore_guild(&msg)
             || !filter_guild(&msg)
         {
             return;
         }
 
-        // Replace mentions in the message content
-        let mut content = replace_mentions(&ctx, &msg).await;
+        // Get the name of the channel the message was sent in, and the thread if any.
+        // Threads and forum posts in ignored channels are ignored too.
+        let (channel_name, thread) = get_channel_info(&ctx, msg.channel_id)
+            .await
+            .unwrap_or_default();
+        let parent_id = thread
+            .as_ref()
+            .and_then(|thread| thread.parent_id.parse::<u64>().ok());
+        if parent_id.is_some_and(|parent_id| should_ignore_channel(ChannelId(parent_id))) {
+            return;
+        }
 
-        // Remove URLs from the message content and return early if the content is None
-        match remove_urls(&content) {
-            Some(urls) => content = urls,
-            None => {
-                return;
+        // Moderation alerts apply to everyone, including users who opted out and short
+        // messages that aren't stored
+        let links = self.links.process(&msg.content);
+        let blocked = self.links.blocked_domains(&links.domains);
+        if !blocked.is_empty() {
+            self.alert_blocked_link(&ctx, &msg, &blocked).await;
+        }
+
+        // Staff messages are stored for response-time analytics but never analyzed, so
+        // short answers count too
+        let is_staff = is_staff(&msg);
+
+        // Check every message for raids and copy-paste spam, even ones that aren't stored
+        let spam = if is_staff {
+            None
+        } else {
+            self.spam.check(
+                &msg.id.to_string(),
+                msg.author.id.0,
+                &msg.content,
+                account_created(&msg),
+                Utc::now(),
+            )
+        };
+        if let Some(verdict) = &spam {
+            self.handle_spam(&ctx, &msg, verdict).await;
+        }
+
+        // Watchlist mentions are checked for every message too, and alerted after processing
+        let hits = self.watchlist.check(
+            msg.guild_id.map(|id| id.0),
+            msg.channel_id.0,
+            parent_id,
+            &msg.content,
+            Utc::now(),
+        );
+
+        // Score community messages for abuse and alert moderators above the thresholds. Short
+        // abuse ("you are trash") matters most here, so this runs before the word count check.
+        let opted_out = is_opted_out(&self.opt_outs, msg.author.id.0).await;
+        let toxicity = match &self.toxicity {
+            Some(classifier)
+                if !is_staff && spam.is_none() && !(opted_out && classifier.is_external()) =>
+            {
+                // PII is redacted before the text can leave for AWS or appear in an alert
+                let (toxicity_text, _) = self.redactor.redact(&links.text);
+                let scores = if toxicity_text.trim().is_empty() {
+                    None
+                } else {
+                    classifier
+                        .classify(&toxicity_text)
+                        .await
+                        .map_err(|err| println!("Error classifying toxicity: {}", err))
+                        .ok()
+                };
+                if let Some(scores) = &scores {
+                    let exceeding = scores.exceeding(&self.toxicity_thresholds);
+                    if !exceeding.is_empty() {
+                        self.alert_toxicity(&ctx, &msg, &toxicity_text, &exceeding)
+                            .await;
+                    }
+                }
+                scores
             }
+            _ => None,
+        };
+
+        // Skip users who opted out of data collection with `/privacy optout`
+        let analysis = if opted_out {
+            None
+        } else {
+            let screening = Screening {
+                is_staff,
+                spam,
+                toxicity,
+                channel_name,
+                thread,
+            };
+            self.store_message(&ctx, &msg, screening).await
+        };
+
+        // Watchlist alerts go out once the message is analyzed, so they reuse its analysis
+        if !hits.is_empty() {
+            self.handle_watchlist_hits(&ctx, &msg, &hits, opted_out, analysis)
+                .await;
         }
+    }
+}
 
-        // Try to analyze sentiment and log any error that occurs
-        let sentiment = analyze_sentiment(&content)
-            .await
-            .map_err(|err| println!("Error detecting sentiment: {}", err))
-            .ok();
+impl Handler {
+    // Analyzes and stores a message that passed moderation. Returns the analysis when the
+    // message was analyzed.
+    async fn store_message(
+        &self,
+        ctx: &Context,
+        msg: &DiscordMessage,
+        screening: Screening,
+    ) -> Option<MessageAnalysis> {
+        let Screening {
+            is_staff,
+            spam,
+            toxicity,
+            channel_name,
+            thread,
+        } = screening;
+
+        // Screenshots and link embeds are stored even with little text, but short messages
+        // are not analyzed
+        let has_media = !msg.attachments.is_empty() || !msg.embeds.is_empty();
+        let has_enough_words = has_minimum_word_count(msg, 5);
+        if !is_staff && !has_enough_words && !has_media {
+            return None;
+        }
+
+        // Replace mentions in the message content
+        let content = replace_mentions(ctx, msg).await;
+
+        // Replace URLs with domain placeholders. Link-only messages aren't analyzed; their
+        // links are represented by the domains and embeds.
+        let links = self.links.process(&content);
+        if links.link_only && !is_staff && !has_media {
+            return None;
+        }
+        let content = if links.link_only && !is_staff {
+            String::new()
+        } else {
+            links.text
+        };
+
+        // Turn emojis and emoticons into words Comprehend understands and drop code blocks
+        // and spoilers
+        let (content, emoji) = normalize_text(&content, &self.custom_emoji);
+        if content.is_empty() && !is_staff && !has_media {
+            return None;
+        }
+
+        // Redact PII before the content leaves for AWS or is stored
+        let (content, redactions) = self.redactor.redact(&content);
+
+        let attachments: Vec<AttachmentInfo> = msg
+            .attachments
+            .iter()
+            .map(|attachment| AttachmentInfo::new(attachment, &self.redactor))
+            .collect();
+        let embeds: Vec<EmbedInfo> = msg
+            .embeds
+            .iter()
+            .map(|embed| EmbedInfo::new(embed, &self.redactor))
+            .collect();
+        let issue_report = !is_staff && is_issue_report(&content, &attachments, !has_enough_words);
+
+        // Optionally analyze the link previews along with the message
+        let mut analysis_text = content.clone();
+        if self.analyze_embed_text && !embeds.is_empty() {
+            analysis_text = format!("{}\n{}", analysis_text, embed_text(&embeds))
+                .trim()
+                .to_string();
+        }
+
+        let analyze =
+            !is_staff && spam.is_none() && (has_enough_words || count_words(&analysis_text) >= 5);
+        let (sentiment, translate_ko) = if !analyze {
+            (None, None)
+        } else {
+            // Try to analyze sentiment and log any error that occurs
+            let sentiment = analyze_sentiment(&analysis_text)
+                .await
+                .map_err(|err| println!("Error detecting sentiment: {}", err))
+                .ok();
 
-        // Translate the message content to Korean
-        let translate_ko = translate_to_ko(&content).await.map_or_else(
-            |err| {
-                println!("Error translating to Korean: {}", err);
+            // Translate the message content to Korean
+            let translate_ko = if content.is_empty() {
                 None
-            },
-            |translated_text| translated_text,
-        );
+            } else {
+                translate_to_ko(&content).await.unwrap_or_else(|err| {
+                    println!("Error translating to Korean: {}", err);
+                    None
+                })
+            };
+
+            (sentiment, translate_ko)
+        };
+
+        // Break analyzed messages down into emotions beyond polarity
+        let emotions = match &self.emotions {
+            Some(classifier) if analyze => classifier
+                .classify(&analysis_text)
+                .await
+                .map_err(|err| println!("Error classifying emotions: {}", err))
+                .ok()
+                .flatten(),
+            _ => None,
+        };
+
+        // Extract what the message is about and tag it with topics from the taxonomy
+        let phrases = match &self.phrases {
+            Some(extractor) if analyze => extractor
+                .extract(&analysis_text)
+                .await
+                .map_err(|err| println!("Error extracting key phrases: {}", err))
+                .unwrap_or_default(),
+            _ => ExtractedPhrases::default(),
+        };
+        let topics = self.topics.tag(&analysis_text, &phrases.key_phrases);
+
+        // Sentiment toward each entity/topic the message mentions
+        let aspects = match &self.aspects {
+            Some(analyzer) if analyze => analyzer
+                .analyze(&analysis_text, &self.topics)
+                .await
+                .map_err(|err| println!("Error analyzing aspects: {}", err))
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        };
 
         // Adjust the timestamp to the local timezone (UTC+9)
-        let adjusted_timestamp = Utc::now() + Duration::hours(9);
+        let adjusted_timestamp = local_now();
+
+        // Store a pseudonym instead of the author's identity when pseudonymization is enabled
+        let (author_id, username) = self.author_identity(msg).await?;
 
-        // Get the name of the channel the message was sent in
-        let channel_name = get_channel_name(ctx, &msg).await.unwrap_or_default();
+        // Link the message into its conversation session
+        let session_message = SessionMessage {
+            message_id: msg.id.to_string(),
+            channel_id: msg.channel_id.to_string(),
+            channel: &channel_name,
+            reply_to: reply_to(msg),
+            author_id: &author_id,
+            is_staff,
+            is_question: !is_staff && is_question(&content),
+            is_issue_report: issue_report,
+            sentiment: sentiment.as_deref(),
+            created_at: adjusted_timestamp,
+        };
+        let session_id = record_session_message(&self.db, &session_message, self.session_gap)
+            .await
+            .map_err(|e| println!("Error recording session: {:?}", e))
+            .ok();
 
         // Create a Message struct from the discord message
-        let message = Message {
+        let mut message = Message {
             id: None,
-            username: msg.author.name,
+            message_id: Some(msg.id.to_string()),
+            channel_id: Some(msg.channel_id.to_string()),
+            reply_to: reply_to(msg),
+            session_id,
+            author_id,
+            username,
+            role: Some(if is_staff { "staff" } else { "community" }.to_string()),
             channel: channel_name,
+            thread,
             text: content,
             korean: translate_ko,
+            key_id: None,
+            redactions,
             analyzed: sentiment,
+            reactions: Default::default(),
+            reaction_sentiment: None,
+            emoji,
+            attachments,
+            embeds,
+            issue_report,
+            domains: links.domains,
+            spam: spam.map(|verdict| verdict.reason.as_str().to_string()),
+            toxicity,
+            emotions,
+            key_phrases: phrases.key_phrases,
+            entities: phrases.entities,
+            topics,
+            aspects,
+            cluster_id: None,
             created_at: adjusted_timestamp,
-            ..Default::default()
         };
 
+        // Kept in plaintext for the watchlist alerts
+        let analysis = analyze.then(|| MessageAnalysis {
+            content: message.text.clone(),
+            sentiment: message.analyzed.clone(),
+            korean: message.korean.clone(),
+        });
+
+        // Encrypt the message text and translation when encryption is enabled
+        if let Some(keyring) = &self.keyring {
+            if let Err(e) = keyring.encrypt_message(&mut message) {
+                println!("Error encrypting message: {}", e);
+                return None;
+            }
+        }
+
         // Save the message to the database
         if let Err(e) = save_message(&self.db, &message).await {
             println!("Error saving message: {:?}", e);
         }
+
+        // Update the thread's sentiment trajectory
+        if let Some(thread) = &message.thread {
+            if let Err(e) = record_thread_message(
+                &self.db,
+                thread,
+                &message.channel,
+                message.analyzed.as_deref(),
+                message.created_at,
+            )
+            .await
+            {
+                println!("Error updating thread: {:?}", e);
+            }
+        }
+
+        analysis
+    }
+
+    async fn alert_toxicity(
+        &self,
+        ctx: &Context,
+        msg: &DiscordMessage,
+        content: &str,
+        categories: &[(ToxicityCategory, f64)],
+    ) {
+        let embed = toxicity_alert_embed(
+            msg.author.id,
+            msg.channel_id,
+            &msg.link(),
+            content,
+            categories,
+        );
+        if let Err(e) = self
+            .toxicity_alert_channel
+            .send_message(&ctx.http, |m| m.set_embed(embed))
+            .await
+        {
+            println!("Error sending toxicity alert: {:?}", e);
+        }
+    }
+
+    async fn handle_spam(&self, ctx: &Context, msg: &DiscordMessage, verdict: &SpamVerdict) {
+        // The earlier copies were stored before the pattern was recognizable
+        if let Err(e) = mark_spam(&self.db, &verdict.related_message_ids, verdict.reason).await {
+            println!("Error marking spam: {:?}", e);
+        }
+
+        if !self.spam.should_alert(verdict.reason, msg.channel_id.0) {
+            return;
+        }
+        let (sample, _) = self.redactor.redact(&msg.content);
+        let embed = spam_alert_embed(verdict, msg.channel_id, &msg.link(), &sample);
+        if let Err(e) = self
+            .spam_alert_channel
+            .send_message(&ctx.http, |m| m.set_embed(embed))
+            .await
+        {
+            println!("Error sending spam alert: {:?}", e);
+        }
+    }
+
+    async fn handle_watchlist_hits(
+        &self,
+        ctx: &Context,
+        msg: &DiscordMessage,
+        hits: &[WatchlistHit],
+        opted_out: bool,
+        analysis: Option<MessageAnalysis>,
+    ) {
+        // Messages the pipeline didn't analyze (short, link-only or spam) are analyzed here
+        // when an alert goes out. Messages of users who opted out are never sent to AWS.
+        let analysis = match analysis {
+            Some(analysis) => analysis,
+            None => {
+                let content = self.alert_content(ctx, msg).await;
+                let (sentiment, korean) =
+                    if opted_out || content.is_empty() || !hits.iter().any(|hit| hit.alert) {
+                        (None, None)
+                    } else {
+                        let sentiment = analyze_sentiment(&content)
+                            .await
+                            .map_err(|err| println!("Error detecting sentiment: {}", err))
+                            .ok();
+                        let korean = translate_to_ko(&content).await.unwrap_or_else(|err| {
+                            println!("Error translating to Korean: {}", err);
+                            None
+                        });
+                        (sentiment, korean)
+                    };
+                MessageAnalysis {
+                    content,
+                    sentiment,
+                    korean,
+                }
+            }
+        };
+        let MessageAnalysis {
+            content,
+            sentiment,
+            korean: translate_ko,
+        } = analysis;
+
+        let author_id = if opted_out {
+            None
+        } else {
+            self.author_identity(msg)
+                .await
+                .map(|(author_id, _)| author_id)
+        };
+        for hit in hits {
+            if let Err(e) = record_hit(
+                &self.db,
+                hit,
+                &msg.id.to_string(),
+                &msg.channel_id.to_string(),
+                author_id.as_deref(),
+                sentiment.as_deref(),
+                local_now(),
+            )
+            .await
+            {
+                println!("Error recording watchlist hit: {:?}", e);
+            }
+            if !hit.alert {
+                continue;
+            }
+
+            let embed = watchlist_alert_embed(
+                hit,
+                msg.author.id,
+                msg.channel_id,
+                &msg.link(),
+                &content,
+                sentiment.as_deref(),
+                translate_ko.as_deref(),
+            );
+            if let Some(channel) = hit.alert_channel {
+                let embed = embed.clone();
+                if let Err(e) = channel
+                    .send_message(&ctx.http, |m| m.set_embed(embed))
+                    .await
+                {
+                    println!("Error sending watchlist alert: {:?}", e);
+                }
+            }
+            for user_id in &hit.dm_users {
+                if let Err(e) = send_embed_to_user_http(&ctx.http, *user_id, embed.clone()).await {
+                    println!("Error sending watchlist alert to {}: {:?}", user_id, e);
+                }
+            }
+        }
+    }
+
+    // The message content as the pipeline prepares it: mentions replaced, links turned into
+    // domain placeholders, emojis normalized and PII redacted
+    async fn alert_content(&self, ctx: &Context, msg: &DiscordMessage) -> String {
+        let content = replace_mentions(ctx, msg).await;
+        let links = self.links.process(&content);
+        let (content, _) = normalize_text(&links.text, &self.custom_emoji);
+        self.redactor.redact(&content).0
+    }
+
+    async fn alert_blocked_link(&self, ctx: &Context, msg: &DiscordMessage, domains: &[String]) {
+        let embed = blocked_link_embed(msg.author.id, msg.channel_id, &msg.link(), domains);
+        if let Err(e) = self
+            .link_alert_channel
+            .send_message(&ctx.http, |m| m.set_embed(embed))
+            .await
+        {
+            println!("Error sending blocked link alert: {:?}", e);
+        }
+    }
+
+    async fn track_reaction(&self, reaction: &Reaction, delta: i32) {
+        let guild = env::var("DISCORD_GUILD").unwrap_or_default();
+        if reaction.guild_id.map(|id| id.to_string()) != Some(guild) {
+            return;
+        }
+        if let Some(user_id) = reaction.user_id {
+            if is_opted_out(&self.opt_outs, user_id.0).await {
+                return;
+            }
+        }
+
+        let Some((key, polarity)) = classify_reaction(&reaction.emoji, &self.custom_emoji) else {
+            return;
+        };
+        let message_id = reaction.message_id.to_string();
+        if let Err(e) = record_reaction(&self.db, &message_id, &key, polarity, delta).await {
+            println!("Error recording reaction: {:?}", e);
+        }
+    }
+
+    // Returns the author ID and username to store, or the pseudonym and no username when
+    // pseudonymization is enabled. None if the pseudonym couldn't be recorded.
+    async fn author_identity(&self, msg: &DiscordMessage) -> Option<(String, Option<String>)> {
+        match &self.pseudonymizer {
+            Some(pseudonymizer) => match pseudonymizer.pseudonymize(msg.author.id.0).await {
+                Ok(pseudonym) => Some((pseudonym, None)),
+                Err(e) => {
+                    println!("Error recording pseudonym: {:?}", e);
+                    None
+                }
+            },
+            None => Some((msg.author.id.to_string(), Some(msg.author.name.clone()))),
+        }
     }
 }
 
-pub async fn run_discord_bot(token: &str, db: Database) -> tokio::task::JoinHandle<()> {
-    let intents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::MESSAGE_CONTENT;
-    let mut client = Client::builder(&token, intents)
-        .event_handler(Handler { db })
+fn reply_to(msg: &DiscordMessage) -> Option<String> {
+    msg.message_reference
+        .as_ref()
+        .and_then(|reference| reference.message_id)
+        .map(|id| id.to_string())
+}
+
+pub async fn run_discord_bot(config: &EnvConfig, db: Database) -> tokio::task::JoinHandle<()> {
+    let token = &config.discord_token;
+    let intents = GatewayIntents::GUILD_MESSAGES
+        | GatewayIntents::MESSAGE_CONTENT
+        | GatewayIntents::GUILD_MESSAGE_REACTIONS;
+    let opt_outs = load_opt_outs(&db)
+        .await
+        .expect("Failed to load privacy opt-outs");
+    let charts = config.charts.enabled && register_chart_font(&config.charts.font_path);
+    let report_db = db.clone();
+    let clustering_db = db.clone();
+    let churn_db = db.clone();
+    let health_db = db.clone();
+    let anomaly_db = db.clone();
+    let announcement_db = db.clone();
+    let mut client = Client::builder(token, intents)
+        .event_handler(Handler {
+            opt_outs: opt_outs.clone(),
+            redactor: Redactor::new(&config.redaction),
+            pseudonymizer: load_pseudonymizer(config).await,
+            keyring: config.encryption.as_ref().map(Keyring::new),
+            session_gap: Duration::minutes(config.sessions.gap_minutes),
+            custom_emoji: config.reactions.custom_emoji.clone(),
+            analyze_embed_text: config.media.analyze_embed_text,
+            links: LinkProcessor::new(&config.links),
+            link_alert_channel: ChannelId(config.links.alert_channel_id),
+            spam: SpamDetector::new(&config.spam),
+            spam_alert_channel: ChannelId(config.spam.alert_channel_id),
+            toxicity: load_toxicity_classifier(&config.toxicity),
+            toxicity_thresholds: config.toxicity.alert_thresholds.clone(),
+            toxicity_alert_channel: ChannelId(config.toxicity.alert_channel_id),
+            emotions: load_emotion_classifier(&config.emotions),
+            phrases: load_phrase_extractor(&config.phrases),
+            topics: TopicTagger::new(&config.topics),
+            aspects: load_aspect_analyzer(&config.aspects, &config.emotions),
+            watchlist: Watchlist::new(&config.watchlists, default_notify_channel_id()),
+            announcement_channels: config.announcements.channel_ids.clone(),
+            charts,
+            db,
+        })
         .await
         .expect("Error creating Discord client");
 
     // Create a separate client for monitoring and sending memory stats
-    let monitoring_client = Client::builder(&token, intents)
+    let monitoring_client = Client::builder(token, intents)
         .await
         .expect("Error creating monitoring Discord client");
 
@@ -112,13 +762,65 @@ pub async fn run_discord_bot(token: &str, db: Database) -> tokio::task::JoinHand
     tokio::spawn(monitor_memory_stats(monitoring_client, channel_id));
 
     // Create a separate client for signal alerts
-    let signal_alert_client = Client::builder(&token, intents)
+    let signal_alert_client = Client::builder(token, intents)
         .await
         .expect("Error creating signal alert Discord client");
 
     // Start sending signal alerts
     tokio::spawn(send_signal_alert(signal_alert_client, channel_id));
 
+    // Post the weekly digest
+    tokio::spawn(start_report_scheduler(
+        client.cache_and_http.http.clone(),
+        report_db,
+        ChannelId(config.reports.channel_id),
+        config.reports.schedule.clone(),
+        charts,
+    ));
+
+    // Cluster the week's messages and post emerging topics
+    if config.clustering.enabled {
+        tokio::spawn(start_clustering_scheduler(
+            client.cache_and_http.http.clone(),
+            clustering_db,
+            config.encryption.as_ref().map(Keyring::new),
+            config.clustering.clone(),
+        ));
+    }
+
+    // Score every channel's health for the previous day
+    if config.health.enabled {
+        tokio::spawn(start_health_scheduler(health_db, config.health.clone()));
+    }
+
+    // Alert on hours with unusually high or low volume
+    if config.anomalies.enabled {
+        tokio::spawn(start_anomaly_scheduler(
+            client.cache_and_http.http.clone(),
+            anomaly_db,
+            config.anomalies.clone(),
+        ));
+    }
+
+    // Post an impact report once each announcement's after window has passed
+    tokio::spawn(start_announcement_scheduler(
+        client.cache_and_http.http.clone(),
+        announcement_db,
+        config.encryption.as_ref().map(Keyring::new),
+        config.announcements.clone(),
+    ));
+
+    // Flag members whose sentiment fell well below their baseline
+    if config.churn.enabled {
+        tokio::spawn(start_churn_scheduler(
+            client.cache_and_http.http.clone(),
+            churn_db,
+            opt_outs,
+            load_pseudonymizer(config).await,
+            config.churn.clone(),
+        ));
+    }
+
     let handler = tokio::spawn(async move {
         client.start().await.expect("Error starting Discord client");
     });
@@ -126,16 +828,79 @@ pub async fn run_discord_bot(token: &str, db: Database) -> tokio::task::JoinHand
     handler
 }
 
-async fn get_channel_name(ctx: Context, message: &DiscordMessage) -> Option<String> {
-    let channel_id = message.channel_id;
-    let channel = channel_id.to_channel(&ctx).await.ok()?;
+// The later of the account creation and guild join times, used to recognize new accounts
+fn account_created(msg: &DiscordMessage) -> chrono::DateTime<Utc> {
+    let created = msg.author.id.created_at().unix_timestamp();
+    let joined = msg
+        .member
+        .as_ref()
+        .and_then(|member| member.joined_at)
+        .map(|joined_at| joined_at.unix_timestamp())
+        .unwrap_or_default();
+    Utc.timestamp_opt(created.max(joined), 0)
+        .single()
+        .unwrap_or_else(Utc::now)
+}
+
+// Returns the channel name and, for threads and forum posts, the thread context. Thread
+// messages are attributed to the parent channel so channel statistics include them.
+pub async fn get_channel_info(
+    ctx: &Context,
+    channel_id: ChannelId,
+) -> Option<(String, Option<ThreadInfo>)> {
+    let channel = channel_id.to_channel(ctx).await.ok()?;
     match channel {
-        Channel::Guild(channel) => Some(channel.name),
-        Channel::Private(channel) => Some(channel.name()),
+        Channel::Guild(channel) if is_thread(&channel) => Some(get_thread_info(ctx, channel).await),
+        Channel::Guild(channel) => Some((channel.name, None)),
+        Channel::Private(channel) => Some((channel.name(), None)),
+        Channel::Category(category) => Some((category.name, None)),
         _ => None,
     }
 }
 
+fn is_thread(channel: &GuildChannel) -> bool {
+    matches!(
+        channel.kind,
+        ChannelType::PublicThread | ChannelType::PrivateThread | ChannelType::NewsThread
+    )
+}
+
+async fn get_thread_info(ctx: &Context, thread: GuildChannel) -> (String, Option<ThreadInfo>) {
+    let parent = match thread.parent_id {
+        Some(parent_id) => match parent_id.to_channel(ctx).await {
+            Ok(Channel::Guild(parent)) => Some(parent),
+            _ => None,
+        },
+        None => None,
+    };
+
+    // Forum posts carry tag IDs; the tag names are defined on the forum channel
+    let tags = parent
+        .as_ref()
+        .map(|parent| {
+            parent
+                .available_tags
+                .iter()
+                .filter(|tag| thread.applied_tags.contains(&tag.id))
+                .map(|tag| tag.name.clone())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let info = ThreadInfo {
+        id: thread.id.to_string(),
+        name: thread.name.clone(),
+        parent_id: thread
+            .parent_id
+            .map(|id| id.to_string())
+            .unwrap_or_default(),
+        tags,
+    };
+    let channel_name = parent.map(|parent| parent.name).unwrap_or(thread.name);
+
+    (channel_name, Some(info))
+}
+
 pub fn memory_stats_embed(stats: MemoryStats) -> CreateEmbed {
     let mut embed = CreateEmbed::default();
     embed
@@ -195,17 +960,591 @@ pub async fn send_embed_to_user(
     user_id: u64,
     embed: CreateEmbed,
 ) -> Result<(), serenity::Error> {
-    let user = UserId(user_id).to_user(&client.cache_and_http.http).await?;
+    send_embed_to_user_http(&client.cache_and_http.http, user_id, embed).await
+}
+
+pub async fn send_embed_to_user_http(
+    http: &Http,
+    user_id: u64,
+    embed: CreateEmbed,
+) -> Result<(), serenity::Error> {
+    let user = UserId(user_id).to_user(http).await?;
 
-    let dm_channel = user.create_dm_channel(&client.cache_and_http.http).await?;
+    let dm_channel = user.create_dm_channel(http).await?;
 
     dm_channel
-        .send_message(&client.cache_and_http.http, |m| m.set_embed(embed))
+        .send_message(http, |m| m.set_embed(embed))
         .await?;
 
     Ok(())
 }
 
+pub fn privacy_deletion_embed(deleted_count: u64) -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+    embed
+        .title("Data Deletion Confirmation")
+        .description("Your data deletion request has been processed. The messages we stored from you, and the data derived from them, have been removed. Messages stored before author IDs were recorded can't be traced back to you and expire with the three-week retention.")
+        .field("Deleted Documents", deleted_count.to_string(), true)
+        .timestamp(chrono::Utc::now().to_rfc3339())
+        .color(Color::new(0x00ff00));
+
+    embed
+}
+
+pub fn weekly_report_embed(report: &WeeklyReport) -> CreateEmbed {
+    let total: i64 = report.sentiment_counts.iter().map(|(_, count)| count).sum();
+    let sentiment = sentiment_summary(&report.sentiment_counts);
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title("Weekly Sentiment Digest")
+        .description(format!(
+            "{} ~ {}",
+            report.since.format("%Y-%m-%d"),
+            report.until.format("%Y-%m-%d")
+        ))
+        .field(format!("Sentiment ({} messages)", total), sentiment, false)
+        .field(
+            format!("Emotions ({} messages)", report.emotion_messages),
+            emotion_summary(&report.emotions),
+            false,
+        )
+        .field(
+            format!("Reactions ({})", report.reactions.total()),
+            reaction_summary(&report.reactions),
+            false,
+        )
+        .field("Topics", topic_summary(&report.topics), false)
+        .field("Aspects by topic", topic_summary(&report.aspects), false)
+        .field("Support", support_stats_summary(&report.support), false)
+        .field(
+            "Channel health (least healthy first)",
+            channel_health_summary(&report.channel_health),
+            false,
+        )
+        .timestamp(chrono::Utc::now().to_rfc3339())
+        .color(Color::new(0x0000ff));
+
+    embed
+}
+
+pub fn sentiment_embed(
+    counts: &[(String, i64)],
+    emotions: &[(String, f64)],
+    emotion_messages: i64,
+    days: i64,
+) -> CreateEmbed {
+    let total: i64 = counts.iter().map(|(_, count)| count).sum();
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title("Community Sentiment")
+        .description(format!("Messages posted in the last {} day(s)", days))
+        .field(
+            format!("Sentiment ({} messages)", total),
+            sentiment_summary(counts),
+            false,
+        )
+        .field(
+            format!("Emotions ({} messages)", emotion_messages),
+            emotion_summary(emotions),
+            false,
+        )
+        .timestamp(chrono::Utc::now().to_rfc3339())
+        .color(Color::new(0x0000ff));
+
+    embed
+}
+
+fn sentiment_summary(counts: &[(String, i64)]) -> String {
+    let total: i64 = counts.iter().map(|(_, count)| count).sum();
+    counts
+        .iter()
+        .map(|(label, count)| {
+            format!(
+                "{}: {} ({:.1}%)",
+                label,
+                count,
+                *count as f64 / total.max(1) as f64 * 100.0
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn support_stats_embed(stats: &[ChannelSupportStats], days: i64) -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+    embed
+        .title("Support Statistics")
+        .description(format!("Conversations started in the last {} day(s)", days))
+        .timestamp(chrono::Utc::now().to_rfc3339())
+        .color(Color::new(0x0000ff));
+
+    if stats.is_empty() {
+        embed.field("No conversations", "Nothing to report yet.", false);
+    }
+    // Discord allows at most 25 fields per embed
+    for channel in stats.iter().take(25) {
+        embed.field(
+            format!("#{}", channel.channel),
+            format!(
+                "Conversations: {} (answered {})\nFirst response: {}\nResolution: {}\nUnanswered questions: {}\nIssue reports: {}",
+                channel.sessions,
+                channel.answered,
+                format_seconds(channel.avg_first_response_seconds),
+                format_seconds(channel.avg_resolution_seconds),
+                channel.unanswered_questions,
+                channel.issue_reports,
+            ),
+            true,
+        );
+    }
+
+    embed
+}
+
+pub fn emerging_topics_embed(clusters: &[TopicCluster]) -> CreateEmbed {
+    let emerging = emerging_clusters(clusters);
+    // New clusters where at least half of the analyzed messages are negative
+    let (negative, other): (Vec<&TopicCluster>, Vec<&TopicCluster>) = emerging
+        .into_iter()
+        .partition(|cluster| cluster.analyzed > 0 && cluster.negative_share() >= 0.5);
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title("Emerging Topics This Week")
+        .description(format!(
+            "{} messages grouped into {} topic clusters",
+            clusters.iter().map(|cluster| cluster.size).sum::<i64>(),
+            clusters.len()
+        ))
+        .field(
+            "New and mostly negative",
+            cluster_summary(&negative, "No new negative topics"),
+            false,
+        )
+        .field(
+            "Other new topics",
+            cluster_summary(&other, "No other new topics"),
+            false,
+        )
+        .timestamp(chrono::Utc::now().to_rfc3339())
+        .color(if negative.is_empty() {
+            Color::new(0x0000ff)
+        } else {
+            Color::new(0xff0000)
+        });
+
+    embed
+}
+
+// Pseudonymized members are listed by pseudonym; `reidentify` resolves one when needed
+pub fn at_risk_members_embed(members: &[AtRiskMember], pseudonymized: bool) -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+    embed
+        .title("At-Risk Members")
+        .description("Long-time members whose sentiment this week fell well below their usual")
+        .timestamp(chrono::Utc::now().to_rfc3339())
+        .color(Color::new(0xff8c00));
+
+    if members.is_empty() {
+        embed.field(
+            "No members at risk",
+            "Nobody's sentiment dropped significantly.",
+            false,
+        );
+    }
+    // Discord allows at most 25 fields per embed
+    for member in members.iter().take(25) {
+        let name = if pseudonymized {
+            format!("`{}`", member.author_id)
+        } else {
+            format!("<@{}>", member.author_id)
+        };
+        embed.field(
+            "Member",
+            format!(
+                "{}\nUsual score {:+.2}, this week {:+.2} over {} messages (z {:.1})",
+                name, member.baseline, member.score, member.messages, member.z
+            ),
+            false,
+        );
+    }
+
+    embed
+}
+
+fn cluster_summary(clusters: &[&TopicCluster], empty: &str) -> String {
+    if clusters.is_empty() {
+        return empty.to_string();
+    }
+
+    clusters
+        .iter()
+        .take(10)
+        .map(|cluster| {
+            format!(
+                "**{}**: {} messages ({} positive, {} negative)",
+                cluster.label, cluster.size, cluster.positive, cluster.negative
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn channel_health_summary(channels: &[ChannelHealthSummary]) -> String {
+    if channels.is_empty() {
+        return "No channel health scores yet".to_string();
+    }
+
+    channels
+        .iter()
+        .take(10)
+        .map(|channel| {
+            format!(
+                "#{}: {:.0} ({} days, {} messages)",
+                channel.channel, channel.score, channel.days, channel.messages
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn channel_health_embed(channel: &str, days: &[ChannelHealth]) -> CreateEmbed {
+    let component = |value: Option<f64>| match value {
+        Some(value) => format!("{:.2}", value),
+        None => "-".to_string(),
+    };
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(format!("Channel Health: #{}", channel))
+        .description("Daily score from 0 to 100; components from 0 (unhealthy) to 1")
+        .timestamp(chrono::Utc::now().to_rfc3339())
+        .color(Color::new(0x0000ff));
+
+    if days.is_empty() {
+        embed.field("No scores", "This channel hasn't been scored yet.", false);
+    }
+    for day in days {
+        embed.field(
+            format!("{}: {:.0}", day.date.format("%Y-%m-%d"), day.score),
+            format!(
+                "Sentiment {} · Toxicity {} · Spam {} · Volume {} · Response {}\n{} messages",
+                component(day.sentiment),
+                component(day.toxicity),
+                component(day.spam),
+                component(day.volume),
+                component(day.response),
+                day.messages
+            ),
+            false,
+        );
+    }
+
+    embed
+}
+
+fn emotion_summary(emotions: &[(String, f64)]) -> String {
+    emotions
+        .iter()
+        .map(|(emotion, share)| format!("{}: {:.1}%", emotion, share * 100.0))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn topic_summary(topics: &[TopicSentiment]) -> String {
+    if topics.is_empty() {
+        return "No tagged messages".to_string();
+    }
+
+    topics
+        .iter()
+        .take(10)
+        .map(|topic| {
+            let score = match topic.score {
+                Some(score) => format!("{:+.2}", score),
+                None => "-".to_string(),
+            };
+            format!(
+                "{}: {} messages, score {} ({} positive, {} negative)",
+                topic.topic, topic.messages, score, topic.positive, topic.negative
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn reaction_summary(reactions: &ReactionSentiment) -> String {
+    let score = match reactions.score() {
+        Some(score) => format!("{:+.2}", score),
+        None => "-".to_string(),
+    };
+    format!(
+        "positive: {}\nneutral: {}\nnegative: {}\nNet reaction score: {}",
+        reactions.positive, reactions.neutral, reactions.negative, score
+    )
+}
+
+fn support_stats_summary(stats: &[ChannelSupportStats]) -> String {
+    if stats.is_empty() {
+        return "No conversations".to_string();
+    }
+
+    stats
+        .iter()
+        .take(10)
+        .map(|channel| {
+            format!(
+                "#{}: first response {}, resolution {}, {} unanswered, {} issue reports",
+                channel.channel,
+                format_seconds(channel.avg_first_response_seconds),
+                format_seconds(channel.avg_resolution_seconds),
+                channel.unanswered_questions,
+                channel.issue_reports,
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn format_seconds(seconds: Option<f64>) -> String {
+    let Some(seconds) = seconds else {
+        return "-".to_string();
+    };
+    let minutes = (seconds / 60.0).round() as i64;
+    if minutes < 60 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}h {}m", minutes / 60, minutes % 60)
+    }
+}
+
+pub fn blocked_link_embed(
+    author: UserId,
+    channel: ChannelId,
+    link: &str,
+    domains: &[String],
+) -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+    embed
+        .title("Blocked Link Posted")
+        .description(format!(
+            "<@{}> posted a link to a known scam/phishing domain in <#{}>.",
+            author.0, channel.0
+        ))
+        .field("Domains", domains.join("\n"), false)
+        .field("Message", link, false)
+        .timestamp(chrono::Utc::now().to_rfc3339())
+        .color(Color::new(0xff0000));
+
+    embed
+}
+
+pub fn spam_alert_embed(
+    verdict: &SpamVerdict,
+    channel: ChannelId,
+    link: &str,
+    sample: &str,
+) -> CreateEmbed {
+    let description = match verdict.reason {
+        SpamReason::Duplicate => "The same message is being posted repeatedly",
+        SpamReason::Flood => "A user is flooding the channel",
+        SpamReason::NewAccountBurst => "Many new accounts are posting at once",
+    };
+    let authors = verdict
+        .authors
+        .iter()
+        .take(20)
+        .map(|author| format!("<@{}>", author))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let sample: String = sample.chars().take(200).collect();
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title("Possible Spam")
+        .description(format!("{} in <#{}>.", description, channel.0))
+        .field("Users", authors, false)
+        .field("Latest message", link, false)
+        .field("Sample", format!("`{}`", sample.replace('`', "'")), false)
+        .timestamp(chrono::Utc::now().to_rfc3339())
+        .color(Color::new(0xff8c00));
+
+    embed
+}
+
+pub fn toxicity_alert_embed(
+    author: UserId,
+    channel: ChannelId,
+    link: &str,
+    content: &str,
+    categories: &[(ToxicityCategory, f64)],
+) -> CreateEmbed {
+    let scores = categories
+        .iter()
+        .map(|(category, score)| format!("{}: {:.2}", category.as_str(), score))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let content: String = content.chars().take(200).collect();
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title("Abusive Message")
+        .description(format!(
+            "<@{}> posted a message in <#{}> that may be abusive.",
+            author.0, channel.0
+        ))
+        .field("Scores", scores, true)
+        .field("Message", link, true)
+        .field("Content", format!("`{}`", content.replace('`', "'")), false)
+        .timestamp(chrono::Utc::now().to_rfc3339())
+        .color(Color::new(0xff0000));
+
+    embed
+}
+
+pub fn watchlist_alert_embed(
+    hit: &WatchlistHit,
+    author: UserId,
+    channel: ChannelId,
+    link: &str,
+    content: &str,
+    sentiment: Option<&str>,
+    translate_ko: Option<&str>,
+) -> CreateEmbed {
+    let content: String = content.chars().take(200).collect();
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(format!("Watchlist: {}", hit.rule))
+        .description(format!(
+            "<@{}> mentioned {} in <#{}>.",
+            author.0,
+            hit.terms.join(", "),
+            channel.0
+        ))
+        .field("Sentiment", sentiment.unwrap_or("-"), true)
+        .field("Message", link, true)
+        .field("Content", format!("`{}`", content.replace('`', "'")), false)
+        .timestamp(chrono::Utc::now().to_rfc3339())
+        .color(Color::new(0xff8c00));
+    if let Some(translate_ko) = translate_ko {
+        let translate_ko: String = translate_ko.chars().take(200).collect();
+        embed.field("Korean", translate_ko, false);
+    }
+
+    embed
+}
+
+pub fn volume_anomaly_embed(anomaly: &VolumeAnomaly) -> CreateEmbed {
+    let (title, description, color) = match anomaly.kind {
+        AnomalyKind::Surge if anomaly.incident => (
+            "Possible Incident",
+            "Message volume surged and most analyzed messages are negative",
+            0xff0000,
+        ),
+        AnomalyKind::Surge => (
+            "Activity Surge",
+            "Message volume surged without a negative turn",
+            0x00ff00,
+        ),
+        AnomalyKind::Silence => (
+            "Unusual Silence",
+            "Message volume dropped far below the usual for this hour",
+            0xff8c00,
+        ),
+    };
+    let negative_share = match anomaly.negative_share {
+        Some(share) => format!("{:.0}%", share * 100.0),
+        None => "-".to_string(),
+    };
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(title)
+        .description(format!(
+            "{} in #{} between {} and {} KST.",
+            description,
+            anomaly.channel,
+            anomaly.hour.format("%Y-%m-%d %H:00"),
+            (anomaly.hour + Duration::hours(1)).format("%H:00")
+        ))
+        .field("Messages", anomaly.observed.to_string(), true)
+        .field("Usual", format!("{:.0}", anomaly.expected), true)
+        .field("Deviation", format!("{:+.1}", anomaly.z), true)
+        .field("Negative", negative_share, true)
+        .timestamp(chrono::Utc::now().to_rfc3339())
+        .color(Color::new(color));
+
+    embed
+}
+
+pub fn impact_report_embed(report: &ImpactReport) -> CreateEmbed {
+    let score_change = match (report.before.score, report.after.score) {
+        (Some(before), Some(after)) => format!("{:+.2}", after - before),
+        _ => "-".to_string(),
+    };
+    let hourly = |stats: &WindowStats, hours: i64| stats.messages as f64 / hours.max(1) as f64;
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(format!(
+            "Announcement Impact: {}",
+            report.announcement.title
+        ))
+        .description(format!(
+            "Posted {} KST. {} hours before vs {} hours after. Sentiment score change: {}\n{}",
+            report.announcement.posted_at.format("%Y-%m-%d %H:%M"),
+            report.before_hours,
+            report.after_hours,
+            score_change,
+            report.announcement.link
+        ))
+        .field(
+            "Before",
+            window_summary(&report.before, hourly(&report.before, report.before_hours)),
+            true,
+        )
+        .field(
+            "After",
+            window_summary(&report.after, hourly(&report.after, report.after_hours)),
+            true,
+        )
+        .timestamp(chrono::Utc::now().to_rfc3339())
+        .color(Color::new(0x0000ff));
+
+    embed
+}
+
+fn window_summary(stats: &WindowStats, per_hour: f64) -> String {
+    let score = match stats.score {
+        Some(score) => format!("{:+.2}", score),
+        None => "-".to_string(),
+    };
+    let terms = |values: &[(String, i64)]| {
+        if values.is_empty() {
+            return "-".to_string();
+        }
+        values
+            .iter()
+            .map(|(value, count)| format!("{} ({})", value, count))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    format!(
+        "{} messages ({:.1}/hour)\nScore {} ({} positive, {} negative)\nTopics: {}\nKey phrases: {}",
+        stats.messages,
+        per_hour,
+        score,
+        stats.positive,
+        stats.negative,
+        terms(&stats.topics),
+        terms(&stats.key_phrases)
+    )
+}
+
 pub fn signal_alert_embed(signal: &str) -> CreateEmbed {
     let title = "Application Termination Alert";
     let description = format!("The application received a {} signal.", signal);
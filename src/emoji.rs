@@ -0,0 +1,195 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Polarity {
+    Positive,
+    Neutral,
+    Negative,
+}
+
+impl Polarity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Polarity::Positive => "positive",
+            Polarity::Neutral => "neutral",
+            Polarity::Negative => "negative",
+        }
+    }
+}
+
+const POSITIVE_EMOJI: &[&str] = &[
+    "👍", "❤", "♥", "💖", "💕", "💯", "😂", "🤣", "😄", "😃", "😀", "😁", "😆", "😊", "😍", "🥰",
+    "😎", "🤩", "🥳", "🎉", "🔥", "🙏", "👏", "🙌", "👌", "💪", "✅", "🚀", "✨", "😻",
+];
+
+const NEGATIVE_EMOJI: &[&str] = &[
+    "👎", "😡", "😠", "🤬", "😢", "😭", "😞", "😔", "😟", "😕", "🙁", "☹", "😤", "😒", "🙄", "😩",
+    "😫", "😖", "😱", "😨", "😰", "💔", "💩", "🤮", "🤢", "🖕", "❌", "⚠",
+];
+
+// Sentiment-bearing words substituted for common emojis before analysis, since Comprehend
+// handles the words far better than the emojis themselves
+const EMOJI_TOKENS: &[(&str, &str)] = &[
+    ("😂", "laughing"),
+    ("🤣", "laughing"),
+    ("😆", "laughing"),
+    ("😄", "happy"),
+    ("😃", "happy"),
+    ("😀", "happy"),
+    ("😁", "happy"),
+    ("😊", "happy"),
+    ("🥳", "celebrating"),
+    ("🎉", "celebrating"),
+    ("😍", "love"),
+    ("🥰", "love"),
+    ("❤", "love"),
+    ("♥", "love"),
+    ("💖", "love"),
+    ("💕", "love"),
+    ("👍", "good"),
+    ("👌", "good"),
+    ("💯", "perfect"),
+    ("🔥", "awesome"),
+    ("🚀", "awesome"),
+    ("🤩", "amazing"),
+    ("😎", "cool"),
+    ("🙏", "thanks"),
+    ("👏", "great"),
+    ("🙌", "great"),
+    ("💪", "strong"),
+    ("✅", "done"),
+    ("👎", "bad"),
+    ("😡", "angry"),
+    ("😠", "angry"),
+    ("🤬", "furious"),
+    ("😤", "frustrated"),
+    ("😢", "sad"),
+    ("😞", "sad"),
+    ("😔", "sad"),
+    ("🙁", "sad"),
+    ("☹", "sad"),
+    ("😭", "crying"),
+    ("💔", "heartbroken"),
+    ("😟", "worried"),
+    ("😰", "worried"),
+    ("😨", "scared"),
+    ("😱", "shocked"),
+    ("😕", "confused"),
+    ("🤔", "thinking"),
+    ("😒", "annoyed"),
+    ("🙄", "annoyed"),
+    ("😩", "tired"),
+    ("😫", "tired"),
+    ("🤮", "disgusted"),
+    ("🤢", "disgusted"),
+    ("💩", "terrible"),
+    ("❌", "no"),
+];
+
+// Text emoticons, matched as whole whitespace-separated tokens
+const EMOTICON_TOKENS: &[(&str, &str, Polarity)] = &[
+    (":)", "happy", Polarity::Positive),
+    (":-)", "happy", Polarity::Positive),
+    ("(:", "happy", Polarity::Positive),
+    (":]", "happy", Polarity::Positive),
+    ("=)", "happy", Polarity::Positive),
+    (":D", "laughing", Polarity::Positive),
+    (":-D", "laughing", Polarity::Positive),
+    ("xD", "laughing", Polarity::Positive),
+    ("XD", "laughing", Polarity::Positive),
+    (";)", "wink", Polarity::Positive),
+    (";-)", "wink", Polarity::Positive),
+    (":P", "playful", Polarity::Positive),
+    (":p", "playful", Polarity::Positive),
+    ("<3", "love", Polarity::Positive),
+    ("^^", "happy", Polarity::Positive),
+    ("^_^", "happy", Polarity::Positive),
+    (":(", "sad", Polarity::Negative),
+    (":-(", "sad", Polarity::Negative),
+    ("):", "sad", Polarity::Negative),
+    (":[", "sad", Polarity::Negative),
+    ("=(", "sad", Polarity::Negative),
+    (":'(", "crying", Polarity::Negative),
+    ("T_T", "crying", Polarity::Negative),
+    ("ㅠㅠ", "crying", Polarity::Negative),
+    (">:(", "angry", Polarity::Negative),
+    ("D:", "dismayed", Polarity::Negative),
+    (":/", "unsure", Polarity::Neutral),
+    (":-/", "unsure", Polarity::Neutral),
+    (":|", "indifferent", Polarity::Neutral),
+    ("-_-", "annoyed", Polarity::Negative),
+];
+
+// Emoji usage recorded on the stored message as features
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct EmojiFeatures {
+    pub unicode: i64,
+    pub custom: i64,
+    pub emoticons: i64,
+    pub positive: i64,
+    pub negative: i64,
+}
+
+impl EmojiFeatures {
+    pub fn add(&mut self, polarity: Polarity) {
+        match polarity {
+            Polarity::Positive => self.positive += 1,
+            Polarity::Negative => self.negative += 1,
+            Polarity::Neutral => {}
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unicode + self.custom + self.emoticons == 0
+    }
+}
+
+pub fn emoji_token(emoji: &str) -> Option<&'static str> {
+    let emoji = normalize_emoji(emoji);
+    EMOJI_TOKENS
+        .iter()
+        .find(|(candidate, _)| *candidate == emoji)
+        .map(|(_, token)| *token)
+}
+
+pub fn emoticon(token: &str) -> Option<(&'static str, Polarity)> {
+    EMOTICON_TOKENS
+        .iter()
+        .find(|(emoticon, _, _)| *emoticon == token)
+        .map(|(_, word, polarity)| (*word, *polarity))
+}
+
+// Pictographs, dingbats and the modifiers/joiners used to compose emoji sequences
+pub fn is_emoji_char(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0xFE0E | 0xFE0F | 0x200D
+    )
+}
+
+// Strips skin tone modifiers and variation selectors so that e.g. 👍🏽 and ❤️ match the lexicon
+pub fn normalize_emoji(emoji: &str) -> String {
+    emoji
+        .chars()
+        .filter(|c| !matches!(*c as u32, 0x1F3FB..=0x1F3FF | 0xFE0E | 0xFE0F))
+        .collect()
+}
+
+pub fn unicode_polarity(emoji: &str) -> Polarity {
+    let emoji = normalize_emoji(emoji);
+    if POSITIVE_EMOJI.contains(&emoji.as_str()) {
+        Polarity::Positive
+    } else if NEGATIVE_EMOJI.contains(&emoji.as_str()) {
+        Polarity::Negative
+    } else {
+        Polarity::Neutral
+    }
+}
+
+// Polarity of guild emojis by name, from the `reactions.custom_emoji` config. Unknown custom
+// emojis are neutral.
+pub fn custom_polarity(name: &str, custom_emoji: &HashMap<String, Polarity>) -> Polarity {
+    custom_emoji.get(name).copied().unwrap_or(Polarity::Neutral)
+}
@@ -0,0 +1,336 @@
+use crate::config::{EmotionConfig, EmotionProviderKind};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+
+pub const EMOTIONS: &[&str] = &["joy", "anger", "fear", "sadness", "surprise", "frustration"];
+
+// Share of the emotion-bearing words in a message per category, summing to 1
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+pub struct EmotionScores {
+    pub joy: f64,
+    pub anger: f64,
+    pub fear: f64,
+    pub sadness: f64,
+    pub surprise: f64,
+    pub frustration: f64,
+}
+
+impl EmotionScores {
+    pub fn get(&self, emotion: &str) -> f64 {
+        match emotion {
+            "joy" => self.joy,
+            "anger" => self.anger,
+            "fear" => self.fear,
+            "sadness" => self.sadness,
+            "surprise" => self.surprise,
+            "frustration" => self.frustration,
+            _ => 0.0,
+        }
+    }
+
+    fn add(&mut self, emotion: &str, amount: f64) {
+        match emotion {
+            "joy" => self.joy += amount,
+            "anger" => self.anger += amount,
+            "fear" => self.fear += amount,
+            "sadness" => self.sadness += amount,
+            "surprise" => self.surprise += amount,
+            "frustration" => self.frustration += amount,
+            _ => {}
+        }
+    }
+
+    fn scale(self, factor: f64) -> EmotionScores {
+        EmotionScores {
+            joy: self.joy * factor,
+            anger: self.anger * factor,
+            fear: self.fear * factor,
+            sadness: self.sadness * factor,
+            surprise: self.surprise * factor,
+            frustration: self.frustration * factor,
+        }
+    }
+
+    // The highest-scoring emotion; ties go to the one listed first in `EMOTIONS`
+    pub fn dominant(&self) -> &'static str {
+        EMOTIONS.iter().copied().fold(EMOTIONS[0], |best, emotion| {
+            if self.get(emotion) > self.get(best) {
+                emotion
+            } else {
+                best
+            }
+        })
+    }
+}
+
+#[async_trait]
+pub trait EmotionClassifier: Send + Sync {
+    // Returns None when the text carries no recognizable emotion
+    async fn classify(
+        &self,
+        text: &str,
+    ) -> Result<Option<EmotionScores>, Box<dyn Error + Send + Sync>>;
+}
+
+pub fn load_emotion_classifier(config: &EmotionConfig) -> Option<Box<dyn EmotionClassifier>> {
+    match config.provider {
+        EmotionProviderKind::Offline => Some(Box::new(EmotionLexicon::new(&config.extra_terms))),
+        EmotionProviderKind::Disabled => None,
+    }
+}
+
+// Words that cancel the emotion of the word that follows ("not happy")
+const NEGATIONS: &[&str] = &[
+    "not", "no", "never", "dont", "don't", "isnt", "isn't", "wasnt", "wasn't", "aint", "ain't",
+    "cant", "can't", "wont", "won't", "without", "hardly",
+];
+
+// A small NRC-style word -> emotion lexicon, including the words emojis and emoticons are
+// normalized to
+const DEFAULT_LEXICON: &[(&str, &[&str])] = &[
+    (
+        "joy",
+        &[
+            "happy",
+            "glad",
+            "love",
+            "loving",
+            "great",
+            "awesome",
+            "amazing",
+            "excellent",
+            "fantastic",
+            "wonderful",
+            "excited",
+            "exciting",
+            "enjoy",
+            "fun",
+            "thanks",
+            "thank",
+            "grateful",
+            "perfect",
+            "nice",
+            "cool",
+            "laughing",
+            "celebrating",
+            "good",
+            "best",
+            "yay",
+            "lol",
+            "lmao",
+            "bullish",
+            "moon",
+            "win",
+            "won",
+            "delighted",
+        ],
+    ),
+    (
+        "anger",
+        &[
+            "angry",
+            "furious",
+            "mad",
+            "hate",
+            "hated",
+            "rage",
+            "outraged",
+            "pissed",
+            "disgusted",
+            "disgusting",
+            "unacceptable",
+            "ridiculous",
+            "scam",
+            "scammed",
+            "liar",
+            "lied",
+            "terrible",
+            "worst",
+            "awful",
+            "rugged",
+        ],
+    ),
+    (
+        "fear",
+        &[
+            "afraid",
+            "scared",
+            "fear",
+            "worried",
+            "worry",
+            "nervous",
+            "anxious",
+            "panic",
+            "panicking",
+            "risk",
+            "risky",
+            "danger",
+            "dangerous",
+            "unsafe",
+            "hacked",
+            "stolen",
+            "lost",
+            "terrified",
+            "concerned",
+        ],
+    ),
+    (
+        "sadness",
+        &[
+            "sad",
+            "unhappy",
+            "crying",
+            "cry",
+            "depressed",
+            "disappointed",
+            "disappointing",
+            "heartbroken",
+            "miss",
+            "sorry",
+            "regret",
+            "unfortunately",
+            "lonely",
+            "hopeless",
+            "rip",
+        ],
+    ),
+    (
+        "surprise",
+        &[
+            "surprised",
+            "surprise",
+            "shocked",
+            "shocking",
+            "unexpected",
+            "suddenly",
+            "wow",
+            "omg",
+            "wtf",
+            "unbelievable",
+            "whoa",
+        ],
+    ),
+    (
+        "frustration",
+        &[
+            "frustrated",
+            "frustrating",
+            "annoyed",
+            "annoying",
+            "stuck",
+            "again",
+            "useless",
+            "broken",
+            "slow",
+            "waiting",
+            "ignored",
+            "confused",
+            "confusing",
+            "tired",
+            "fail",
+            "failed",
+            "failing",
+            "error",
+            "bug",
+            "issue",
+            "problem",
+        ],
+    ),
+];
+
+pub struct EmotionLexicon {
+    words: HashMap<String, Vec<&'static str>>,
+}
+
+impl EmotionLexicon {
+    pub fn new(extra_terms: &HashMap<String, Vec<String>>) -> EmotionLexicon {
+        let mut words: HashMap<String, Vec<&'static str>> = HashMap::new();
+        for (emotion, terms) in DEFAULT_LEXICON {
+            for term in terms.iter() {
+                words.entry(term.to_string()).or_default().push(emotion);
+            }
+        }
+        for (emotion, terms) in extra_terms {
+            let Some(emotion) = EMOTIONS.iter().find(|candidate| *candidate == emotion) else {
+                panic!("Unknown emotion in emotions.extra_terms: {}", emotion);
+            };
+            for term in terms {
+                words.entry(term.to_lowercase()).or_default().push(emotion);
+            }
+        }
+
+        EmotionLexicon { words }
+    }
+
+    fn lookup(&self, word: &str) -> Option<&Vec<&'static str>> {
+        if let Some(emotions) = self.words.get(word) {
+            return Some(emotions);
+        }
+        // Light stemming so "hates" and "worrying" find "hate" and "worry"
+        ["ing", "ed", "es", "s", "ly"].iter().find_map(|suffix| {
+            word.strip_suffix(suffix)
+                .filter(|stem| stem.len() >= 3)
+                .and_then(|stem| self.words.get(stem))
+        })
+    }
+
+    pub fn scores(&self, text: &str) -> Option<EmotionScores> {
+        let words: Vec<String> = text
+            .split_whitespace()
+            .map(|word| {
+                word.trim_matches(|c: char| !c.is_alphanumeric() && c != '\'')
+                    .to_lowercase()
+            })
+            .filter(|word| !word.is_empty())
+            .collect();
+
+        let mut scores = EmotionScores::default();
+        let mut total = 0.0;
+        for (index, word) in words.iter().enumerate() {
+            if index > 0 && NEGATIONS.contains(&words[index - 1].as_str()) {
+                continue;
+            }
+            let Some(emotions) = self.lookup(word) else {
+                continue;
+            };
+            // Words with several emotions split their weight between them
+            let weight = 1.0 / emotions.len() as f64;
+            for emotion in emotions {
+                scores.add(emotion, weight);
+            }
+            total += 1.0;
+        }
+
+        if total == 0.0 {
+            return None;
+        }
+        Some(scores.scale(1.0 / total))
+    }
+
+    // Positive when joy outweighs the negative emotions, negative when they outweigh joy
+    pub fn polarity(&self, text: &str) -> &'static str {
+        let Some(scores) = self.scores(text) else {
+            return "neutral";
+        };
+        let negative = scores.anger + scores.fear + scores.sadness + scores.frustration;
+        if scores.joy > negative {
+            "positive"
+        } else if negative > scores.joy {
+            "negative"
+        } else {
+            "neutral"
+        }
+    }
+}
+
+#[async_trait]
+impl EmotionClassifier for EmotionLexicon {
+    async fn classify(
+        &self,
+        text: &str,
+    ) -> Result<Option<EmotionScores>, Box<dyn Error + Send + Sync>> {
+        Ok(self.scores(text))
+    }
+}
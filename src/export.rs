@@ -0,0 +1,521 @@
+use crate::crypto::Keyring;
+use crate::mongo::{read_messages, Message};
+use crate::toxicity::ToxicityCategory;
+use chrono::{Duration, NaiveDate, TimeZone, Utc};
+use mongodb::bson::{doc, DateTime, Document};
+use mongodb::Database;
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+
+// Every exportable column, in the order they are written. New columns are only ever
+// appended so that notebooks reading older exports keep working.
+pub const ALL_FIELDS: &[&str] = &[
+    "id",
+    "createdAt",
+    "channel",
+    "authorId",
+    "username",
+    "text",
+    "korean",
+    "sentiment",
+    "redactions",
+    "threadId",
+    "threadName",
+    "messageId",
+    "replyTo",
+    "sessionId",
+    "role",
+    "reactionScore",
+    "emojiPositive",
+    "emojiNegative",
+    "attachments",
+    "embedTitles",
+    "issueReport",
+    "domains",
+    "spam",
+    "toxicityInsult",
+    "toxicityThreat",
+    "toxicityProfanity",
+    "toxicityHarassment",
+    "emotion",
+    "keyPhrases",
+    "entities",
+    "topics",
+    "aspects",
+    "clusterId",
+];
+
+// Rows are buffered and flushed as one Parquet row group at a time
+const PARQUET_ROW_GROUP_SIZE: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    Parquet,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" | "ndjson" => Ok(ExportFormat::Json),
+            "parquet" => Ok(ExportFormat::Parquet),
+            _ => Err(format!("Unknown export format: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    pub output: String,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub channel: Option<String>,
+    pub sentiment: Option<String>,
+    pub fields: Vec<String>,
+    pub redact_text: bool,
+    pub include_spam: bool,
+}
+
+impl ExportOptions {
+    // Builds the MongoDB filter for the date range (inclusive) and channel/sentiment
+    pub fn filter(&self) -> Document {
+        let mut filter = doc! {};
+
+        let mut created_at = doc! {};
+        if let Some(from) = self.from {
+            created_at.insert("$gte", start_of_day(from));
+        }
+        if let Some(to) = self.to {
+            created_at.insert("$lt", start_of_day(to + Duration::days(1)));
+        }
+        if !created_at.is_empty() {
+            filter.insert("createdAt", created_at);
+        }
+        if let Some(channel) = &self.channel {
+            filter.insert("channel", channel);
+        }
+        if let Some(sentiment) = &self.sentiment {
+            filter.insert("sentiment", sentiment);
+        }
+        if !self.include_spam {
+            filter.insert("spam", doc! { "$exists": false });
+        }
+
+        filter
+    }
+
+    // Returns the selected fields in schema order, or an error for unknown fields
+    pub fn columns(&self) -> Result<Vec<&'static str>, String> {
+        if let Some(unknown) = self
+            .fields
+            .iter()
+            .find(|field| !ALL_FIELDS.contains(&field.as_str()))
+        {
+            return Err(format!("Unknown export field: {}", unknown));
+        }
+
+        Ok(ALL_FIELDS
+            .iter()
+            .copied()
+            .filter(|field| self.fields.is_empty() || self.fields.iter().any(|f| f == field))
+            .collect())
+    }
+}
+
+pub fn start_of_day(date: NaiveDate) -> DateTime {
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+    DateTime::from_chrono(Utc.from_utc_datetime(&midnight))
+}
+
+fn field_value(message: &Message, field: &str) -> Option<String> {
+    match field {
+        "id" => message.id.map(|id| id.to_hex()),
+        "createdAt" => Some(message.created_at.to_rfc3339()),
+        "channel" => Some(message.channel.clone()),
+        "authorId" => Some(message.author_id.clone()),
+        "username" => message.username.clone(),
+        "text" => Some(message.text.clone()),
+        "korean" => message.korean.clone(),
+        "sentiment" => message.analyzed.clone(),
+        "redactions" => Some(message.redactions.join(";")),
+        "threadId" => message.thread.as_ref().map(|thread| thread.id.clone()),
+        "threadName" => message.thread.as_ref().map(|thread| thread.name.clone()),
+        "messageId" => message.message_id.clone(),
+        "replyTo" => message.reply_to.clone(),
+        "sessionId" => message.session_id.map(|id| id.to_hex()),
+        "role" => message.role.clone(),
+        "reactionScore" => message
+            .reaction_sentiment
+            .and_then(|reactions| reactions.score())
+            .map(|score| format!("{:.3}", score)),
+        "emojiPositive" => message.emoji.map(|emoji| emoji.positive.to_string()),
+        "emojiNegative" => message.emoji.map(|emoji| emoji.negative.to_string()),
+        "attachments" => Some(
+            message
+                .attachments
+                .iter()
+                .map(|attachment| attachment.filename.as_str())
+                .collect::<Vec<_>>()
+                .join(";"),
+        ),
+        "embedTitles" => Some(
+            message
+                .embeds
+                .iter()
+                .filter_map(|embed| embed.title.as_deref())
+                .collect::<Vec<_>>()
+                .join(";"),
+        ),
+        "issueReport" => Some(message.issue_report.to_string()),
+        "domains" => Some(message.domains.join(";")),
+        "spam" => message.spam.clone(),
+        "toxicityInsult" => toxicity_score(message, ToxicityCategory::Insult),
+        "toxicityThreat" => toxicity_score(message, ToxicityCategory::Threat),
+        "toxicityProfanity" => toxicity_score(message, ToxicityCategory::Profanity),
+        "toxicityHarassment" => toxicity_score(message, ToxicityCategory::Harassment),
+        "emotion" => message
+            .emotions
+            .map(|emotions| emotions.dominant().to_string()),
+        "keyPhrases" => Some(message.key_phrases.join(";")),
+        "entities" => Some(
+            message
+                .entities
+                .iter()
+                .map(|entity| format!("{}:{}", entity.kind, entity.text))
+                .collect::<Vec<_>>()
+                .join(";"),
+        ),
+        "topics" => Some(message.topics.join(";")),
+        "aspects" => Some(
+            message
+                .aspects
+                .iter()
+                .map(|aspect| format!("{}:{}", aspect.aspect, aspect.sentiment))
+                .collect::<Vec<_>>()
+                .join(";"),
+        ),
+        "clusterId" => message.cluster_id.map(|id| id.to_hex()),
+        _ => None,
+    }
+}
+
+fn toxicity_score(message: &Message, category: ToxicityCategory) -> Option<String> {
+    message
+        .toxicity
+        .map(|toxicity| format!("{:.3}", toxicity.get(category)))
+}
+
+// Stored text is already redacted, so this leaves the text out altogether, along with the
+// phrases, entities and aspects quoted from it and the attachment and embed text
+fn redact_free_text(message: &mut Message) {
+    message.text = String::new();
+    message.korean = None;
+    message.key_phrases.clear();
+    message.entities.clear();
+    message.aspects.clear();
+    message.attachments.clear();
+    message.embeds.clear();
+}
+
+// Streams the matching messages to `options.output` and returns the number exported
+pub async fn export_messages(
+    db: &Database,
+    keyring: Option<&Keyring>,
+    options: &ExportOptions,
+) -> Result<u64, Box<dyn Error>> {
+    let columns = options.columns()?;
+    let mut reader = read_messages(db, options.filter(), keyring).await?;
+    let file = File::create(&options.output)?;
+    let mut sink = RowSink::new(options.format, file, &columns)?;
+
+    let mut exported = 0;
+    while let Some(mut message) = reader.next().await? {
+        if options.redact_text {
+            redact_free_text(&mut message);
+        }
+
+        let row: Vec<Option<String>> = columns
+            .iter()
+            .map(|column| field_value(&message, column))
+            .collect();
+        sink.write_row(row)?;
+        exported += 1;
+    }
+
+    sink.finish()?;
+    Ok(exported)
+}
+
+enum RowSink {
+    Csv(csv::Writer<File>),
+    Json {
+        writer: BufWriter<File>,
+        columns: Vec<&'static str>,
+    },
+    Parquet {
+        writer: SerializedFileWriter<File>,
+        rows: Vec<Vec<Option<String>>>,
+        columns: usize,
+    },
+}
+
+impl RowSink {
+    fn new(
+        format: ExportFormat,
+        file: File,
+        columns: &[&'static str],
+    ) -> Result<RowSink, Box<dyn Error>> {
+        match format {
+            ExportFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(file);
+                writer.write_record(columns)?;
+                Ok(RowSink::Csv(writer))
+            }
+            ExportFormat::Json => Ok(RowSink::Json {
+                writer: BufWriter::new(file),
+                columns: columns.to_vec(),
+            }),
+            ExportFormat::Parquet => {
+                // Every column is an optional UTF-8 string so the schema never depends on data
+                let fields: Vec<String> = columns
+                    .iter()
+                    .map(|column| format!("OPTIONAL BYTE_ARRAY {} (UTF8);", column))
+                    .collect();
+                let schema =
+                    parse_message_type(&format!("message messages {{ {} }}", fields.join(" ")))?;
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                let writer =
+                    SerializedFileWriter::new(file, Arc::new(schema), Arc::new(properties))?;
+
+                Ok(RowSink::Parquet {
+                    writer,
+                    rows: Vec::with_capacity(PARQUET_ROW_GROUP_SIZE),
+                    columns: columns.len(),
+                })
+            }
+        }
+    }
+
+    fn write_row(&mut self, row: Vec<Option<String>>) -> Result<(), Box<dyn Error>> {
+        match self {
+            RowSink::Csv(writer) => {
+                writer
+                    .write_record(row.iter().map(|value| value.as_deref().unwrap_or_default()))?;
+            }
+            RowSink::Json { writer, columns } => {
+                let object: serde_json::Map<String, serde_json::Value> = columns
+                    .iter()
+                    .zip(row)
+                    .map(|(column, value)| (column.to_string(), value.into()))
+                    .collect();
+                serde_json::to_writer(&mut *writer, &object)?;
+                writer.write_all(b"\n")?;
+            }
+            RowSink::Parquet {
+                writer,
+                rows,
+                columns,
+            } => {
+                rows.push(row);
+                if rows.len() >= PARQUET_ROW_GROUP_SIZE {
+                    write_row_group(writer, rows, *columns)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self {
+            RowSink::Csv(mut writer) => writer.flush()?,
+            RowSink::Json { mut writer, .. } => writer.flush()?,
+            RowSink::Parquet {
+                mut writer,
+                mut rows,
+                columns,
+            } => {
+                if !rows.is_empty() {
+                    write_row_group(&mut writer, &mut rows, columns)?;
+                }
+                writer.close()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn write_row_group(
+    writer: &mut SerializedFileWriter<File>,
+    rows: &mut Vec<Vec<Option<String>>>,
+    columns: usize,
+) -> Result<(), Box<dyn Error>> {
+    let mut row_group = writer.next_row_group()?;
+
+    for index in 0..columns {
+        let Some(mut column) = row_group.next_column()? else {
+            break;
+        };
+
+        // Definition level 1 marks a present value, 0 a null
+        let mut values = Vec::new();
+        let mut definition_levels = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            match &row[index] {
+                Some(value) => {
+                    values.push(ByteArray::from(value.as_str()));
+                    definition_levels.push(1);
+                }
+                None => definition_levels.push(0),
+            }
+        }
+
+        column
+            .typed::<ByteArrayType>()
+            .write_batch(&values, Some(&definition_levels), None)?;
+        column.close()?;
+    }
+
+    row_group.close()?;
+    rows.clear();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aspects::AspectSentiment;
+    use crate::media::{AttachmentInfo, EmbedInfo};
+    use crate::phrases::EntityInfo;
+
+    fn default_options() -> ExportOptions {
+        ExportOptions {
+            format: ExportFormat::Csv,
+            output: "export.csv".to_string(),
+            from: None,
+            to: None,
+            channel: None,
+            sentiment: None,
+            fields: Vec::new(),
+            redact_text: false,
+            include_spam: false,
+        }
+    }
+
+    #[test]
+    fn filter_covers_whole_days_and_excludes_spam() {
+        let options = ExportOptions {
+            from: NaiveDate::from_ymd_opt(2024, 3, 1),
+            to: NaiveDate::from_ymd_opt(2024, 3, 7),
+            channel: Some("general".to_string()),
+            ..default_options()
+        };
+
+        let expected = doc! {
+            "createdAt": {
+                "$gte": start_of_day(NaiveDate::from_ymd_opt(2024, 3, 1).unwrap()),
+                "$lt": start_of_day(NaiveDate::from_ymd_opt(2024, 3, 8).unwrap()),
+            },
+            "channel": "general",
+            "spam": { "$exists": false },
+        };
+        assert_eq!(options.filter(), expected);
+
+        let options = ExportOptions {
+            include_spam: true,
+            ..default_options()
+        };
+        assert_eq!(options.filter(), doc! {});
+    }
+
+    #[test]
+    fn columns_follow_the_schema_order() {
+        assert_eq!(default_options().columns().unwrap(), ALL_FIELDS);
+
+        let options = ExportOptions {
+            fields: vec!["sentiment".to_string(), "createdAt".to_string()],
+            ..default_options()
+        };
+        assert_eq!(options.columns().unwrap(), vec!["createdAt", "sentiment"]);
+
+        let options = ExportOptions {
+            fields: vec!["password".to_string()],
+            ..options
+        };
+        assert_eq!(
+            options.columns(),
+            Err("Unknown export field: password".to_string())
+        );
+    }
+
+    #[test]
+    fn redacted_rows_leave_out_every_free_text_field() {
+        let mut message = Message {
+            channel: "general".to_string(),
+            text: "my seed phrase leaked from wallet.dat".to_string(),
+            korean: Some("지갑에서 시드 문구가 유출됐어요".to_string()),
+            analyzed: Some("NEGATIVE".to_string()),
+            key_phrases: vec!["seed phrase".to_string()],
+            entities: vec![EntityInfo {
+                text: "wallet.dat".to_string(),
+                kind: "OTHER".to_string(),
+            }],
+            aspects: vec![AspectSentiment {
+                aspect: "seed phrase".to_string(),
+                topic: None,
+                sentiment: "NEGATIVE".to_string(),
+            }],
+            attachments: vec![AttachmentInfo {
+                filename: "my-seed.png".to_string(),
+                ..Default::default()
+            }],
+            embeds: vec![EmbedInfo {
+                title: Some("Recovery guide".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        redact_free_text(&mut message);
+
+        for field in [
+            "text",
+            "keyPhrases",
+            "entities",
+            "aspects",
+            "attachments",
+            "embedTitles",
+        ] {
+            assert_eq!(
+                field_value(&message, field),
+                Some(String::new()),
+                "{}",
+                field
+            );
+        }
+        assert_eq!(field_value(&message, "korean"), None);
+        assert_eq!(
+            field_value(&message, "channel"),
+            Some("general".to_string())
+        );
+        assert_eq!(
+            field_value(&message, "sentiment"),
+            Some("NEGATIVE".to_string())
+        );
+    }
+}
@@ -0,0 +1,334 @@
+use crate::config::HealthConfig;
+use crate::mongo::get_number;
+use crate::util::median;
+use chrono::{Duration, NaiveTime, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, Document};
+use mongodb::error::Error;
+use mongodb::options::{FindOptions, UpdateOptions};
+use mongodb::Database;
+use std::collections::{BTreeMap, HashMap};
+
+const HEALTH_COLLECTION: &str = "channel_health";
+
+// Weight of each component in the composite score. Components without data for the day
+// (no analyzed messages, no support sessions, no volume history) are left out.
+const SENTIMENT_WEIGHT: f64 = 0.3;
+const TOXICITY_WEIGHT: f64 = 0.2;
+const SPAM_WEIGHT: f64 = 0.15;
+const VOLUME_WEIGHT: f64 = 0.15;
+const RESPONSE_WEIGHT: f64 = 0.2;
+
+// Rates at which the toxicity and spam components reach zero
+const MAX_TOXIC_RATE: f64 = 0.1;
+const MAX_SPAM_RATE: f64 = 0.2;
+
+// First response time at which the response component is halved
+const TARGET_RESPONSE_SECONDS: f64 = 1800.0;
+
+// Days of history the volume baseline is the median of, and the minimum needed
+const VOLUME_BASELINE_DAYS: i64 = 14;
+const MIN_VOLUME_BASELINE_DAYS: usize = 3;
+
+// One channel's health for one day. The components range from 0 (unhealthy) to 1, the
+// score from 0 to 100.
+#[derive(Debug, Clone, Default)]
+pub struct ChannelHealth {
+    pub date: chrono::DateTime<Utc>,
+    pub score: f64,
+    pub sentiment: Option<f64>,
+    pub toxicity: Option<f64>,
+    pub spam: Option<f64>,
+    pub volume: Option<f64>,
+    pub response: Option<f64>,
+    pub messages: i64,
+}
+
+impl ChannelHealth {
+    fn from_document(document: &Document) -> ChannelHealth {
+        ChannelHealth {
+            date: document
+                .get_datetime("date")
+                .map(|date| date.to_chrono())
+                .unwrap_or_default(),
+            score: get_number(document, "score").unwrap_or_default(),
+            sentiment: get_number(document, "sentiment"),
+            toxicity: get_number(document, "toxicity"),
+            spam: get_number(document, "spam"),
+            volume: get_number(document, "volume"),
+            response: get_number(document, "response"),
+            messages: get_number(document, "messages").unwrap_or_default() as i64,
+        }
+    }
+}
+
+// Average health of a channel over several days
+#[derive(Debug, Clone, Default)]
+pub struct ChannelHealthSummary {
+    pub channel: String,
+    pub score: f64,
+    pub days: i64,
+    pub messages: i64,
+}
+
+#[derive(Default)]
+struct DayCounts {
+    messages: f64,
+    spam: f64,
+    analyzed: f64,
+    positive: f64,
+    negative: f64,
+    toxic: f64,
+    sessions: f64,
+    answered: f64,
+    avg_first_response_seconds: Option<f64>,
+}
+
+// Computes and stores the health of every channel for the local day starting at `date`
+// (midnight, in the UTC+9 timestamps messages are stored with). Returns the number of
+// channels scored.
+pub async fn compute_channel_health(
+    db: &Database,
+    config: &HealthConfig,
+    date: chrono::DateTime<Utc>,
+) -> Result<usize, Error> {
+    let start = bson::DateTime::from_chrono(date);
+    let end = bson::DateTime::from_chrono(date + Duration::days(1));
+    let mut days: BTreeMap<String, DayCounts> = BTreeMap::new();
+
+    let toxicity = doc! {
+        "$max": [
+            "$toxicity.insult",
+            "$toxicity.threat",
+            "$toxicity.profanity",
+            "$toxicity.harassment",
+        ]
+    };
+    let pipeline = vec![
+        doc! {
+            "$match": {
+                "createdAt": { "$gte": start, "$lt": end },
+                "role": { "$ne": "staff" },
+            }
+        },
+        doc! {
+            "$group": {
+                "_id": "$channel",
+                "messages": { "$sum": { "$cond": [{ "$gt": ["$spam", null] }, 0, 1] } },
+                "spam": { "$sum": { "$cond": [{ "$gt": ["$spam", null] }, 1, 0] } },
+                "analyzed": { "$sum": { "$cond": [{ "$gt": ["$sentiment", null] }, 1, 0] } },
+                "positive": { "$sum": { "$cond": [{ "$eq": ["$sentiment", "positive"] }, 1, 0] } },
+                "negative": { "$sum": { "$cond": [{ "$eq": ["$sentiment", "negative"] }, 1, 0] } },
+                "toxic": {
+                    "$sum": {
+                        "$cond": [{ "$gte": [toxicity, config.toxicity_threshold] }, 1, 0]
+                    }
+                },
+            }
+        },
+    ];
+    let mut cursor = db
+        .collection::<Document>("messages")
+        .aggregate(pipeline, None)
+        .await?;
+    while let Some(document) = cursor.try_next().await? {
+        let Ok(channel) = document.get_str("_id") else {
+            continue;
+        };
+        let day = days.entry(channel.to_string()).or_default();
+        day.messages = get_number(&document, "messages").unwrap_or_default();
+        day.spam = get_number(&document, "spam").unwrap_or_default();
+        day.analyzed = get_number(&document, "analyzed").unwrap_or_default();
+        day.positive = get_number(&document, "positive").unwrap_or_default();
+        day.negative = get_number(&document, "negative").unwrap_or_default();
+        day.toxic = get_number(&document, "toxic").unwrap_or_default();
+    }
+
+    let pipeline = vec![
+        doc! {
+            "$match": {
+                "startedAt": { "$gte": start, "$lt": end },
+                "startedByStaff": false,
+            }
+        },
+        doc! {
+            "$group": {
+                "_id": "$channel",
+                "sessions": { "$sum": 1 },
+                "answered": {
+                    "$sum": { "$cond": [{ "$gt": ["$firstResponseSeconds", null] }, 1, 0] }
+                },
+                "avgFirstResponseSeconds": { "$avg": "$firstResponseSeconds" },
+            }
+        },
+    ];
+    let mut cursor = db
+        .collection::<Document>("sessions")
+        .aggregate(pipeline, None)
+        .await?;
+    while let Some(document) = cursor.try_next().await? {
+        let Ok(channel) = document.get_str("_id") else {
+            continue;
+        };
+        let day = days.entry(channel.to_string()).or_default();
+        day.sessions = get_number(&document, "sessions").unwrap_or_default();
+        day.answered = get_number(&document, "answered").unwrap_or_default();
+        day.avg_first_response_seconds = get_number(&document, "avgFirstResponseSeconds");
+    }
+
+    // Daily message counts of the previous days, for the volume baseline
+    let collection = db.collection::<Document>(HEALTH_COLLECTION);
+    let history_start = bson::DateTime::from_chrono(date - Duration::days(VOLUME_BASELINE_DAYS));
+    let mut history: HashMap<String, Vec<f64>> = HashMap::new();
+    let mut cursor = collection
+        .find(
+            doc! { "date": { "$gte": history_start, "$lt": start } },
+            None,
+        )
+        .await?;
+    while let Some(document) = cursor.try_next().await? {
+        if let (Ok(channel), Some(messages)) = (
+            document.get_str("channel"),
+            get_number(&document, "messages"),
+        ) {
+            history
+                .entry(channel.to_string())
+                .or_default()
+                .push(messages);
+        }
+    }
+
+    let options = UpdateOptions::builder().upsert(true).build();
+    let mut scored = 0;
+    for (channel, day) in days {
+        let sentiment = (day.analyzed > 0.0)
+            .then(|| ((day.positive - day.negative) / day.analyzed + 1.0) / 2.0);
+        let toxicity = (day.messages > 0.0)
+            .then(|| 1.0 - (day.toxic / day.messages / MAX_TOXIC_RATE).min(1.0));
+        let total = day.messages + day.spam;
+        let spam = (total > 0.0).then(|| 1.0 - (day.spam / total / MAX_SPAM_RATE).min(1.0));
+        let baseline = history
+            .get(&channel)
+            .filter(|counts| counts.len() >= MIN_VOLUME_BASELINE_DAYS)
+            .map(|counts| median(counts));
+        // Quieter than usual lowers the score; busier days are capped at healthy
+        let volume = baseline
+            .filter(|baseline| *baseline > 0.0)
+            .map(|baseline| (day.messages / baseline).min(1.0));
+        let response = (day.sessions > 0.0).then(|| {
+            let speed = day
+                .avg_first_response_seconds
+                .map(|seconds| TARGET_RESPONSE_SECONDS / (TARGET_RESPONSE_SECONDS + seconds))
+                .unwrap_or_default();
+            day.answered / day.sessions * speed
+        });
+
+        let components = [
+            (sentiment, SENTIMENT_WEIGHT),
+            (toxicity, TOXICITY_WEIGHT),
+            (spam, SPAM_WEIGHT),
+            (volume, VOLUME_WEIGHT),
+            (response, RESPONSE_WEIGHT),
+        ];
+        let weight: f64 = components
+            .iter()
+            .filter(|(value, _)| value.is_some())
+            .map(|(_, weight)| weight)
+            .sum();
+        if weight == 0.0 {
+            continue;
+        }
+        let score = components
+            .iter()
+            .filter_map(|(value, weight)| value.map(|value| value * weight))
+            .sum::<f64>()
+            / weight
+            * 100.0;
+
+        collection
+            .update_one(
+                doc! { "channel": &channel, "date": start },
+                doc! {
+                    "$set": {
+                        "score": score,
+                        "sentiment": sentiment,
+                        "toxicity": toxicity,
+                        "spam": spam,
+                        "volume": volume,
+                        "response": response,
+                        "messages": day.messages as i64,
+                        "spamMessages": day.spam as i64,
+                        "toxicMessages": day.toxic as i64,
+                        "baselineMessages": baseline,
+                        "sessions": day.sessions as i64,
+                        "avgFirstResponseSeconds": day.avg_first_response_seconds,
+                        "updatedAt": bson::DateTime::now(),
+                    }
+                },
+                options.clone(),
+            )
+            .await?;
+
+        scored += 1;
+    }
+
+    Ok(scored)
+}
+
+// Midnight of the day `now` falls on, for the UTC+9 timestamps messages are stored with
+pub fn local_midnight(now: chrono::DateTime<Utc>) -> chrono::DateTime<Utc> {
+    now.date_naive().and_time(NaiveTime::MIN).and_utc()
+}
+
+// The channel's daily health since `since`, oldest first
+pub async fn channel_health_history(
+    db: &Database,
+    channel: &str,
+    since: chrono::DateTime<Utc>,
+) -> Result<Vec<ChannelHealth>, Error> {
+    let collection = db.collection::<Document>(HEALTH_COLLECTION);
+    let options = FindOptions::builder().sort(doc! { "date": 1 }).build();
+    let days: Vec<Document> = collection
+        .find(
+            doc! { "channel": channel, "date": { "$gte": bson::DateTime::from_chrono(since) } },
+            options,
+        )
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(days.iter().map(ChannelHealth::from_document).collect())
+}
+
+// Average daily health per channel since `since`, least healthy first
+pub async fn channel_health_ranking(
+    db: &Database,
+    since: chrono::DateTime<Utc>,
+) -> Result<Vec<ChannelHealthSummary>, Error> {
+    let collection = db.collection::<Document>(HEALTH_COLLECTION);
+    let pipeline = vec![
+        doc! { "$match": { "date": { "$gte": bson::DateTime::from_chrono(since) } } },
+        doc! {
+            "$group": {
+                "_id": "$channel",
+                "score": { "$avg": "$score" },
+                "days": { "$sum": 1 },
+                "messages": { "$sum": "$messages" },
+            }
+        },
+        doc! { "$sort": { "score": 1 } },
+    ];
+
+    let mut cursor = collection.aggregate(pipeline, None).await?;
+    let mut ranking = Vec::new();
+    while let Some(document) = cursor.try_next().await? {
+        ranking.push(ChannelHealthSummary {
+            channel: document.get_str("_id").unwrap_or_default().to_string(),
+            score: get_number(&document, "score").unwrap_or_default(),
+            days: get_number(&document, "days").unwrap_or_default() as i64,
+            messages: get_number(&document, "messages").unwrap_or_default() as i64,
+        });
+    }
+
+    Ok(ranking)
+}
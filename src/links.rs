@@ -0,0 +1,97 @@
+use crate::config::LinkConfig;
+use regex::Regex;
+use std::collections::HashSet;
+use std::fs;
+
+// The message content with every URL replaced by a `[LINK:domain]` placeholder
+pub struct ProcessedLinks {
+    pub text: String,
+    // Linked domains in order of appearance, without duplicates
+    pub domains: Vec<String>,
+    // True when the message had links and nothing else
+    pub link_only: bool,
+}
+
+pub struct LinkProcessor {
+    url_pattern: Regex,
+    blocklist: HashSet<String>,
+}
+
+impl LinkProcessor {
+    pub fn new(config: &LinkConfig) -> LinkProcessor {
+        let mut blocklist: HashSet<String> = config
+            .blocklist
+            .iter()
+            .map(|domain| normalize_domain(domain))
+            .collect();
+
+        // One domain per line; blank lines and `#` comments are ignored
+        if let Some(path) = &config.blocklist_file {
+            let contents = fs::read_to_string(path).expect("Failed to read link blocklist file");
+            blocklist.extend(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(normalize_domain),
+            );
+        }
+
+        LinkProcessor {
+            // Also matches links wrapped in <> to suppress the embed
+            url_pattern: Regex::new(r"<?https?://([^\s/?#<>]+)[^\s<>]*>?").unwrap(),
+            blocklist,
+        }
+    }
+
+    pub fn process(&self, content: &str) -> ProcessedLinks {
+        let mut domains = Vec::new();
+        let text = self
+            .url_pattern
+            .replace_all(content, |caps: &regex::Captures| {
+                let domain = normalize_domain(&caps[1]);
+                let placeholder = format!("[LINK:{}]", domain);
+                if !domains.contains(&domain) {
+                    domains.push(domain);
+                }
+                placeholder
+            })
+            .into_owned();
+
+        let link_only =
+            !domains.is_empty() && self.url_pattern.replace_all(content, "").trim().is_empty();
+        ProcessedLinks {
+            text,
+            domains,
+            link_only,
+        }
+    }
+
+    // Returns the domains that are on the blocklist or are subdomains of a blocked domain
+    pub fn blocked_domains(&self, domains: &[String]) -> Vec<String> {
+        domains
+            .iter()
+            .filter(|domain| {
+                let mut candidate = domain.as_str();
+                loop {
+                    if self.blocklist.contains(candidate) {
+                        return true;
+                    }
+                    match candidate.split_once('.') {
+                        Some((_, parent)) if parent.contains('.') => candidate = parent,
+                        _ => return false,
+                    }
+                }
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+// Lowercases the host and drops the port, credentials and a leading `www.`
+fn normalize_domain(host: &str) -> String {
+    let host = host.rsplit('@').next().unwrap_or(host);
+    let host = host.split(':').next().unwrap_or(host);
+    let host = host.trim_end_matches('.').to_lowercase();
+    host.strip_prefix("www.").unwrap_or(&host).to_string()
+}
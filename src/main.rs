@@ -1,14 +1,44 @@
+mod announcements;
+mod anomaly;
+mod aspects;
+mod charts;
+mod churn;
+mod cli;
+mod clustering;
+mod commands;
 mod config;
+mod crypto;
+mod dashboard;
 mod discord;
+mod emoji;
+mod emotion;
+mod export;
+mod health;
+mod links;
+mod media;
 mod mongo;
 mod monitor;
+mod phrases;
+mod privacy;
+mod pseudonym;
+mod reactions;
+mod report;
 mod scheduler;
 mod sentiment;
+mod sessions;
+mod spam;
+mod support;
+mod threads;
+mod topics;
+mod toxicity;
 mod translate;
 mod util;
+mod watchlist;
 
+use crypto::Keyring;
+use dashboard::start_dashboard;
 use discord::run_discord_bot;
-use mongo::get_mongo_db;
+use mongo::{create_indexes, get_mongo_db};
 use scheduler::start_scheduler;
 
 use std::env;
@@ -34,6 +64,16 @@ async fn main() {
     config::set_env_variables(env_config);
 
     let db = get_mongo_db(&env_config.mongo_uri).await;
+    if let Err(e) = create_indexes(&db).await {
+        println!("Error creating indexes: {:?}", e);
+    }
+
+    // Run a maintenance command instead of the bot when one is given
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        cli::run_command(&args, env_config, &db).await;
+        return;
+    }
 
     // Create a clone of the database connection
     let db_clone = db.clone();
@@ -42,6 +82,15 @@ async fn main() {
         start_scheduler(&db_clone).await;
     });
 
+    // Serve the web dashboard
+    if env_config.dashboard.enabled {
+        spawn(start_dashboard(
+            db.clone(),
+            env_config.encryption.as_ref().map(Keyring::new),
+            env_config.dashboard.clone(),
+        ));
+    }
+
     // List collections in the database
     let coll_names = db.list_collection_names(None).await;
     print!("Collections in database: ");
@@ -49,7 +98,7 @@ async fn main() {
         println!("{:?}", name);
     }
 
-    let discord_bot_handle = run_discord_bot(&env_config.discord_token, db).await;
+    let discord_bot_handle = run_discord_bot(env_config, db).await;
     if let Err(err) = discord_bot_handle.await {
         println!("An error occurred while running the Discord Bot: {}", err);
     }
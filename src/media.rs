@@ -0,0 +1,136 @@
+use crate::util::Redactor;
+use mongodb::bson::{doc, Document};
+use mongodb::error::Error;
+use mongodb::Database;
+use serde::{Deserialize, Serialize};
+use serenity::model::channel::{Attachment, Embed};
+
+// Words that mark a message with a screenshot as an issue report
+const ISSUE_KEYWORDS: &[&str] = &[
+    "error",
+    "bug",
+    "crash",
+    "fail",
+    "broken",
+    "stuck",
+    "issue",
+    "problem",
+    "not working",
+    "doesn't work",
+    "won't",
+    "can't",
+    "cannot",
+    "help",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct AttachmentInfo {
+    pub filename: String,
+    #[serde(rename = "contentType", skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    pub size: i64,
+}
+
+impl AttachmentInfo {
+    pub fn new(attachment: &Attachment, redactor: &Redactor) -> AttachmentInfo {
+        AttachmentInfo {
+            filename: redactor.redact(&attachment.filename).0,
+            content_type: attachment.content_type.clone(),
+            size: attachment.size as i64,
+        }
+    }
+
+    pub fn is_image(&self) -> bool {
+        match &self.content_type {
+            Some(content_type) => content_type.starts_with("image/"),
+            None => {
+                let filename = self.filename.to_lowercase();
+                [".png", ".jpg", ".jpeg", ".gif", ".webp"]
+                    .iter()
+                    .any(|extension| filename.ends_with(extension))
+            }
+        }
+    }
+}
+
+// Link previews and bot embeds. The URL isn't kept, only what the embed displays.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct EmbedInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl EmbedInfo {
+    pub fn new(embed: &Embed, redactor: &Redactor) -> EmbedInfo {
+        EmbedInfo {
+            kind: embed.kind.clone(),
+            provider: embed
+                .provider
+                .as_ref()
+                .and_then(|provider| provider.name.clone()),
+            title: embed.title.as_ref().map(|title| redactor.redact(title).0),
+            description: embed
+                .description
+                .as_ref()
+                .map(|description| redactor.redact(description).0),
+        }
+    }
+}
+
+// The titles and descriptions of the embeds, one per line
+pub fn embed_text(embeds: &[EmbedInfo]) -> String {
+    embeds
+        .iter()
+        .flat_map(|embed| [embed.title.as_deref(), embed.description.as_deref()])
+        .flatten()
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// A "screenshot of an error": an image posted with little text, or with text or a filename
+// that mentions an error
+pub fn is_issue_report(text: &str, attachments: &[AttachmentInfo], short_text: bool) -> bool {
+    let images: Vec<&AttachmentInfo> = attachments
+        .iter()
+        .filter(|attachment| attachment.is_image())
+        .collect();
+    if images.is_empty() {
+        return false;
+    }
+
+    let text = text.to_lowercase();
+    short_text
+        || ISSUE_KEYWORDS.iter().any(|keyword| text.contains(keyword))
+        || images
+            .iter()
+            .any(|image| image.filename.to_lowercase().contains("error"))
+}
+
+// Link previews usually arrive in a message update shortly after the message is created.
+// Encrypted embeds are only recorded on messages encrypted under the same key.
+pub async fn record_embeds(
+    db: &Database,
+    message_id: &str,
+    embeds: &[EmbedInfo],
+    key_id: Option<&str>,
+) -> Result<(), Error> {
+    let embeds = bson::to_bson(embeds).unwrap_or_default();
+    let key_id = match key_id {
+        Some(key_id) => bson::Bson::from(key_id),
+        None => bson::Bson::Document(doc! { "$exists": false }),
+    };
+    db.collection::<Document>("messages")
+        .update_one(
+            doc! { "messageId": message_id, "keyId": key_id },
+            doc! { "$set": { "embeds": embeds } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
@@ -1,34 +1,118 @@
+use crate::aspects::AspectSentiment;
+use crate::crypto::Keyring;
+use crate::emoji::EmojiFeatures;
+use crate::emotion::EmotionScores;
+use crate::media::{AttachmentInfo, EmbedInfo};
+use crate::phrases::EntityInfo;
+use crate::reactions::ReactionSentiment;
+use crate::threads::ThreadInfo;
+use crate::toxicity::ToxicityScores;
 use chrono::{Duration, Utc};
-use mongodb::bson::{doc, oid::ObjectId, DateTime};
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, oid::ObjectId, DateTime, Document};
 use mongodb::error::Error;
+use mongodb::options::FindOptions;
 use mongodb::results::DeleteResult;
-use mongodb::{options::ClientOptions, Client, Database};
+use mongodb::{options::ClientOptions, Client, Cursor, Database, IndexModel};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Message {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<ObjectId>,
-    pub username: String,
+    #[serde(rename = "messageId", skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    #[serde(rename = "channelId", skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<String>,
+    // ID of the message this one replies to
+    #[serde(rename = "replyTo", skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    #[serde(rename = "sessionId", skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<ObjectId>,
+    #[serde(rename = "authorId", default)]
+    pub author_id: String,
+    // Omitted when authors are pseudonymized; `authorId` then holds the pseudonym
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    // "staff" for support staff, whose messages aren't analyzed, otherwise "community"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
     pub channel: String,
+    // Set for messages posted in threads and forum posts; `channel` is then the parent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread: Option<ThreadInfo>,
     pub text: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub korean: Option<String>,
+    // ID of the key `text` and `korean` are encrypted with; absent for plaintext documents
+    #[serde(rename = "keyId", skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redactions: Vec<String>,
     #[serde(rename = "sentiment", skip_serializing_if = "Option::is_none")]
     pub analyzed: Option<String>,
+    // Reaction counts by emoji, maintained by the reaction_add/reaction_remove events
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reactions: BTreeMap<String, i64>,
+    #[serde(rename = "reactionSentiment", skip_serializing_if = "Option::is_none")]
+    pub reaction_sentiment: Option<ReactionSentiment>,
+    // Emoji usage in the original content, counted before emojis are replaced with words
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emoji: Option<EmojiFeatures>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<AttachmentInfo>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub embeds: Vec<EmbedInfo>,
+    // Domains linked in the message; the URLs themselves are replaced with placeholders
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub domains: Vec<String>,
+    // Why the message was flagged as spam ("duplicate", "flood", "new_account_burst").
+    // Spam isn't analyzed and is excluded from aggregates.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spam: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub toxicity: Option<ToxicityScores>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emotions: Option<EmotionScores>,
+    #[serde(rename = "keyPhrases", default, skip_serializing_if = "Vec::is_empty")]
+    pub key_phrases: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entities: Vec<EntityInfo>,
+    // Topics from the configured taxonomy, see `topics::TopicTagger`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub topics: Vec<String>,
+    // Sentiment per entity/topic, for messages that say different things about different
+    // things
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aspects: Vec<AspectSentiment>,
+    // Topic cluster assigned by the weekly clustering job
+    #[serde(rename = "clusterId", skip_serializing_if = "Option::is_none")]
+    pub cluster_id: Option<ObjectId>,
+    // An image posted with an error description or little text, see `media::is_issue_report`
+    #[serde(
+        rename = "issueReport",
+        default,
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub issue_report: bool,
     #[serde(rename = "createdAt")]
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub created_at: chrono::DateTime<Utc>,
 }
 
 pub async fn get_mongo_db(uri: &str) -> Database {
+    get_database(uri, "discord-stats").await
+}
+
+pub async fn get_database(uri: &str, name: &str) -> Database {
     let client_options = ClientOptions::parse(uri)
         .await
         .expect("Failed to parse MongoDB client otions");
 
     let client = Client::with_options(client_options).expect("Failed to connect to MongoDB client");
 
-    client.database("discord-stats")
+    client.database(name)
 }
 
 pub async fn save_message(db: &Database, message: &Message) -> Result<(), Error> {
@@ -44,6 +128,51 @@ pub async fn save_message(db: &Database, message: &Message) -> Result<(), Error>
         .map(|_| ())
 }
 
+// Streams stored messages, decrypting them when a keyring is given. Every read path goes
+// through here so that encrypted documents are handled transparently.
+pub struct MessageReader<'a> {
+    cursor: Cursor<Message>,
+    keyring: Option<&'a Keyring>,
+}
+
+impl<'a> MessageReader<'a> {
+    pub async fn next(&mut self) -> Result<Option<Message>, Error> {
+        while let Some(mut message) = self.cursor.try_next().await? {
+            if let Some(keyring) = self.keyring {
+                if let Err(e) = keyring.decrypt_message(&mut message) {
+                    println!("Error decrypting message {:?}: {}", message.id, e);
+                    continue;
+                }
+            }
+            return Ok(Some(message));
+        }
+
+        Ok(None)
+    }
+}
+
+pub async fn read_messages<'a>(
+    db: &Database,
+    filter: Document,
+    keyring: Option<&'a Keyring>,
+) -> Result<MessageReader<'a>, Error> {
+    let options = FindOptions::builder().sort(doc! { "createdAt": 1 }).build();
+    read_messages_with(db, filter, options, keyring).await
+}
+
+// Like `read_messages`, with the caller's sort order and paging
+pub async fn read_messages_with<'a>(
+    db: &Database,
+    filter: Document,
+    options: FindOptions,
+    keyring: Option<&'a Keyring>,
+) -> Result<MessageReader<'a>, Error> {
+    let message_collection = db.collection::<Message>("messages");
+    let cursor = message_collection.find(filter, options).await?;
+
+    Ok(MessageReader { cursor, keyring })
+}
+
 pub async fn delete_messages(db: &Database) -> Result<DeleteResult, Error> {
     let message_collection = db.collection::<mongodb::bson::Document>("messages");
     let three_weeks_ago = Utc::now() - Duration::weeks(3);
@@ -55,3 +184,63 @@ pub async fn delete_messages(db: &Database) -> Result<DeleteResult, Error> {
 
     Ok(delete_result)
 }
+
+// Collections derived from messages, with the timestamp they expire by. They are pruned
+// along with the messages.
+const DERIVED_COLLECTIONS: &[(&str, &str)] = &[
+    ("sessions", "endedAt"),
+    ("threads", "lastMessageAt"),
+    ("watchlist_hits", "createdAt"),
+    ("topic_clusters", "runAt"),
+    ("volume_anomalies", "createdAt"),
+];
+
+// Deletes the derived records older than three weeks and returns the number deleted
+pub async fn delete_derived_records(db: &Database) -> Result<u64, Error> {
+    let three_weeks_ago_bson = DateTime::from_chrono(Utc::now() - Duration::weeks(3));
+
+    let mut deleted = 0;
+    for (collection, timestamp) in DERIVED_COLLECTIONS {
+        let result = db
+            .collection::<Document>(collection)
+            .delete_many(doc! { *timestamp: { "$lt": three_weeks_ago_bson } }, None)
+            .await?;
+        deleted += result.deleted_count;
+    }
+
+    Ok(deleted)
+}
+
+// Creates the indexes the bot's lookups and pruning rely on. Creating an existing index
+// is a no-op, so this runs at every startup.
+pub async fn create_indexes(db: &Database) -> Result<(), Error> {
+    let indexes: &[(&str, Document)] = &[
+        ("messages", doc! { "messageId": 1 }),
+        ("messages", doc! { "createdAt": 1 }),
+        ("sessions", doc! { "messageIds": 1 }),
+        ("sessions", doc! { "endedAt": 1 }),
+        ("threads", doc! { "threadId": 1 }),
+        (
+            "volume_anomalies",
+            doc! { "channel": 1, "hour": 1, "kind": 1 },
+        ),
+    ];
+    for (collection, keys) in indexes {
+        let index = IndexModel::builder().keys(keys.clone()).build();
+        db.collection::<Document>(collection)
+            .create_index(index, None)
+            .await?;
+    }
+
+    Ok(())
+}
+
+// Aggregation results come back as i32, i64 or f64 depending on the operator
+pub fn get_number(document: &Document, key: &str) -> Option<f64> {
+    match document.get(key)? {
+        bson::Bson::Int32(value) => Some(*value as f64),
+        bson::Bson::Int64(value) => Some(*value as f64),
+        bson::Bson::Double(value) => Some(*value),
+        _ => None,
+    }
+}
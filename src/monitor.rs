@@ -34,7 +34,7 @@ fn get_memory_stats() -> MemoryStats {
     let used_memory = bytes_to_gb(system.used_memory());
     let free_memory = bytes_to_gb(system.free_memory());
     let available_memory = bytes_to_gb(system.available_memory());
-    let used_memory_percentage = used_memory as f64 / total_memory as f64 * 100.0;
+    let used_memory_percentage = used_memory / total_memory * 100.0;
 
     MemoryStats {
         total_memory,
@@ -137,7 +137,7 @@ pub async fn monitor_memory_stats(client: Client, channel_id: ChannelId) {
             _ = alert_timer.tick() => {
                 // If used_memory_percentage is greater than 95%, send an alert
                 if stats.used_memory_percentage > 95.0 {
-                    let embed = memory_stats_alert_embed(stats.clone());
+                    let embed = memory_stats_alert_embed(stats);
 
                     // Send the embed to the channel
                     let _ = channel_id.send_message(&client.cache_and_http.http, |m| {
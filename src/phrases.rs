@@ -0,0 +1,226 @@
+use crate::config::{PhraseConfig, PhraseProviderKind};
+use async_trait::async_trait;
+use aws_sdk_comprehend::types::LanguageCode;
+use aws_sdk_comprehend::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+
+// Comprehend's minimum confidence for phrases and entities we keep
+const MIN_COMPREHEND_SCORE: f32 = 0.8;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct EntityInfo {
+    pub text: String,
+    // Comprehend entity type, e.g. ORGANIZATION, COMMERCIAL_ITEM, DATE
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExtractedPhrases {
+    pub key_phrases: Vec<String>,
+    pub entities: Vec<EntityInfo>,
+}
+
+#[async_trait]
+pub trait PhraseExtractor: Send + Sync {
+    async fn extract(&self, text: &str) -> Result<ExtractedPhrases, Box<dyn Error + Send + Sync>>;
+}
+
+pub fn load_phrase_extractor(config: &PhraseConfig) -> Option<Box<dyn PhraseExtractor>> {
+    match config.provider {
+        PhraseProviderKind::Offline => Some(Box::new(RakeExtractor {
+            max_phrases: config.max_phrases,
+        })),
+        PhraseProviderKind::Comprehend => Some(Box::new(ComprehendPhrases {
+            max_phrases: config.max_phrases,
+        })),
+        PhraseProviderKind::Disabled => None,
+    }
+}
+
+// AWS Comprehend DetectKeyPhrases and DetectEntities
+pub struct ComprehendPhrases {
+    max_phrases: usize,
+}
+
+#[async_trait]
+impl PhraseExtractor for ComprehendPhrases {
+    async fn extract(&self, text: &str) -> Result<ExtractedPhrases, Box<dyn Error + Send + Sync>> {
+        let shared_config = aws_config::load_from_env().await;
+        let client = Client::new(&shared_config);
+
+        let key_phrases = client
+            .detect_key_phrases()
+            .text(text)
+            .language_code(LanguageCode::En)
+            .send()
+            .await?
+            .key_phrases
+            .unwrap_or_default();
+        let entities = client
+            .detect_entities()
+            .text(text)
+            .language_code(LanguageCode::En)
+            .send()
+            .await?
+            .entities
+            .unwrap_or_default();
+
+        let mut phrases = ExtractedPhrases::default();
+        for phrase in key_phrases {
+            if phrase.score.unwrap_or_default() < MIN_COMPREHEND_SCORE {
+                continue;
+            }
+            if let Some(text) = phrase.text {
+                let text = text.to_lowercase();
+                if !phrases.key_phrases.contains(&text) {
+                    phrases.key_phrases.push(text);
+                }
+            }
+        }
+        phrases.key_phrases.truncate(self.max_phrases);
+
+        for entity in entities {
+            if entity.score.unwrap_or_default() < MIN_COMPREHEND_SCORE {
+                continue;
+            }
+            if let (Some(text), Some(kind)) = (entity.text, entity.r#type) {
+                let entity = EntityInfo {
+                    text,
+                    kind: kind.as_str().to_string(),
+                };
+                if !phrases.entities.contains(&entity) {
+                    phrases.entities.push(entity);
+                }
+            }
+        }
+
+        Ok(phrases)
+    }
+}
+
+pub const STOPWORDS: &[&str] = &[
+    "a", "about", "after", "again", "all", "also", "am", "an", "and", "any", "are", "as", "at",
+    "be", "because", "been", "before", "being", "but", "by", "can", "could", "did", "do", "does",
+    "doing", "dont", "don't", "for", "from", "get", "got", "had", "has", "have", "having", "he",
+    "her", "here", "him", "his", "how", "i", "i'm", "if", "in", "into", "is", "it", "it's", "its",
+    "just", "let", "like", "me", "more", "my", "no", "not", "now", "of", "on", "one", "only", "or",
+    "our", "out", "over", "please", "really", "same", "she", "should", "so", "some", "still",
+    "such", "than", "that", "the", "their", "them", "then", "there", "these", "they", "this",
+    "those", "to", "too", "up", "us", "very", "was", "we", "were", "what", "when", "where",
+    "which", "while", "who", "why", "will", "with", "would", "yes", "you", "your",
+];
+
+// Longer candidates are usually run-on fragments rather than phrases
+const MAX_PHRASE_WORDS: usize = 4;
+
+// Offline keyword extraction with RAKE: candidate phrases are the runs of words between
+// stopwords and punctuation, scored by the sum of their words' degree/frequency ratios
+pub struct RakeExtractor {
+    max_phrases: usize,
+}
+
+#[async_trait]
+impl PhraseExtractor for RakeExtractor {
+    async fn extract(&self, text: &str) -> Result<ExtractedPhrases, Box<dyn Error + Send + Sync>> {
+        let mut candidates: Vec<Vec<String>> = Vec::new();
+        let mut current: Vec<String> = Vec::new();
+        for token in text.split_whitespace() {
+            // Redaction and link placeholders split phrases without being part of them
+            let is_placeholder = token.starts_with('[') && token.ends_with(']');
+            let word = token
+                .trim_matches(|c: char| !c.is_alphanumeric() && c != '\'')
+                .to_lowercase();
+            let ends_clause = token.ends_with(['.', ',', '!', '?', ';', ':']);
+
+            if is_placeholder || word.is_empty() || STOPWORDS.contains(&word.as_str()) {
+                candidates.push(std::mem::take(&mut current));
+            } else {
+                current.push(word);
+            }
+            if ends_clause {
+                candidates.push(std::mem::take(&mut current));
+            }
+        }
+        candidates.push(current);
+        candidates.retain(|phrase| !phrase.is_empty() && phrase.len() <= MAX_PHRASE_WORDS);
+
+        let mut frequency: HashMap<&str, f64> = HashMap::new();
+        let mut degree: HashMap<&str, f64> = HashMap::new();
+        for phrase in &candidates {
+            for word in phrase {
+                *frequency.entry(word).or_default() += 1.0;
+                *degree.entry(word).or_default() += phrase.len() as f64;
+            }
+        }
+
+        let mut scored: Vec<(String, f64)> = Vec::new();
+        for phrase in &candidates {
+            // Single short words ("ok", "gm") are rarely informative
+            if phrase.len() == 1 && phrase[0].chars().count() < 4 {
+                continue;
+            }
+            let text = phrase.join(" ");
+            if scored.iter().any(|(existing, _)| *existing == text) {
+                continue;
+            }
+            let score = phrase
+                .iter()
+                .map(|word| degree[word.as_str()] / frequency[word.as_str()])
+                .sum();
+            scored.push((text, score));
+        }
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        Ok(ExtractedPhrases {
+            key_phrases: scored
+                .into_iter()
+                .take(self.max_phrases)
+                .map(|(phrase, _)| phrase)
+                .collect(),
+            entities: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn rake(text: &str, max_phrases: usize) -> Vec<String> {
+        RakeExtractor { max_phrases }
+            .extract(text)
+            .await
+            .unwrap()
+            .key_phrases
+    }
+
+    #[tokio::test]
+    async fn splits_phrases_at_stopwords_and_punctuation() {
+        let phrases = rake(
+            "Staking rewards are missing, and the withdrawal button is broken",
+            5,
+        )
+        .await;
+        assert_eq!(
+            phrases,
+            vec!["staking rewards", "withdrawal button", "missing", "broken"]
+        );
+    }
+
+    #[tokio::test]
+    async fn ranks_longer_phrases_first_and_keeps_the_top_ones() {
+        let phrases = rake("send to [WALLET] wallet connect failed again", 1).await;
+        assert_eq!(phrases, vec!["wallet connect failed"]);
+    }
+
+    #[tokio::test]
+    async fn drops_short_single_words_and_run_on_fragments() {
+        assert!(rake("gm! ok.", 5).await.is_empty());
+        assert!(rake("swap page keeps showing stale prices", 5)
+            .await
+            .is_empty());
+    }
+}
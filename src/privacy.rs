@@ -0,0 +1,158 @@
+use crate::pseudonym::Pseudonymizer;
+use chrono::Utc;
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, Document};
+use mongodb::error::Error;
+use mongodb::options::UpdateOptions;
+use mongodb::Database;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+// In-memory copy of the opted-out user IDs, shared between the event handler and the
+// slash command handlers so that `Handler::message` doesn't have to query MongoDB.
+pub type OptOuts = Arc<RwLock<HashSet<u64>>>;
+
+// Collections holding documents keyed by `authorId` that must be purged on a
+// delete-my-data request. `messages` is the source; the rest are derived from it.
+pub const USER_DATA_COLLECTIONS: &[&str] = &["messages", "watchlist_hits", "user_baselines"];
+
+// Collections and array fields listing users alongside others, e.g. session participants.
+// The user is pulled from the array rather than the whole document being deleted.
+pub const USER_REFERENCE_FIELDS: &[(&str, &str)] = &[("sessions", "participants")];
+
+const OPTOUT_COLLECTION: &str = "privacy_optouts";
+const AUDIT_COLLECTION: &str = "privacy_requests";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrivacyAction {
+    OptOut,
+    OptIn,
+    DeleteMyData,
+}
+
+impl PrivacyAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PrivacyAction::OptOut => "optout",
+            PrivacyAction::OptIn => "optin",
+            PrivacyAction::DeleteMyData => "delete-my-data",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PrivacyRequest {
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    pub action: String,
+    #[serde(rename = "deletedCount")]
+    pub deleted_count: u64,
+    #[serde(rename = "createdAt")]
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub created_at: chrono::DateTime<Utc>,
+}
+
+pub async fn load_opt_outs(db: &Database) -> Result<OptOuts, Error> {
+    let collection = db.collection::<Document>(OPTOUT_COLLECTION);
+    let mut cursor = collection.find(None, None).await?;
+
+    let mut user_ids = HashSet::new();
+    while let Some(document) = cursor.try_next().await? {
+        if let Some(user_id) = document
+            .get_str("userId")
+            .ok()
+            .and_then(|id| id.parse::<u64>().ok())
+        {
+            user_ids.insert(user_id);
+        }
+    }
+
+    Ok(Arc::new(RwLock::new(user_ids)))
+}
+
+pub async fn is_opted_out(opt_outs: &OptOuts, user_id: u64) -> bool {
+    opt_outs.read().await.contains(&user_id)
+}
+
+pub async fn opt_out(db: &Database, opt_outs: &OptOuts, user_id: u64) -> Result<(), Error> {
+    let collection = db.collection::<Document>(OPTOUT_COLLECTION);
+    let options = UpdateOptions::builder().upsert(true).build();
+    collection
+        .update_one(
+            doc! { "userId": user_id.to_string() },
+            doc! { "$setOnInsert": { "createdAt": bson::DateTime::now() } },
+            options,
+        )
+        .await?;
+
+    opt_outs.write().await.insert(user_id);
+    record_request(db, user_id, PrivacyAction::OptOut, 0).await
+}
+
+pub async fn opt_in(db: &Database, opt_outs: &OptOuts, user_id: u64) -> Result<(), Error> {
+    let collection = db.collection::<Document>(OPTOUT_COLLECTION);
+    collection
+        .delete_many(doc! { "userId": user_id.to_string() }, None)
+        .await?;
+
+    opt_outs.write().await.remove(&user_id);
+    record_request(db, user_id, PrivacyAction::OptIn, 0).await
+}
+
+// Removes every stored document authored by the user and returns the number of deleted
+// documents. Only `authorId` identifies the author: usernames aren't unique, and messages
+// saved before `authorId` was recorded expire with the three-week retention.
+pub async fn delete_user_data(
+    db: &Database,
+    pseudonymizer: Option<&Pseudonymizer>,
+    user_id: u64,
+) -> Result<u64, Error> {
+    let mut author_ids = vec![user_id.to_string()];
+    if let Some(pseudonymizer) = pseudonymizer {
+        author_ids.push(pseudonymizer.pseudonym(user_id));
+    }
+    let filter = doc! { "authorId": { "$in": &author_ids } };
+
+    let mut deleted_count = 0;
+    for name in USER_DATA_COLLECTIONS {
+        let collection = db.collection::<Document>(name);
+        let result = collection.delete_many(filter.clone(), None).await?;
+        deleted_count += result.deleted_count;
+    }
+
+    for (name, field) in USER_REFERENCE_FIELDS {
+        let collection = db.collection::<Document>(name);
+        collection
+            .update_many(
+                doc! { *field: { "$in": &author_ids } },
+                doc! { "$pull": { *field: { "$in": &author_ids } } },
+                None,
+            )
+            .await?;
+    }
+
+    if let Some(pseudonymizer) = pseudonymizer {
+        pseudonymizer.forget(user_id).await?;
+    }
+
+    record_request(db, user_id, PrivacyAction::DeleteMyData, deleted_count).await?;
+    Ok(deleted_count)
+}
+
+async fn record_request(
+    db: &Database,
+    user_id: u64,
+    action: PrivacyAction,
+    deleted_count: u64,
+) -> Result<(), Error> {
+    let collection = db.collection::<PrivacyRequest>(AUDIT_COLLECTION);
+    let request = PrivacyRequest {
+        user_id: user_id.to_string(),
+        action: action.as_str().to_string(),
+        deleted_count,
+        created_at: Utc::now(),
+    };
+    collection.insert_one(request, None).await.map(|_| ())
+}
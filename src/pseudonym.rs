@@ -0,0 +1,180 @@
+use crate::config::EnvConfig;
+use crate::mongo::get_database;
+use crate::privacy::{USER_DATA_COLLECTIONS, USER_REFERENCE_FIELDS};
+use chrono::Utc;
+use futures::stream::TryStreamExt;
+use hmac::{Hmac, Mac};
+use mongodb::bson::{doc, Document};
+use mongodb::error::Error;
+use mongodb::options::UpdateOptions;
+use mongodb::Database;
+use sha2::Sha256;
+use std::collections::HashSet;
+use tokio::sync::Mutex;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const IDENTITY_COLLECTION: &str = "identities";
+
+pub struct Pseudonymizer {
+    key: Vec<u8>,
+    identity_db: Database,
+    // Pseudonyms already written to the identity map during this run
+    known: Mutex<HashSet<String>>,
+}
+
+// Returns None when pseudonymization isn't configured for the environment
+pub async fn load_pseudonymizer(config: &EnvConfig) -> Option<Pseudonymizer> {
+    let pseudonymization = config.pseudonymization.as_ref()?;
+    let identity_db = get_database(&config.mongo_uri, &pseudonymization.identity_database).await;
+
+    Some(Pseudonymizer {
+        key: pseudonymization.key.as_bytes().to_vec(),
+        identity_db,
+        known: Mutex::new(HashSet::new()),
+    })
+}
+
+impl Pseudonymizer {
+    pub fn pseudonym(&self, user_id: u64) -> String {
+        pseudonym_with_key(&self.key, user_id)
+    }
+
+    // Returns the pseudonym for the author, recording the mapping in the identity database
+    // the first time it is seen so that it can be re-identified later.
+    pub async fn pseudonymize(&self, user_id: u64) -> Result<String, Error> {
+        let pseudonym = self.pseudonym(user_id);
+
+        let mut known = self.known.lock().await;
+        if !known.contains(&pseudonym) {
+            let options = UpdateOptions::builder().upsert(true).build();
+            self.identities()
+                .update_one(
+                    doc! { "pseudonym": &pseudonym },
+                    doc! {
+                        "$setOnInsert": {
+                            "authorId": user_id.to_string(),
+                            "createdAt": bson::DateTime::now(),
+                        }
+                    },
+                    options,
+                )
+                .await?;
+            known.insert(pseudonym.clone());
+        }
+
+        Ok(pseudonym)
+    }
+
+    pub async fn forget(&self, user_id: u64) -> Result<(), Error> {
+        let pseudonym = self.pseudonym(user_id);
+        self.identities()
+            .delete_many(doc! { "pseudonym": &pseudonym }, None)
+            .await?;
+        self.known.lock().await.remove(&pseudonym);
+        Ok(())
+    }
+
+    pub async fn reidentify(&self, pseudonym: &str) -> Result<Option<String>, Error> {
+        let identity = self
+            .identities()
+            .find_one(doc! { "pseudonym": pseudonym }, None)
+            .await?;
+        Ok(identity.and_then(|identity| identity.get_str("authorId").ok().map(String::from)))
+    }
+
+    // Rewrites every stored pseudonym to one derived from `new_key` and returns the number
+    // of identities rotated. The config must be updated to the new key afterwards.
+    pub async fn rotate_key(&self, db: &Database, new_key: &str) -> Result<u64, Error> {
+        // Load the identities up front so that rewritten documents aren't visited twice
+        let identities: Vec<Document> = self
+            .identities()
+            .find(None, None)
+            .await?
+            .try_collect()
+            .await?;
+        let mut rotated = 0;
+
+        for identity in identities {
+            let (Ok(old_pseudonym), Some(user_id)) = (
+                identity.get_str("pseudonym"),
+                identity
+                    .get_str("authorId")
+                    .ok()
+                    .and_then(|id| id.parse::<u64>().ok()),
+            ) else {
+                continue;
+            };
+            let new_pseudonym = pseudonym_with_key(new_key.as_bytes(), user_id);
+
+            for name in USER_DATA_COLLECTIONS {
+                db.collection::<Document>(name)
+                    .update_many(
+                        doc! { "authorId": old_pseudonym },
+                        doc! { "$set": { "authorId": &new_pseudonym } },
+                        None,
+                    )
+                    .await?;
+            }
+            for (name, field) in USER_REFERENCE_FIELDS {
+                db.collection::<Document>(name)
+                    .update_many(
+                        doc! { *field: old_pseudonym },
+                        doc! { "$set": { format!("{}.$", field): &new_pseudonym } },
+                        None,
+                    )
+                    .await?;
+            }
+
+            self.identities()
+                .update_one(
+                    doc! { "pseudonym": old_pseudonym },
+                    doc! {
+                        "$set": {
+                            "pseudonym": &new_pseudonym,
+                            "rotatedAt": bson::DateTime::from_chrono(Utc::now()),
+                        }
+                    },
+                    None,
+                )
+                .await?;
+            rotated += 1;
+        }
+
+        Ok(rotated)
+    }
+
+    fn identities(&self) -> mongodb::Collection<Document> {
+        self.identity_db.collection(IDENTITY_COLLECTION)
+    }
+}
+
+fn pseudonym_with_key(key: &[u8], user_id: u64) -> String {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC can take a key of any size");
+    mac.update(user_id.to_string().as_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    // 128 bits is plenty to avoid collisions between community members
+    format!("u_{}", hex::encode(&digest[..16]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pseudonym_is_a_truncated_hmac_of_the_user_id() {
+        assert_eq!(
+            pseudonym_with_key(b"secret", 123456789012345678),
+            "u_f04095ef703c4bc860ee80c74ae3a78a"
+        );
+    }
+
+    #[test]
+    fn pseudonyms_are_stable_per_key_and_user() {
+        let pseudonym = pseudonym_with_key(b"secret", 42);
+        assert_eq!(pseudonym, pseudonym_with_key(b"secret", 42));
+        assert_ne!(pseudonym, pseudonym_with_key(b"secret", 43));
+        assert_ne!(pseudonym, pseudonym_with_key(b"other secret", 42));
+    }
+}
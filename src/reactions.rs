@@ -0,0 +1,83 @@
+use crate::emoji::{custom_polarity, normalize_emoji, unicode_polarity, Polarity};
+use mongodb::bson::{doc, Document};
+use mongodb::error::Error;
+use mongodb::Database;
+use serde::{Deserialize, Serialize};
+use serenity::model::channel::ReactionType;
+use std::collections::HashMap;
+
+// Reaction counts on a stored message, bucketed by the polarity of the emoji
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default)]
+pub struct ReactionSentiment {
+    #[serde(default)]
+    pub positive: i64,
+    #[serde(default)]
+    pub neutral: i64,
+    #[serde(default)]
+    pub negative: i64,
+}
+
+impl ReactionSentiment {
+    pub fn total(&self) -> i64 {
+        self.positive + self.neutral + self.negative
+    }
+
+    // Net polarity from -1 (all negative) to 1 (all positive)
+    pub fn score(&self) -> Option<f64> {
+        let total = self.total();
+        if total == 0 {
+            return None;
+        }
+        Some((self.positive - self.negative) as f64 / total as f64)
+    }
+}
+
+// Returns the key the reaction is counted under and its polarity. Custom guild emojis are
+// keyed by name, unicode emojis by the emoji itself.
+pub fn classify_reaction(
+    emoji: &ReactionType,
+    custom_emoji: &HashMap<String, Polarity>,
+) -> Option<(String, Polarity)> {
+    match emoji {
+        ReactionType::Unicode(emoji) => Some((normalize_emoji(emoji), unicode_polarity(emoji))),
+        ReactionType::Custom { name, .. } => {
+            let name = name.clone()?;
+            let polarity = custom_polarity(&name, custom_emoji);
+            Some((name, polarity))
+        }
+        _ => None,
+    }
+}
+
+// Adds `delta` (1 or -1) to the reaction counts of the stored message. Reactions on
+// messages we didn't store are ignored.
+pub async fn record_reaction(
+    db: &Database,
+    message_id: &str,
+    key: &str,
+    polarity: Polarity,
+    delta: i32,
+) -> Result<(), Error> {
+    // `.` and `$` can't appear in field names
+    let key = key.replace(['.', '$'], "_");
+    let reaction_field = format!("reactions.{}", key);
+
+    let mut filter = doc! { "messageId": message_id };
+    if delta < 0 {
+        filter.insert(reaction_field.as_str(), doc! { "$gt": 0 });
+    }
+
+    db.collection::<Document>("messages")
+        .update_one(
+            filter,
+            doc! {
+                "$inc": {
+                    reaction_field: delta,
+                    format!("reactionSentiment.{}", polarity.as_str()): delta,
+                }
+            },
+            None,
+        )
+        .await
+        .map(|_| ())
+}
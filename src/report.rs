@@ -0,0 +1,257 @@
+use crate::aspects::aspect_sentiment;
+use crate::emotion::EMOTIONS;
+use crate::health::{channel_health_ranking, local_midnight, ChannelHealthSummary};
+use crate::mongo::get_number;
+use crate::reactions::ReactionSentiment;
+use crate::support::{support_stats, ChannelSupportStats};
+use crate::topics::{topic_sentiment, TopicSentiment};
+use crate::util::local_now;
+use chrono::{Duration, NaiveDate, Utc};
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::error::Error;
+use mongodb::Database;
+use std::collections::BTreeMap;
+
+pub const SENTIMENT_LABELS: &[&str] = &["positive", "neutral", "mixed", "negative"];
+
+// Everything that goes into the weekly digest posted by the report scheduler
+#[derive(Debug, Clone, Default)]
+pub struct WeeklyReport {
+    pub since: chrono::DateTime<Utc>,
+    pub until: chrono::DateTime<Utc>,
+    pub sentiment_counts: Vec<(String, i64)>,
+    // Per-day and per-channel counts in `SENTIMENT_LABELS` order, for the charts
+    pub daily_sentiment: Vec<(NaiveDate, Vec<i64>)>,
+    pub channel_sentiment: Vec<(String, Vec<i64>)>,
+    // Average share of each emotion over the messages that carried one, in `EMOTIONS` order
+    pub emotions: Vec<(String, f64)>,
+    pub emotion_messages: i64,
+    // Reaction-based signal, alongside the text sentiment above
+    pub reactions: ReactionSentiment,
+    pub support: Vec<ChannelSupportStats>,
+    pub topics: Vec<TopicSentiment>,
+    // Per-topic sentiment from the aspects of each message; `messages` counts mentions
+    pub aspects: Vec<TopicSentiment>,
+    // Average daily channel health over the week, least healthy first
+    pub channel_health: Vec<ChannelHealthSummary>,
+}
+
+pub async fn build_weekly_report(db: &Database) -> Result<WeeklyReport, Error> {
+    let until = local_now();
+    let since = until - Duration::weeks(1);
+
+    let (emotion_messages, emotions) = emotion_breakdown(db, since).await?;
+
+    Ok(WeeklyReport {
+        since,
+        until,
+        sentiment_counts: sentiment_counts(db, since).await?,
+        daily_sentiment: daily_sentiment(db, since, until).await?,
+        channel_sentiment: channel_sentiment(db, since).await?,
+        emotions,
+        emotion_messages,
+        reactions: reaction_sentiment(db, since).await?,
+        support: support_stats(db, since).await?,
+        topics: topic_sentiment(db, since).await?,
+        aspects: aspect_sentiment(db, since).await?,
+        channel_health: channel_health_ranking(db, local_midnight(since)).await?,
+    })
+}
+
+// Counts community messages per sentiment label, in `SENTIMENT_LABELS` order
+pub async fn sentiment_counts(
+    db: &Database,
+    since: chrono::DateTime<Utc>,
+) -> Result<Vec<(String, i64)>, Error> {
+    let collection = db.collection::<Document>("messages");
+    let pipeline = vec![
+        doc! {
+            "$match": {
+                "createdAt": { "$gte": bson::DateTime::from_chrono(since) },
+                "role": { "$ne": "staff" },
+                "spam": { "$exists": false },
+                "sentiment": { "$exists": true },
+            }
+        },
+        doc! { "$group": { "_id": "$sentiment", "count": { "$sum": 1 } } },
+    ];
+
+    let mut counts: Vec<(String, i64)> = SENTIMENT_LABELS
+        .iter()
+        .map(|label| (label.to_string(), 0))
+        .collect();
+    let mut cursor = collection.aggregate(pipeline, None).await?;
+    while let Some(document) = cursor.try_next().await? {
+        let label = document.get_str("_id").unwrap_or_default();
+        if let Some(entry) = counts.iter_mut().find(|(name, _)| name == label) {
+            entry.1 = get_number(&document, "count").unwrap_or_default() as i64;
+        }
+    }
+
+    Ok(counts)
+}
+
+// Channels shown in the per-channel sentiment chart
+const MAX_CHART_CHANNELS: usize = 8;
+
+// Community message counts per sentiment label for every day from `since` to `until`,
+// oldest first; days without messages have zero counts
+pub async fn daily_sentiment(
+    db: &Database,
+    since: chrono::DateTime<Utc>,
+    until: chrono::DateTime<Utc>,
+) -> Result<Vec<(NaiveDate, Vec<i64>)>, Error> {
+    let key = doc! { "$dateToString": { "format": "%Y-%m-%d", "date": "$createdAt" } };
+    let counts = sentiment_by(db, since, key.into()).await?;
+
+    let mut days = Vec::new();
+    let mut day = since.date_naive();
+    while day <= until.date_naive() {
+        let key = day.format("%Y-%m-%d").to_string();
+        let day_counts = counts
+            .get(&key)
+            .cloned()
+            .unwrap_or_else(|| vec![0; SENTIMENT_LABELS.len()]);
+        days.push((day, day_counts));
+        day += Duration::days(1);
+    }
+
+    Ok(days)
+}
+
+// Community message counts per sentiment label for the busiest channels, busiest first
+pub async fn channel_sentiment(
+    db: &Database,
+    since: chrono::DateTime<Utc>,
+) -> Result<Vec<(String, Vec<i64>)>, Error> {
+    let mut channels: Vec<(String, Vec<i64>)> = sentiment_by(db, since, "$channel".into())
+        .await?
+        .into_iter()
+        .collect();
+    channels.sort_by_key(|(_, counts)| std::cmp::Reverse(counts.iter().sum::<i64>()));
+    channels.truncate(MAX_CHART_CHANNELS);
+
+    Ok(channels)
+}
+
+// Counts analyzed community messages per `key` value and sentiment label, in
+// `SENTIMENT_LABELS` order
+async fn sentiment_by(
+    db: &Database,
+    since: chrono::DateTime<Utc>,
+    key: Bson,
+) -> Result<BTreeMap<String, Vec<i64>>, Error> {
+    let collection = db.collection::<Document>("messages");
+    let pipeline = vec![
+        doc! {
+            "$match": {
+                "createdAt": { "$gte": bson::DateTime::from_chrono(since) },
+                "role": { "$ne": "staff" },
+                "spam": { "$exists": false },
+                "sentiment": { "$exists": true },
+            }
+        },
+        doc! {
+            "$group": {
+                "_id": { "key": key, "sentiment": "$sentiment" },
+                "count": { "$sum": 1 },
+            }
+        },
+    ];
+
+    let mut counts: BTreeMap<String, Vec<i64>> = BTreeMap::new();
+    let mut cursor = collection.aggregate(pipeline, None).await?;
+    while let Some(document) = cursor.try_next().await? {
+        let Ok(group) = document.get_document("_id") else {
+            continue;
+        };
+        let (Ok(key), Ok(label)) = (group.get_str("key"), group.get_str("sentiment")) else {
+            continue;
+        };
+        let Some(index) = SENTIMENT_LABELS.iter().position(|name| *name == label) else {
+            continue;
+        };
+        counts
+            .entry(key.to_string())
+            .or_insert_with(|| vec![0; SENTIMENT_LABELS.len()])[index] +=
+            get_number(&document, "count").unwrap_or_default() as i64;
+    }
+
+    Ok(counts)
+}
+
+// Averages the emotion scores of community messages posted since `since`; returns the number
+// of messages with emotions and the average share per emotion
+pub async fn emotion_breakdown(
+    db: &Database,
+    since: chrono::DateTime<Utc>,
+) -> Result<(i64, Vec<(String, f64)>), Error> {
+    let collection = db.collection::<Document>("messages");
+    let mut group = doc! { "_id": null, "count": { "$sum": 1 } };
+    for emotion in EMOTIONS {
+        group.insert(*emotion, doc! { "$avg": format!("$emotions.{}", emotion) });
+    }
+    let pipeline = vec![
+        doc! {
+            "$match": {
+                "createdAt": { "$gte": bson::DateTime::from_chrono(since) },
+                "role": { "$ne": "staff" },
+                "spam": { "$exists": false },
+                "emotions": { "$exists": true },
+            }
+        },
+        doc! { "$group": group },
+    ];
+
+    let mut cursor = collection.aggregate(pipeline, None).await?;
+    let document = cursor.try_next().await?.unwrap_or_default();
+    let emotions = EMOTIONS
+        .iter()
+        .map(|emotion| {
+            let share = get_number(&document, emotion).unwrap_or_default();
+            (emotion.to_string(), share)
+        })
+        .collect();
+
+    Ok((
+        get_number(&document, "count").unwrap_or_default() as i64,
+        emotions,
+    ))
+}
+
+// Sums the reaction counts on messages posted since `since`
+pub async fn reaction_sentiment(
+    db: &Database,
+    since: chrono::DateTime<Utc>,
+) -> Result<ReactionSentiment, Error> {
+    let collection = db.collection::<Document>("messages");
+    let pipeline = vec![
+        doc! {
+            "$match": {
+                "createdAt": { "$gte": bson::DateTime::from_chrono(since) },
+                "spam": { "$exists": false },
+                "reactionSentiment": { "$exists": true },
+            }
+        },
+        doc! {
+            "$group": {
+                "_id": null,
+                "positive": { "$sum": "$reactionSentiment.positive" },
+                "neutral": { "$sum": "$reactionSentiment.neutral" },
+                "negative": { "$sum": "$reactionSentiment.negative" },
+            }
+        },
+    ];
+
+    let mut cursor = collection.aggregate(pipeline, None).await?;
+    let Some(document) = cursor.try_next().await? else {
+        return Ok(ReactionSentiment::default());
+    };
+
+    Ok(ReactionSentiment {
+        positive: get_number(&document, "positive").unwrap_or_default() as i64,
+        neutral: get_number(&document, "neutral").unwrap_or_default() as i64,
+        negative: get_number(&document, "negative").unwrap_or_default() as i64,
+    })
+}
@@ -1,8 +1,32 @@
-use crate::mongo::delete_messages;
+use crate::announcements::{build_impact_report, due_announcements, mark_reported};
+use crate::anomaly::{detect_volume_anomalies, hour_start, AnomalyKind};
+use crate::charts::sentiment_charts;
+use crate::churn::{opted_out_authors, update_baselines};
+use crate::clustering::cluster_weekly_messages;
+use crate::config::{
+    AnnouncementConfig, AnomalyConfig, ChurnConfig, ClusteringConfig, HealthConfig,
+};
+use crate::crypto::Keyring;
+use crate::discord::{
+    at_risk_members_embed, emerging_topics_embed, impact_report_embed, volume_anomaly_embed,
+    weekly_report_embed,
+};
+use crate::health::{compute_channel_health, local_midnight};
+use crate::mongo::{delete_derived_records, delete_messages};
+use crate::privacy::OptOuts;
+use crate::pseudonym::Pseudonymizer;
+use crate::report::build_weekly_report;
+use crate::util::local_now;
 use chrono::Utc;
+use chrono_tz::Asia::Seoul;
 use cron::Schedule;
 use mongodb::Database;
+use serenity::http::Http;
+use serenity::model::id::ChannelId;
+use std::collections::HashMap;
+use std::future::Future;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use tokio::time::sleep;
 
 pub async fn start_scheduler(db: &Database) {
@@ -41,12 +65,22 @@ pub async fn start_scheduler(db: &Database) {
             println!("[{}] Running delete messages", timestamp);
 
             // Run the delete_messages function
-            match delete_messages(&db).await {
+            match delete_messages(db).await {
                 Ok(result) => {
                     let deleted_count = result.deleted_count;
                     task_succeeded = true;
                     // Print the success message with the number of deleted messages
                     println!("[{}] Deleted {} message(s)", timestamp, deleted_count);
+
+                    // Sessions, threads and other records derived from the messages go too
+                    match delete_derived_records(db).await {
+                        Ok(deleted) => {
+                            println!("[{}] Deleted {} derived record(s)", timestamp, deleted)
+                        }
+                        Err(e) => {
+                            println!("[{}] Error deleting derived records: {:?}", timestamp, e)
+                        }
+                    }
                 }
                 Err(e) => {
                     // Print the error message if there's an error deleting messages
@@ -58,3 +92,239 @@ pub async fn start_scheduler(db: &Database) {
         }
     }
 }
+
+pub async fn start_report_scheduler(
+    http: Arc<Http>,
+    db: Database,
+    channel_id: ChannelId,
+    cron_expression: String,
+    charts: bool,
+) {
+    let schedule =
+        Schedule::from_str(&cron_expression).expect("Failed to parse the report schedule");
+
+    loop {
+        let seoul_now = Utc::now().with_timezone(&Seoul);
+        let next_event = schedule.upcoming(Seoul).next().unwrap();
+        println!(
+            "[Weekly Report] Waiting until next scheduled event [{}].",
+            next_event
+        );
+
+        let duration_until_next_event = (next_event - seoul_now).to_std().unwrap();
+        sleep(duration_until_next_event).await;
+
+        match build_weekly_report(&db).await {
+            Ok(report) => {
+                let charts = if charts {
+                    sentiment_charts(
+                        &report.daily_sentiment,
+                        &report.channel_sentiment,
+                        &report.emotions,
+                    )
+                } else {
+                    Vec::new()
+                };
+                let mut embed = weekly_report_embed(&report);
+                if let Some(chart) = charts.first() {
+                    embed.image(chart.url());
+                }
+                let result = channel_id
+                    .send_message(&http, |m| {
+                        m.set_embed(embed)
+                            .add_files(charts.into_iter().map(|chart| chart.attachment()))
+                    })
+                    .await;
+                if let Err(e) = result {
+                    println!("Error sending weekly report: {:?}", e);
+                }
+            }
+            Err(e) => println!("Error building weekly report: {:?}", e),
+        }
+    }
+}
+
+// Runs `job` at every upcoming time of the cron expression, in Seoul time. The job's
+// errors are logged by the job itself; the schedule keeps going regardless.
+async fn run_on_schedule<F, Fut>(name: &str, expression: &str, mut job: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let schedule = Schedule::from_str(expression)
+        .unwrap_or_else(|e| panic!("Failed to parse the {} schedule: {}", name, e));
+
+    loop {
+        let seoul_now = Utc::now().with_timezone(&Seoul);
+        let next_event = schedule.upcoming(Seoul).next().unwrap();
+        println!(
+            "[{}] Waiting until next scheduled event [{}].",
+            name, next_event
+        );
+
+        let duration_until_next_event = (next_event - seoul_now).to_std().unwrap();
+        sleep(duration_until_next_event).await;
+
+        job().await;
+    }
+}
+
+pub async fn start_clustering_scheduler(
+    http: Arc<Http>,
+    db: Database,
+    keyring: Option<Keyring>,
+    config: ClusteringConfig,
+) {
+    let channel_id = ChannelId(config.channel_id);
+    // Every run borrows the scheduler's state
+    let (http, db, keyring, config) = (&http, &db, keyring.as_ref(), &config);
+
+    run_on_schedule("Topic Clustering", &config.schedule, || async move {
+        match cluster_weekly_messages(db, keyring, config.max_clusters, config.min_messages).await {
+            Ok(clusters) if clusters.is_empty() => {
+                println!("[Topic Clustering] Not enough messages to cluster this week")
+            }
+            Ok(clusters) => {
+                let embed = emerging_topics_embed(&clusters);
+                if let Err(e) = channel_id.send_message(http, |m| m.set_embed(embed)).await {
+                    println!("Error sending emerging topics: {:?}", e);
+                }
+            }
+            Err(e) => println!("Error clustering messages: {:?}", e),
+        }
+    })
+    .await
+}
+
+pub async fn start_churn_scheduler(
+    http: Arc<Http>,
+    db: Database,
+    opt_outs: OptOuts,
+    pseudonymizer: Option<Pseudonymizer>,
+    config: ChurnConfig,
+) {
+    let channel_id = ChannelId(config.channel_id);
+    // Every run borrows the scheduler's state
+    let (http, db, opt_outs, pseudonymizer, config) =
+        (&http, &db, &opt_outs, pseudonymizer.as_ref(), &config);
+
+    run_on_schedule("At-Risk Members", &config.schedule, || async move {
+        let excluded = opted_out_authors(opt_outs, pseudonymizer).await;
+        match update_baselines(db, config, &excluded).await {
+            Ok(members) => {
+                let embed = at_risk_members_embed(&members, pseudonymizer.is_some());
+                if let Err(e) = channel_id.send_message(http, |m| m.set_embed(embed)).await {
+                    println!("Error sending at-risk members: {:?}", e);
+                }
+            }
+            Err(e) => println!("Error updating sentiment baselines: {:?}", e),
+        }
+    })
+    .await
+}
+
+pub async fn start_health_scheduler(db: Database, config: HealthConfig) {
+    let (db, config) = (&db, &config);
+
+    run_on_schedule("Channel Health", &config.schedule, || async move {
+        let yesterday = local_midnight(local_now()) - chrono::Duration::days(1);
+        match compute_channel_health(db, config, yesterday).await {
+            Ok(scored) => println!(
+                "[Channel Health] Scored {} channel(s) for {}",
+                scored,
+                yesterday.format("%Y-%m-%d")
+            ),
+            Err(e) => println!("Error computing channel health: {:?}", e),
+        }
+    })
+    .await
+}
+
+pub async fn start_anomaly_scheduler(http: Arc<Http>, db: Database, config: AnomalyConfig) {
+    let channel_id = ChannelId(config.alert_channel_id);
+    let cooldown = chrono::Duration::hours(config.cooldown_hours);
+    // Shared by every run; the lock is never held across an await
+    let last_alerts: Mutex<HashMap<(String, AnomalyKind), chrono::DateTime<Utc>>> =
+        Mutex::new(HashMap::new());
+    let (http, db, config, last_alerts) = (&http, &db, &config, &last_alerts);
+
+    run_on_schedule("Volume Anomalies", &config.schedule, || async move {
+        let hour = hour_start(local_now()) - chrono::Duration::hours(1);
+        let anomalies = match detect_volume_anomalies(db, config, hour).await {
+            Ok(anomalies) => anomalies,
+            Err(e) => {
+                println!("Error detecting volume anomalies: {:?}", e);
+                return;
+            }
+        };
+
+        for anomaly in anomalies {
+            {
+                let mut last_alerts = last_alerts.lock().unwrap();
+                let key = (anomaly.channel.clone(), anomaly.kind);
+                if last_alerts
+                    .get(&key)
+                    .is_some_and(|last| anomaly.hour - *last < cooldown)
+                {
+                    continue;
+                }
+                last_alerts.insert(key, anomaly.hour);
+            }
+
+            let embed = volume_anomaly_embed(&anomaly);
+            if let Err(e) = channel_id.send_message(http, |m| m.set_embed(embed)).await {
+                println!("Error sending volume anomaly alert: {:?}", e);
+            }
+        }
+    })
+    .await
+}
+
+pub async fn start_announcement_scheduler(
+    http: Arc<Http>,
+    db: Database,
+    keyring: Option<Keyring>,
+    config: AnnouncementConfig,
+) {
+    let channel_id = ChannelId(config.report_channel_id);
+    // Every run borrows the scheduler's state
+    let (http, db, keyring, config) = (&http, &db, keyring.as_ref(), &config);
+
+    run_on_schedule("Announcement Impact", &config.schedule, || async move {
+        let announcements = match due_announcements(db, local_now(), config.after_hours).await {
+            Ok(announcements) => announcements,
+            Err(e) => {
+                println!("Error loading announcements: {:?}", e);
+                return;
+            }
+        };
+
+        for announcement in announcements {
+            let report = match build_impact_report(
+                db,
+                keyring,
+                announcement.clone(),
+                config.before_hours,
+                config.after_hours,
+            )
+            .await
+            {
+                Ok(report) => report,
+                Err(e) => {
+                    println!("Error building announcement impact report: {:?}", e);
+                    continue;
+                }
+            };
+
+            let embed = impact_report_embed(&report);
+            if let Err(e) = channel_id.send_message(http, |m| m.set_embed(embed)).await {
+                println!("Error sending announcement impact report: {:?}", e);
+                continue;
+            }
+            if let Err(e) = mark_reported(db, &announcement).await {
+                println!("Error marking announcement as reported: {:?}", e);
+            }
+        }
+    })
+    .await
+}
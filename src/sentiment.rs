@@ -19,16 +19,29 @@ pub async fn analyze_sentiment(text: &str) -> Result<String, Box<dyn std::error:
 
     // Extract the sentiment from the response and map it to a String
     let sentiment = match response.sentiment {
-        Some(sentiment_type) => match sentiment_type {
-            SentimentType::Mixed => "mixed",
-            SentimentType::Negative => "negative",
-            SentimentType::Neutral => "neutral",
-            SentimentType::Positive => "positive",
-            _ => "unknown",
-        },
+        Some(sentiment_type) => sentiment_label(&sentiment_type),
         None => "unknown",
     };
 
     // Return the detected sentiment as a String
     Ok(sentiment.to_string())
 }
+
+pub fn sentiment_label(sentiment_type: &SentimentType) -> &'static str {
+    match sentiment_type {
+        SentimentType::Mixed => "mixed",
+        SentimentType::Negative => "negative",
+        SentimentType::Neutral => "neutral",
+        SentimentType::Positive => "positive",
+        _ => "unknown",
+    }
+}
+
+// Maps a sentiment label to a polarity score so that sentiment can be averaged over time
+pub fn sentiment_score(sentiment: &str) -> f64 {
+    match sentiment {
+        "positive" => 1.0,
+        "negative" => -1.0,
+        _ => 0.0,
+    }
+}
@@ -0,0 +1,156 @@
+use chrono::{Duration, Utc};
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::error::Error;
+use mongodb::options::FindOneOptions;
+use mongodb::Database;
+
+// Bounds the number of message IDs kept per session for resolving reply links
+const MAX_SESSION_MESSAGE_IDS: i32 = 1000;
+
+const SESSION_COLLECTION: &str = "sessions";
+
+// A message as seen by the session tracker. Staff messages are tracked for participation
+// and reply links but carry no sentiment.
+pub struct SessionMessage<'a> {
+    pub message_id: String,
+    pub channel_id: String,
+    pub channel: &'a str,
+    pub reply_to: Option<String>,
+    pub author_id: &'a str,
+    pub is_staff: bool,
+    pub is_question: bool,
+    pub is_issue_report: bool,
+    pub sentiment: Option<&'a str>,
+    pub created_at: chrono::DateTime<Utc>,
+}
+
+// Assigns the message to a conversation session and returns the session ID. A reply joins
+// the session of the message it replies to; otherwise the message joins the channel's
+// latest session if it was active within `gap`, or starts a new one.
+pub async fn record_session_message(
+    db: &Database,
+    message: &SessionMessage<'_>,
+    gap: Duration,
+) -> Result<ObjectId, Error> {
+    let collection = db.collection::<Document>(SESSION_COLLECTION);
+
+    let mut session = None;
+    if let Some(reply_to) = &message.reply_to {
+        session = collection
+            .find_one(doc! { "messageIds": reply_to }, None)
+            .await?;
+    }
+    if session.is_none() {
+        let cutoff = bson::DateTime::from_chrono(message.created_at - gap);
+        let options = FindOneOptions::builder()
+            .sort(doc! { "endedAt": -1 })
+            .build();
+        session = collection
+            .find_one(
+                doc! { "channelId": &message.channel_id, "endedAt": { "$gte": cutoff } },
+                options,
+            )
+            .await?;
+    }
+
+    let created_at = bson::DateTime::from_chrono(message.created_at);
+    let session_id = match session {
+        Some(session) => {
+            let session_id = session.get_object_id("_id").unwrap_or_default();
+            let started_at = session
+                .get_datetime("startedAt")
+                .copied()
+                .unwrap_or(created_at);
+            let duration = (created_at.timestamp_millis() - started_at.timestamp_millis()) / 1000;
+
+            let mut set = doc! { "endedAt": created_at, "durationSeconds": duration };
+            if message.is_staff {
+                set.insert("staffParticipated", true);
+                set.insert("lastStaffMessageAt", created_at);
+            }
+            if message.is_question {
+                set.insert("hasQuestion", true);
+                set.insert("lastQuestionAt", created_at);
+            }
+            if message.is_issue_report {
+                set.insert("hasIssueReport", true);
+            }
+            if let Some(sentiment) = message.sentiment {
+                set.insert("endSentiment", sentiment);
+            }
+            collection
+                .update_one(
+                    doc! { "_id": session_id },
+                    doc! {
+                        "$set": set,
+                        "$addToSet": { "participants": message.author_id },
+                        "$inc": { "messageCount": 1 },
+                        "$push": {
+                            "messageIds": {
+                                "$each": [&message.message_id],
+                                "$slice": -MAX_SESSION_MESSAGE_IDS,
+                            }
+                        },
+                    },
+                    None,
+                )
+                .await?;
+
+            // The first staff message in a session opened by the community is its first response
+            if message.is_staff {
+                collection
+                    .update_one(
+                        doc! {
+                            "_id": session_id,
+                            "startedByStaff": false,
+                            "firstResponseSeconds": { "$exists": false },
+                        },
+                        doc! { "$set": { "firstResponseSeconds": duration } },
+                        None,
+                    )
+                    .await?;
+            }
+            session_id
+        }
+        None => {
+            let session_id = ObjectId::new();
+            let mut session = doc! {
+                "_id": session_id,
+                "channelId": &message.channel_id,
+                "channel": message.channel,
+                "startedAt": created_at,
+                "endedAt": created_at,
+                "durationSeconds": 0,
+                "participants": [message.author_id],
+                "staffParticipated": message.is_staff,
+                "startedByStaff": message.is_staff,
+                "hasQuestion": message.is_question,
+                "hasIssueReport": message.is_issue_report,
+                "messageCount": 1,
+                "messageIds": [&message.message_id],
+            };
+            if message.is_staff {
+                session.insert("lastStaffMessageAt", created_at);
+            }
+            if message.is_question {
+                session.insert("lastQuestionAt", created_at);
+            }
+            collection.insert_one(session, None).await?;
+            session_id
+        }
+    };
+
+    // Sessions started by staff or by unanalyzed messages get their start sentiment from
+    // the first analyzed community message
+    if let Some(sentiment) = message.sentiment {
+        collection
+            .update_one(
+                doc! { "_id": session_id, "startSentiment": { "$exists": false } },
+                doc! { "$set": { "startSentiment": sentiment, "endSentiment": sentiment } },
+                None,
+            )
+            .await?;
+    }
+
+    Ok(session_id)
+}
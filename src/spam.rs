@@ -0,0 +1,335 @@
+use crate::config::SpamConfig;
+use chrono::{Duration, Utc};
+use mongodb::bson::{doc, Document};
+use mongodb::error::Error;
+use mongodb::Database;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+
+// Bounds the detector's memory during raids
+const MAX_RECENT_MESSAGES: usize = 2000;
+
+// Messages this similar (Jaccard similarity of their word sets) count as copies
+const DUPLICATE_SIMILARITY: f64 = 0.85;
+
+// Shorter messages ("gm", "thanks!") are repeated legitimately and never count as copies
+const MIN_DUPLICATE_WORDS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpamReason {
+    // The same or nearly the same text posted repeatedly
+    Duplicate,
+    // One user posting faster than `flood_messages` per window
+    Flood,
+    // Many recently created accounts posting at once
+    NewAccountBurst,
+}
+
+impl SpamReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SpamReason::Duplicate => "duplicate",
+            SpamReason::Flood => "flood",
+            SpamReason::NewAccountBurst => "new_account_burst",
+        }
+    }
+}
+
+pub struct SpamVerdict {
+    pub reason: SpamReason,
+    // Earlier messages that are part of the same spam, already stored unflagged
+    pub related_message_ids: Vec<String>,
+    pub authors: Vec<u64>,
+}
+
+struct RecentMessage {
+    message_id: String,
+    author: u64,
+    words: HashSet<String>,
+    new_account: bool,
+    at: chrono::DateTime<Utc>,
+}
+
+// Keeps a sliding window of recent messages in memory and checks each new message against it
+pub struct SpamDetector {
+    enabled: bool,
+    window: Duration,
+    duplicate_threshold: usize,
+    flood_messages: usize,
+    new_account_age: Duration,
+    new_account_burst: usize,
+    alert_cooldown: Duration,
+    recent: Mutex<VecDeque<RecentMessage>>,
+    last_alerts: Mutex<HashMap<String, chrono::DateTime<Utc>>>,
+}
+
+impl SpamDetector {
+    pub fn new(config: &SpamConfig) -> SpamDetector {
+        SpamDetector {
+            enabled: config.enabled,
+            window: Duration::seconds(config.window_seconds),
+            duplicate_threshold: config.duplicate_threshold,
+            flood_messages: config.flood_messages,
+            new_account_age: Duration::days(config.new_account_days),
+            new_account_burst: config.new_account_burst,
+            alert_cooldown: Duration::minutes(config.alert_cooldown_minutes),
+            recent: Mutex::new(VecDeque::new()),
+            last_alerts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Records the message and returns a verdict when it looks like spam. `account_created`
+    // should be the later of the account creation and guild join times.
+    pub fn check(
+        &self,
+        message_id: &str,
+        author: u64,
+        content: &str,
+        account_created: chrono::DateTime<Utc>,
+        at: chrono::DateTime<Utc>,
+    ) -> Option<SpamVerdict> {
+        if !self.enabled {
+            return None;
+        }
+
+        let words = words(content);
+        let new_account = at - account_created < self.new_account_age;
+        let mut recent = self.recent.lock().unwrap();
+        while let Some(oldest) = recent.front() {
+            if at - oldest.at > self.window || recent.len() >= MAX_RECENT_MESSAGES {
+                recent.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let copies: Vec<&RecentMessage> = if words.len() >= MIN_DUPLICATE_WORDS {
+            recent
+                .iter()
+                .filter(|message| similarity(&message.words, &words) >= DUPLICATE_SIMILARITY)
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let own: Vec<&RecentMessage> = recent
+            .iter()
+            .filter(|message| message.author == author)
+            .collect();
+        let new_accounts: Vec<&RecentMessage> = recent
+            .iter()
+            .filter(|message| message.new_account && message.author != author)
+            .collect();
+        let new_account_authors: HashSet<u64> =
+            new_accounts.iter().map(|message| message.author).collect();
+
+        // Counts include the message being checked
+        let verdict = if copies.len() + 1 >= self.duplicate_threshold {
+            Some((SpamReason::Duplicate, copies))
+        } else if own.len() + 1 >= self.flood_messages {
+            Some((SpamReason::Flood, own))
+        } else if new_account && new_account_authors.len() + 1 >= self.new_account_burst {
+            Some((SpamReason::NewAccountBurst, new_accounts))
+        } else {
+            None
+        }
+        .map(|(reason, related)| {
+            let mut authors: Vec<u64> = related.iter().map(|message| message.author).collect();
+            authors.push(author);
+            authors.sort_unstable();
+            authors.dedup();
+            SpamVerdict {
+                reason,
+                related_message_ids: related
+                    .iter()
+                    .map(|message| message.message_id.clone())
+                    .collect(),
+                authors,
+            }
+        });
+
+        recent.push_back(RecentMessage {
+            message_id: message_id.to_string(),
+            author,
+            words,
+            new_account,
+            at,
+        });
+
+        verdict
+    }
+
+    // Rate-limits moderation alerts so a raid produces one alert per reason and channel
+    // rather than one per message
+    pub fn should_alert(&self, reason: SpamReason, channel_id: u64) -> bool {
+        let key = format!("{}:{}", reason.as_str(), channel_id);
+        let now = Utc::now();
+        let mut last_alerts = self.last_alerts.lock().unwrap();
+        match last_alerts.get(&key) {
+            Some(last) if now - *last < self.alert_cooldown => false,
+            _ => {
+                last_alerts.insert(key, now);
+                true
+            }
+        }
+    }
+}
+
+// Flags already stored messages, e.g. the first copies of a message that only became
+// recognizable as spam once it was repeated
+pub async fn mark_spam(
+    db: &Database,
+    message_ids: &[String],
+    reason: SpamReason,
+) -> Result<(), Error> {
+    if message_ids.is_empty() {
+        return Ok(());
+    }
+
+    db.collection::<Document>("messages")
+        .update_many(
+            doc! { "messageId": { "$in": message_ids }, "spam": { "$exists": false } },
+            doc! { "$set": { "spam": reason.as_str() } },
+            None,
+        )
+        .await?;
+    Ok(())
+}
+
+fn words(content: &str) -> HashSet<String> {
+    content
+        .split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric())
+                .collect::<String>()
+                .to_lowercase()
+        })
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+fn similarity(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const SPAM: &str = "Claim your free airdrop tokens now at the official site";
+
+    fn start() -> chrono::DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap()
+    }
+
+    fn old_account() -> chrono::DateTime<Utc> {
+        start() - Duration::days(365)
+    }
+
+    #[test]
+    fn flags_copies_once_the_threshold_is_reached() {
+        let detector = SpamDetector::new(&SpamConfig::default());
+        let at = start();
+        assert!(detector.check("1", 1, SPAM, old_account(), at).is_none());
+        assert!(detector
+            .check("2", 2, &SPAM.to_uppercase(), old_account(), at)
+            .is_none());
+
+        // Punctuation and case don't hide a copy
+        let verdict = detector
+            .check("3", 3, &format!("{}!!", SPAM), old_account(), at)
+            .unwrap();
+        assert_eq!(verdict.reason, SpamReason::Duplicate);
+        assert_eq!(verdict.related_message_ids, vec!["1", "2"]);
+        assert_eq!(verdict.authors, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn forgets_copies_outside_the_window() {
+        let detector = SpamDetector::new(&SpamConfig::default());
+        detector.check("1", 1, SPAM, old_account(), start());
+        detector.check("2", 2, SPAM, old_account(), start() + Duration::seconds(30));
+
+        let later = start() + Duration::seconds(61);
+        let verdict = detector.check("3", 3, SPAM, old_account(), later);
+        assert!(verdict.is_none());
+    }
+
+    #[test]
+    fn never_counts_short_messages_as_copies() {
+        let detector = SpamDetector::new(&SpamConfig::default());
+        for id in 0..5 {
+            let verdict = detector.check(&id.to_string(), id, "gm all", old_account(), start());
+            assert!(verdict.is_none());
+        }
+    }
+
+    #[test]
+    fn flags_floods_from_one_user() {
+        let config = SpamConfig {
+            flood_messages: 3,
+            ..Default::default()
+        };
+        let detector = SpamDetector::new(&config);
+        detector.check(
+            "1",
+            1,
+            "first different message here",
+            old_account(),
+            start(),
+        );
+        detector.check(
+            "2",
+            1,
+            "second unrelated message there",
+            old_account(),
+            start(),
+        );
+
+        let verdict = detector
+            .check(
+                "3",
+                1,
+                "third message about something",
+                old_account(),
+                start(),
+            )
+            .unwrap();
+        assert_eq!(verdict.reason, SpamReason::Flood);
+        assert_eq!(verdict.authors, vec![1]);
+    }
+
+    #[test]
+    fn flags_bursts_of_new_accounts() {
+        let config = SpamConfig {
+            new_account_burst: 3,
+            ..Default::default()
+        };
+        let detector = SpamDetector::new(&config);
+        let new_account = start() - Duration::days(1);
+        detector.check("1", 1, "hello", new_account, start());
+        detector.check("2", 2, "hi", new_account, start());
+
+        let verdict = detector.check("3", 3, "hey", new_account, start()).unwrap();
+        assert_eq!(verdict.reason, SpamReason::NewAccountBurst);
+        assert_eq!(verdict.authors, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn ignores_everything_when_disabled() {
+        let config = SpamConfig {
+            enabled: false,
+            duplicate_threshold: 1,
+            ..Default::default()
+        };
+        let detector = SpamDetector::new(&config);
+        assert!(detector
+            .check("1", 1, SPAM, old_account(), start())
+            .is_none());
+    }
+}
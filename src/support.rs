@@ -0,0 +1,82 @@
+use crate::mongo::get_number;
+use chrono::Utc;
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, Document};
+use mongodb::error::Error;
+use mongodb::Database;
+
+// Support metrics for one channel, computed from the conversation sessions community
+// members started in it
+#[derive(Debug, Clone, Default)]
+pub struct ChannelSupportStats {
+    pub channel: String,
+    pub sessions: i64,
+    pub answered: i64,
+    pub unanswered_questions: i64,
+    pub issue_reports: i64,
+    pub avg_first_response_seconds: Option<f64>,
+    pub avg_resolution_seconds: Option<f64>,
+}
+
+pub async fn support_stats(
+    db: &Database,
+    since: chrono::DateTime<Utc>,
+) -> Result<Vec<ChannelSupportStats>, Error> {
+    let collection = db.collection::<Document>("sessions");
+    let pipeline = vec![
+        doc! {
+            "$match": {
+                "startedAt": { "$gte": bson::DateTime::from_chrono(since) },
+                "startedByStaff": false,
+            }
+        },
+        doc! {
+            "$group": {
+                "_id": "$channel",
+                "sessions": { "$sum": 1 },
+                "answered": {
+                    "$sum": { "$cond": [{ "$gt": ["$firstResponseSeconds", null] }, 1, 0] }
+                },
+                // A question is unanswered when no staff message followed it in the session
+                "unansweredQuestions": {
+                    "$sum": {
+                        "$cond": [
+                            {
+                                "$and": [
+                                    "$hasQuestion",
+                                    { "$lt": ["$lastStaffMessageAt", "$lastQuestionAt"] },
+                                ]
+                            },
+                            1,
+                            0,
+                        ]
+                    }
+                },
+                "issueReports": { "$sum": { "$cond": ["$hasIssueReport", 1, 0] } },
+                "avgFirstResponseSeconds": { "$avg": "$firstResponseSeconds" },
+                // Sessions staff took part in are resolved when the conversation ends
+                "avgResolutionSeconds": {
+                    "$avg": { "$cond": ["$staffParticipated", "$durationSeconds", null] }
+                },
+            }
+        },
+        doc! { "$sort": { "sessions": -1 } },
+    ];
+
+    let mut cursor = collection.aggregate(pipeline, None).await?;
+    let mut stats = Vec::new();
+    while let Some(document) = cursor.try_next().await? {
+        stats.push(ChannelSupportStats {
+            channel: document.get_str("_id").unwrap_or_default().to_string(),
+            sessions: get_number(&document, "sessions").unwrap_or_default() as i64,
+            answered: get_number(&document, "answered").unwrap_or_default() as i64,
+            unanswered_questions: get_number(&document, "unansweredQuestions").unwrap_or_default()
+                as i64,
+            issue_reports: get_number(&document, "issueReports").unwrap_or_default() as i64,
+            avg_first_response_seconds: get_number(&document, "avgFirstResponseSeconds"),
+            avg_resolution_seconds: get_number(&document, "avgResolutionSeconds"),
+        });
+    }
+
+    Ok(stats)
+}
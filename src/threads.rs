@@ -0,0 +1,90 @@
+use crate::sentiment::sentiment_score;
+use chrono::Utc;
+use mongodb::bson::{doc, Document};
+use mongodb::error::Error;
+use mongodb::options::UpdateOptions;
+use mongodb::Database;
+use serde::{Deserialize, Serialize};
+
+// Keeps thread documents bounded; long-running threads only keep their latest points
+const MAX_TRAJECTORY_POINTS: i32 = 500;
+
+// Thread context stored on every message posted in a thread or forum post
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ThreadInfo {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "parentId")]
+    pub parent_id: String,
+    // Names of the forum tags applied to the post, empty for regular threads
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+// Updates the per-thread aggregate in the `threads` collection: the sentiment of the first
+// analyzed message, the sentiment of the latest one, and the trajectory in between.
+pub async fn record_thread_message(
+    db: &Database,
+    thread: &ThreadInfo,
+    channel: &str,
+    sentiment: Option<&str>,
+    created_at: chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    let collection = db.collection::<Document>("threads");
+    let created_at = bson::DateTime::from_chrono(created_at);
+
+    let mut set = doc! {
+        "name": &thread.name,
+        "channel": channel,
+        "parentId": &thread.parent_id,
+        "tags": &thread.tags,
+        "lastMessageAt": created_at,
+    };
+    let mut update = doc! {
+        "$inc": { "messageCount": 1 },
+        "$setOnInsert": { "firstMessageAt": created_at },
+    };
+
+    if let Some(sentiment) = sentiment {
+        set.insert("closingSentiment", sentiment);
+        set.insert("closingScore", sentiment_score(sentiment));
+        update.insert(
+            "$push",
+            doc! {
+                "trajectory": {
+                    "$each": [{
+                        "sentiment": sentiment,
+                        "score": sentiment_score(sentiment),
+                        "createdAt": created_at,
+                    }],
+                    "$slice": -MAX_TRAJECTORY_POINTS,
+                }
+            },
+        );
+    }
+    update.insert("$set", set);
+
+    let options = UpdateOptions::builder().upsert(true).build();
+    collection
+        .update_one(doc! { "threadId": &thread.id }, update, options)
+        .await?;
+
+    // The opening sentiment is the first *analyzed* message, which isn't necessarily the
+    // message that created the document
+    if let Some(sentiment) = sentiment {
+        collection
+            .update_one(
+                doc! { "threadId": &thread.id, "openingSentiment": { "$exists": false } },
+                doc! {
+                    "$set": {
+                        "openingSentiment": sentiment,
+                        "openingScore": sentiment_score(sentiment),
+                    }
+                },
+                None,
+            )
+            .await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use crate::config::TopicRule;
+use crate::mongo::get_number;
+use chrono::Utc;
+use futures::stream::TryStreamExt;
+use mongodb::bson::{doc, Document};
+use mongodb::error::Error;
+use mongodb::Database;
+use regex::Regex;
+
+// Tags messages with the topics of the configured taxonomy whose keywords or patterns match
+pub struct TopicTagger {
+    topics: Vec<(String, Vec<Regex>)>,
+}
+
+impl TopicTagger {
+    pub fn new(rules: &[TopicRule]) -> TopicTagger {
+        let topics = rules
+            .iter()
+            .map(|rule| {
+                let mut regexes = Vec::new();
+                if !rule.keywords.is_empty() {
+                    // Keywords match whole words, case-insensitively
+                    let keywords: Vec<String> = rule
+                        .keywords
+                        .iter()
+                        .map(|keyword| regex::escape(keyword))
+                        .collect();
+                    let pattern = format!(r"(?i)\b(?:{})\b", keywords.join("|"));
+                    regexes.push(Regex::new(&pattern).expect("Invalid topic keyword"));
+                }
+                for pattern in &rule.patterns {
+                    regexes.push(Regex::new(pattern).expect("Invalid topic pattern"));
+                }
+                (rule.name.clone(), regexes)
+            })
+            .collect();
+
+        TopicTagger { topics }
+    }
+
+    // Matches the message text and its extracted key phrases
+    pub fn tag(&self, text: &str, key_phrases: &[String]) -> Vec<String> {
+        let haystack = format!("{}\n{}", text, key_phrases.join("\n"));
+        self.topics
+            .iter()
+            .filter(|(_, regexes)| regexes.iter().any(|regex| regex.is_match(&haystack)))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TopicSentiment {
+    pub topic: String,
+    pub messages: i64,
+    pub positive: i64,
+    pub negative: i64,
+    // Average sentiment score (-1 to 1) of the analyzed messages
+    pub score: Option<f64>,
+}
+
+// Sentiment of community messages per topic since `since`, most discussed first
+pub async fn topic_sentiment(
+    db: &Database,
+    since: chrono::DateTime<Utc>,
+) -> Result<Vec<TopicSentiment>, Error> {
+    let filter = doc! {
+        "createdAt": { "$gte": bson::DateTime::from_chrono(since) },
+        "role": { "$ne": "staff" },
+        "spam": { "$exists": false },
+    };
+    topic_sentiment_matching(db, filter).await
+}
+
+// Sentiment per topic of the messages matching `filter`, most discussed first
+pub async fn topic_sentiment_matching(
+    db: &Database,
+    mut filter: Document,
+) -> Result<Vec<TopicSentiment>, Error> {
+    filter.insert("topics.0", doc! { "$exists": true });
+    let collection = db.collection::<Document>("messages");
+    let pipeline = vec![
+        doc! { "$match": filter },
+        doc! { "$unwind": "$topics" },
+        doc! {
+            "$group": {
+                "_id": "$topics",
+                "messages": { "$sum": 1 },
+                "positive": { "$sum": { "$cond": [{ "$eq": ["$sentiment", "positive"] }, 1, 0] } },
+                "negative": { "$sum": { "$cond": [{ "$eq": ["$sentiment", "negative"] }, 1, 0] } },
+                // Unanalyzed messages have no score and are ignored by $avg
+                "score": {
+                    "$avg": {
+                        "$switch": {
+                            "branches": [
+                                { "case": { "$eq": ["$sentiment", "positive"] }, "then": 1 },
+                                { "case": { "$eq": ["$sentiment", "negative"] }, "then": -1 },
+                                { "case": { "$eq": ["$sentiment", "neutral"] }, "then": 0 },
+                                { "case": { "$eq": ["$sentiment", "mixed"] }, "then": 0 },
+                            ],
+                            "default": null,
+                        }
+                    }
+                },
+            }
+        },
+        doc! { "$sort": { "messages": -1 } },
+    ];
+
+    let mut cursor = collection.aggregate(pipeline, None).await?;
+    let mut topics = Vec::new();
+    while let Some(document) = cursor.try_next().await? {
+        topics.push(TopicSentiment {
+            topic: document.get_str("_id").unwrap_or_default().to_string(),
+            messages: get_number(&document, "messages").unwrap_or_default() as i64,
+            positive: get_number(&document, "positive").unwrap_or_default() as i64,
+            negative: get_number(&document, "negative").unwrap_or_default() as i64,
+            score: get_number(&document, "score"),
+        });
+    }
+
+    Ok(topics)
+}
@@ -0,0 +1,423 @@
+use crate::config::{ToxicityConfig, ToxicityProviderKind};
+use async_trait::async_trait;
+use aws_credential_types::provider::ProvideCredentials;
+use aws_sigv4::http_request::{sign, SignableBody, SignableRequest, SigningSettings};
+use aws_sigv4::SigningParams;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::SystemTime;
+
+// Comprehend rejects text segments over 1 KB
+const MAX_COMPREHEND_TEXT_BYTES: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToxicityCategory {
+    Insult,
+    Threat,
+    Profanity,
+    Harassment,
+}
+
+impl ToxicityCategory {
+    pub const ALL: [ToxicityCategory; 4] = [
+        ToxicityCategory::Insult,
+        ToxicityCategory::Threat,
+        ToxicityCategory::Profanity,
+        ToxicityCategory::Harassment,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ToxicityCategory::Insult => "insult",
+            ToxicityCategory::Threat => "threat",
+            ToxicityCategory::Profanity => "profanity",
+            ToxicityCategory::Harassment => "harassment",
+        }
+    }
+}
+
+// Per-category scores from 0 to 1
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+pub struct ToxicityScores {
+    pub insult: f64,
+    pub threat: f64,
+    pub profanity: f64,
+    pub harassment: f64,
+}
+
+impl ToxicityScores {
+    pub fn get(&self, category: ToxicityCategory) -> f64 {
+        match category {
+            ToxicityCategory::Insult => self.insult,
+            ToxicityCategory::Threat => self.threat,
+            ToxicityCategory::Profanity => self.profanity,
+            ToxicityCategory::Harassment => self.harassment,
+        }
+    }
+
+    fn set(&mut self, category: ToxicityCategory, score: f64) {
+        match category {
+            ToxicityCategory::Insult => self.insult = score,
+            ToxicityCategory::Threat => self.threat = score,
+            ToxicityCategory::Profanity => self.profanity = score,
+            ToxicityCategory::Harassment => self.harassment = score,
+        }
+    }
+
+    // Categories at or above their alert threshold, with their scores
+    pub fn exceeding(
+        &self,
+        thresholds: &HashMap<ToxicityCategory, f64>,
+    ) -> Vec<(ToxicityCategory, f64)> {
+        ToxicityCategory::ALL
+            .iter()
+            .filter_map(|category| {
+                let threshold = thresholds.get(category)?;
+                let score = self.get(*category);
+                (score >= *threshold).then_some((*category, score))
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+pub trait ToxicityClassifier: Send + Sync {
+    async fn classify(&self, text: &str) -> Result<ToxicityScores, Box<dyn Error + Send + Sync>>;
+
+    // Whether text is sent to an external service; such classifiers skip opted-out users
+    fn is_external(&self) -> bool {
+        false
+    }
+}
+
+pub fn load_toxicity_classifier(config: &ToxicityConfig) -> Option<Box<dyn ToxicityClassifier>> {
+    match config.provider {
+        ToxicityProviderKind::Offline => Some(Box::new(ProfanityList::new(&config.extra_terms))),
+        ToxicityProviderKind::Comprehend => Some(Box::new(ComprehendToxicity)),
+        ToxicityProviderKind::Disabled => None,
+    }
+}
+
+// AWS Comprehend's DetectToxicContent. The Comprehend SDK version we use predates the API,
+// so the request is signed and sent directly.
+pub struct ComprehendToxicity;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DetectToxicContentResponse {
+    result_list: Vec<ToxicContentResult>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ToxicContentResult {
+    labels: Vec<ToxicContentLabel>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ToxicContentLabel {
+    name: String,
+    score: f64,
+}
+
+#[async_trait]
+impl ToxicityClassifier for ComprehendToxicity {
+    fn is_external(&self) -> bool {
+        true
+    }
+
+    async fn classify(&self, text: &str) -> Result<ToxicityScores, Box<dyn Error + Send + Sync>> {
+        let shared_config = aws_config::load_from_env().await;
+        let region = shared_config
+            .region()
+            .map(|region| region.to_string())
+            .ok_or("AWS region is not configured")?;
+        let credentials = shared_config
+            .credentials_provider()
+            .ok_or("AWS credentials are not configured")?
+            .provide_credentials()
+            .await?;
+
+        let body = serde_json::to_vec(&serde_json::json!({
+            "TextSegments": [{ "Text": truncate(text, MAX_COMPREHEND_TEXT_BYTES) }],
+            "LanguageCode": "en",
+        }))?;
+        let mut request = http::Request::builder()
+            .method("POST")
+            .uri(format!("https://comprehend.{}.amazonaws.com/", region))
+            .header("Content-Type", "application/x-amz-json-1.1")
+            .header("X-Amz-Target", "Comprehend_20171127.DetectToxicContent")
+            .body(body)?;
+
+        let mut params = SigningParams::builder()
+            .access_key(credentials.access_key_id())
+            .secret_key(credentials.secret_access_key())
+            .region(&region)
+            .service_name("comprehend")
+            .time(SystemTime::now())
+            .settings(SigningSettings::default());
+        params.set_security_token(credentials.session_token());
+        let params = params.build()?;
+        let signable = SignableRequest::new(
+            request.method(),
+            request.uri(),
+            request.headers(),
+            SignableBody::Bytes(request.body()),
+        );
+        let (instructions, _) = sign(signable, &params)?.into_parts();
+        instructions.apply_to_request(&mut request);
+
+        let response = reqwest::Client::new()
+            .execute(reqwest::Request::try_from(request)?)
+            .await?
+            .error_for_status()?
+            .json::<DetectToxicContentResponse>()
+            .await?;
+
+        // Comprehend's labels are finer-grained than ours; take the highest matching score
+        let mut scores = ToxicityScores::default();
+        for label in response
+            .result_list
+            .into_iter()
+            .flat_map(|result| result.labels)
+        {
+            let category = match label.name.as_str() {
+                "INSULT" => ToxicityCategory::Insult,
+                "VIOLENCE_OR_THREAT" => ToxicityCategory::Threat,
+                "PROFANITY" => ToxicityCategory::Profanity,
+                "HARASSMENT_OR_ABUSE" | "HATE_SPEECH" => ToxicityCategory::Harassment,
+                _ => continue,
+            };
+            scores.set(category, scores.get(category).max(label.score));
+        }
+
+        Ok(scores)
+    }
+}
+
+fn truncate(text: &str, max_bytes: usize) -> &str {
+    let mut end = text.len().min(max_bytes);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+// Offline keyword classifier. Single words match whole tokens after leetspeak
+// normalization ("1d10t" -> "idiot", "f.u.c.k" -> "fuck"); phrases match the normalized text.
+pub struct ProfanityList {
+    terms: Vec<(ToxicityCategory, String)>,
+}
+
+// Everyday phrases and words that are only sometimes abusive ("this update will kill the
+// game", "that patch was stupid", "scammers are in DMs") are left out, since a single hit
+// scores 0.8; communities can add them with `extra_terms`
+const DEFAULT_TERMS: &[(ToxicityCategory, &[&str])] = &[
+    (
+        ToxicityCategory::Profanity,
+        &[
+            "fuck",
+            "fucking",
+            "fucked",
+            "motherfucker",
+            "shit",
+            "bullshit",
+            "bitch",
+            "asshole",
+            "bastard",
+            "dick",
+            "cunt",
+            "piss",
+            "crap",
+            "wtf",
+            "stfu",
+        ],
+    ),
+    (
+        ToxicityCategory::Insult,
+        &[
+            "idiot", "idiots", "moron", "morons", "dumbass", "loser", "losers",
+        ],
+    ),
+    (
+        ToxicityCategory::Threat,
+        &[
+            "hope you die",
+            "you should die",
+            "hurt you",
+            "beat you up",
+            "know where you live",
+            "find where you live",
+            "dox you",
+        ],
+    ),
+    (
+        ToxicityCategory::Harassment,
+        &[
+            "kys",
+            "kill yourself",
+            "nobody likes you",
+            "no one likes you",
+            "you suck",
+            "piece of shit",
+        ],
+    ),
+];
+
+impl ProfanityList {
+    pub fn new(extra_terms: &HashMap<ToxicityCategory, Vec<String>>) -> ProfanityList {
+        let mut terms: Vec<(ToxicityCategory, String)> = DEFAULT_TERMS
+            .iter()
+            .flat_map(|(category, terms)| terms.iter().map(|term| (*category, term.to_string())))
+            .collect();
+        for (category, extra) in extra_terms {
+            terms.extend(extra.iter().map(|term| {
+                let words: Vec<String> = term.split_whitespace().map(normalize_leetspeak).collect();
+                (*category, words.join(" "))
+            }));
+        }
+
+        ProfanityList { terms }
+    }
+}
+
+#[async_trait]
+impl ToxicityClassifier for ProfanityList {
+    async fn classify(&self, text: &str) -> Result<ToxicityScores, Box<dyn Error + Send + Sync>> {
+        let words: Vec<String> = text
+            .split_whitespace()
+            .map(normalize_leetspeak)
+            .filter(|word| !word.is_empty())
+            .collect();
+        let padded = format!(" {} ", words.join(" "));
+
+        let mut hits: HashMap<ToxicityCategory, usize> = HashMap::new();
+        for (category, term) in &self.terms {
+            let count = if term.contains(' ') {
+                padded.matches(&format!(" {} ", term)).count()
+            } else {
+                words.iter().filter(|word| *word == term).count()
+            };
+            *hits.entry(*category).or_default() += count;
+        }
+
+        // One hit is a strong signal; more hits only add a little
+        let mut scores = ToxicityScores::default();
+        for (category, count) in hits {
+            let score = match count {
+                0 => 0.0,
+                1 => 0.8,
+                2 => 0.9,
+                _ => 1.0,
+            };
+            scores.set(category, score);
+        }
+
+        Ok(scores)
+    }
+}
+
+// Lowercases, maps leetspeak digits and symbols to letters, drops the punctuation used to
+// break words up ("f.u.c.k") and squeezes letters repeated three or more times ("fuuuck")
+fn normalize_leetspeak(word: &str) -> String {
+    let word = word
+        .trim_start_matches(['(', '"', '\''])
+        .trim_end_matches(['!', '?', '.', ',', ';', ':', ')', '"', '\'']);
+    let mapped: String = word
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            '0' => Some('o'),
+            '1' | '!' => Some('i'),
+            '3' => Some('e'),
+            '4' | '@' => Some('a'),
+            '5' | '$' => Some('s'),
+            '7' | '+' => Some('t'),
+            '8' => Some('b'),
+            '9' => Some('g'),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect();
+
+    let mut squeezed = String::with_capacity(mapped.len());
+    let mut chars = mapped.chars().peekable();
+    while let Some(c) = chars.next() {
+        let mut run = 1;
+        while chars.peek() == Some(&c) {
+            chars.next();
+            run += 1;
+        }
+        for _ in 0..if run >= 3 { 1 } else { run } {
+            squeezed.push(c);
+        }
+    }
+    squeezed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classifier() -> ProfanityList {
+        ProfanityList::new(&HashMap::new())
+    }
+
+    #[test]
+    fn normalize_leetspeak_maps_symbols_and_squeezes_repeats() {
+        assert_eq!(normalize_leetspeak("1d10t"), "idiot");
+        assert_eq!(normalize_leetspeak("f.u.c.k"), "fuck");
+        assert_eq!(normalize_leetspeak("fuuuck"), "fuck");
+        assert_eq!(normalize_leetspeak("\"Sh1t!\""), "shit");
+        // Doubled letters are kept
+        assert_eq!(normalize_leetspeak("loser"), "loser");
+        assert_eq!(normalize_leetspeak("sooo"), "so");
+    }
+
+    #[tokio::test]
+    async fn single_words_match_whole_tokens() {
+        let scores = classifier().classify("what an 1d10t").await.unwrap();
+        assert_eq!(scores.insult, 0.8);
+
+        let scores = classifier().classify("the idiotic patch").await.unwrap();
+        assert_eq!(scores, ToxicityScores::default());
+    }
+
+    #[tokio::test]
+    async fn phrases_match_whole_words() {
+        let extra = HashMap::from([(ToxicityCategory::Threat, vec!["kill you".to_string()])]);
+        let classifier = ProfanityList::new(&extra);
+
+        let scores = classifier.classify("I will k1ll you").await.unwrap();
+        assert_eq!(scores.threat, 0.8);
+
+        let scores = classifier.classify("that skill you showed").await.unwrap();
+        assert_eq!(scores.threat, 0.0);
+        let scores = classifier.classify("kill yourself").await.unwrap();
+        assert_eq!(scores.threat, 0.0);
+        assert_eq!(scores.harassment, 0.8);
+    }
+
+    #[tokio::test]
+    async fn repeated_hits_raise_the_score() {
+        let scores = classifier().classify("crap").await.unwrap();
+        assert_eq!(scores.profanity, 0.8);
+        let scores = classifier().classify("crap crap").await.unwrap();
+        assert_eq!(scores.profanity, 0.9);
+        let scores = classifier().classify("crap shit wtf").await.unwrap();
+        assert_eq!(scores.profanity, 1.0);
+        assert_eq!(scores.insult, 0.0);
+    }
+
+    #[tokio::test]
+    async fn ambiguous_words_are_not_built_in() {
+        let scores = classifier()
+            .classify("stupid scammers, this clown project is pathetic")
+            .await
+            .unwrap();
+        assert_eq!(scores, ToxicityScores::default());
+    }
+}
@@ -1,9 +1,22 @@
+use crate::config::RedactionConfig;
+use crate::emoji::{
+    custom_polarity, emoji_token, emoticon, is_emoji_char, unicode_polarity, EmojiFeatures,
+    Polarity,
+};
+use chrono::{Duration, Utc};
 use regex::Regex;
 use serenity::model::prelude::ChannelId;
 use serenity::model::{channel::Channel, channel::Message};
 use serenity::prelude::*;
+use std::collections::HashMap;
 use std::env;
 
+// Stored timestamps are shifted to the local timezone (UTC+9), so queries over `createdAt`
+// compare against this rather than `Utc::now()`
+pub fn local_now() -> chrono::DateTime<Utc> {
+    Utc::now() + Duration::hours(9)
+}
+
 pub async fn replace_mentions(ctx: &Context, msg: &Message) -> String {
     let mut content = msg.content.clone();
 
@@ -62,8 +75,10 @@ pub async fn replace_mentions(ctx: &Context, msg: &Message) -> String {
     content
 }
 
-pub fn should_ignore_user(msg: &Message) -> bool {
-    let ignored_user_ids: &[&str] = &[
+// Support staff. Their messages are stored with the "staff" role and used for response-time
+// analytics instead of sentiment analysis.
+pub fn is_staff(msg: &Message) -> bool {
+    let staff_user_ids: &[&str] = &[
         "983924510220779550",  // wen
         "1026733912778625026", // corrie
         "912897330213179402",  // rosie
@@ -73,12 +88,26 @@ pub fn should_ignore_user(msg: &Message) -> bool {
                                // "623155071735037982",
     ];
 
-    ignored_user_ids
+    staff_user_ids
         .iter()
-        .any(|&id| id == &msg.author.id.to_string())
+        .any(|&id| id == msg.author.id.to_string())
+}
+
+// Median of a non-empty slice
+pub fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let middle = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    } else {
+        sorted[middle]
+    }
 }
 
-pub fn should_ignore_channel(msg: &Message) -> bool {
+// Takes the parent channel too for threads and forum posts, so they are ignored along with
+// their channel
+pub fn should_ignore_channel(channel_id: ChannelId) -> bool {
     let ignored_channel_ids: &[&str] = &[
         "1021958640829210674", // test server (attendance)
         "1069854617011224637", // attendance-beta-version
@@ -99,11 +128,123 @@ pub fn should_ignore_channel(msg: &Message) -> bool {
 
     ignored_channel_ids
         .iter()
-        .any(|&id| id == &msg.channel_id.to_string())
+        .any(|&id| id == channel_id.to_string())
+}
+
+// Whether the message asks something, used to count questions left unanswered by staff
+pub fn is_question(content: &str) -> bool {
+    let question_words: &[&str] = &[
+        "how", "what", "why", "when", "where", "who", "which", "can", "could", "is", "are", "does",
+        "do", "did", "anyone", "any",
+    ];
+
+    content.contains('?')
+        || content
+            .split_whitespace()
+            .next()
+            .map(|word| question_words.contains(&word.to_lowercase().as_str()))
+            .unwrap_or(false)
 }
 
 pub fn has_minimum_word_count(msg: &Message, min_word_count: usize) -> bool {
-    msg.content.split_whitespace().count() >= min_word_count
+    count_words(&msg.content) >= min_word_count
+}
+
+// Counts words, ignoring code blocks, spoilers, emojis and emoticons
+pub fn count_words(content: &str) -> usize {
+    let custom_emoji_pattern = Regex::new(r"^<a?:\w+:\d+>$").unwrap();
+
+    strip_markup(content)
+        .split_whitespace()
+        .filter(|word| {
+            word.chars().any(char::is_alphanumeric)
+                && !custom_emoji_pattern.is_match(word)
+                && emoticon(word).is_none()
+        })
+        .count()
+}
+
+// Removes code blocks, inline code and spoilers, which aren't meaningful to analyze
+pub fn strip_markup(content: &str) -> String {
+    let code_block_pattern = Regex::new(r"(?s)```.*?```").unwrap();
+    let inline_code_pattern = Regex::new(r"`[^`\n]+`").unwrap();
+    let spoiler_pattern = Regex::new(r"(?s)\|\|.*?\|\|").unwrap();
+
+    let content = code_block_pattern.replace_all(content, " ");
+    let content = inline_code_pattern.replace_all(&content, " ");
+    spoiler_pattern.replace_all(&content, " ").into_owned()
+}
+
+// Prepares the content for sentiment analysis: strips markup, replaces custom emoji tags
+// with their names and maps unicode emojis and emoticons to sentiment-bearing words.
+// Returns the normalized text and the emoji usage found along the way.
+pub fn normalize_text(
+    content: &str,
+    custom_emoji: &HashMap<String, Polarity>,
+) -> (String, Option<EmojiFeatures>) {
+    let custom_emoji_pattern = Regex::new(r"<a?:(\w+):\d+>").unwrap();
+    let mut features = EmojiFeatures::default();
+
+    let content = strip_markup(content);
+    let content = custom_emoji_pattern.replace_all(&content, |caps: &regex::Captures| {
+        let name = &caps[1];
+        features.custom += 1;
+        features.add(custom_polarity(name, custom_emoji));
+        format!(" {} ", name.replace('_', " "))
+    });
+
+    let mut words = Vec::new();
+    for token in content.split_whitespace() {
+        if let Some((word, polarity)) = emoticon(token) {
+            features.emoticons += 1;
+            features.add(polarity);
+            words.push(word.to_string());
+            continue;
+        }
+
+        // Split the token into runs of text and emojis, e.g. "great👍👍" -> "great good good"
+        let mut text = String::new();
+        let mut chars = token.chars().peekable();
+        while let Some(c) = chars.next() {
+            if !is_emoji_char(c) {
+                text.push(c);
+                continue;
+            }
+
+            // Gather the full emoji sequence (modifiers, variation selectors, joiners)
+            let mut emoji = c.to_string();
+            while let Some(&next) = chars.peek() {
+                if matches!(next as u32, 0x1F3FB..=0x1F3FF | 0xFE0E | 0xFE0F | 0x200D) {
+                    emoji.push(next);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            if matches!(c as u32, 0xFE0E | 0xFE0F | 0x200D) {
+                continue;
+            }
+
+            features.unicode += 1;
+            features.add(unicode_polarity(&emoji));
+            if !text.is_empty() {
+                words.push(std::mem::take(&mut text));
+            }
+            if let Some(word) = emoji_token(&emoji) {
+                words.push(word.to_string());
+            }
+        }
+        if !text.is_empty() {
+            words.push(text);
+        }
+    }
+
+    let features = if features.is_empty() {
+        None
+    } else {
+        Some(features)
+    };
+    (words.join(" "), features)
 }
 
 pub fn should_not_ignore_guild(msg: &Message) -> bool {
@@ -130,15 +271,151 @@ pub fn filter_guild(msg: &Message) -> bool {
     guild == guild_id.to_string()
 }
 
-pub fn remove_urls(content: &str) -> Option<String> {
-    let url_pattern = Regex::new(r"(https?://[^\s]+)").unwrap();
+// A detector that replaces every match of `regex` with `[LABEL]`. `kind` is recorded on
+// the stored message so we know which redactions were applied without keeping the value.
+struct Detector {
+    kind: String,
+    regex: Regex,
+    placeholder: String,
+}
+
+impl Detector {
+    fn new(kind: &str, pattern: &str) -> Detector {
+        Detector {
+            kind: kind.to_string(),
+            regex: Regex::new(pattern).expect("Invalid redaction pattern"),
+            placeholder: format!("[{}]", kind.to_uppercase()),
+        }
+    }
+}
+
+pub struct Redactor {
+    detectors: Vec<Detector>,
+}
+
+impl Redactor {
+    pub fn new(config: &RedactionConfig) -> Redactor {
+        if !config.enabled {
+            return Redactor {
+                detectors: Vec::new(),
+            };
+        }
 
-    // Check if the entire text is a URL
-    if let Some(m) = url_pattern.find(&content) {
-        if m.start() == 0 && m.end() == content.len() {
-            return None;
+        // Order matters: transaction hashes are checked before wallet addresses, and both
+        // before phone numbers, since they all contain long runs of digits.
+        let mut detectors = vec![
+            Detector::new("email", r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}"),
+            Detector::new("tx_hash", r"\b(0x)?[a-fA-F0-9]{64}\b"),
+            Detector::new(
+                "wallet",
+                r"\b(0x[a-fA-F0-9]{40}|bc1[a-z0-9]{25,59}|[13][a-km-zA-HJ-NP-Z1-9]{25,34})\b",
+            ),
+            Detector::new("phone", r"\+?\d[\d\s().-]{6,}\d"),
+        ];
+        for custom in &config.custom_patterns {
+            detectors.push(Detector::new(&custom.name, &custom.pattern));
         }
+
+        Redactor { detectors }
+    }
+
+    // Replaces detected PII with typed placeholders and returns the redacted text along
+    // with the kinds of redactions that were applied.
+    pub fn redact(&self, content: &str) -> (String, Vec<String>) {
+        let mut content = content.to_string();
+        let mut applied = Vec::new();
+
+        for detector in &self.detectors {
+            let mut matched = false;
+            let redacted = detector
+                .regex
+                .replace_all(&content, |caps: &regex::Captures| {
+                    let value = &caps[0];
+                    if detector.kind == "phone" && !looks_like_phone_number(value) {
+                        return value.to_string();
+                    }
+                    matched = true;
+                    detector.placeholder.clone()
+                });
+            let redacted = redacted.into_owned();
+
+            if matched {
+                applied.push(detector.kind.clone());
+            }
+            content = redacted;
+        }
+
+        (content, applied)
     }
+}
+
+// Phone numbers have 9 to 15 digits; shorter runs are usually dates, prices or counts.
+fn looks_like_phone_number(value: &str) -> bool {
+    let digits = value.chars().filter(|c| c.is_ascii_digit()).count();
+    (9..=15).contains(&digits)
+}
 
-    Some(content.to_string())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CustomPattern;
+
+    fn redactor() -> Redactor {
+        Redactor::new(&RedactionConfig::default())
+    }
+
+    #[test]
+    fn redacts_emails_and_wallets() {
+        let (text, applied) = redactor().redact(
+            "mail me at jane.doe@example.com or send to 0x52908400098527886E0F7030069857D2E4169EEf",
+        );
+        assert_eq!(text, "mail me at [EMAIL] or send to [WALLET]");
+        assert_eq!(applied, vec!["email", "wallet"]);
+    }
+
+    #[test]
+    fn redacts_transaction_hashes_before_wallets() {
+        let hash = format!("0x{}", "ab".repeat(32));
+        let (text, applied) = redactor().redact(&format!("tx {} failed", hash));
+        assert_eq!(text, "tx [TX_HASH] failed");
+        assert_eq!(applied, vec!["tx_hash"]);
+    }
+
+    #[test]
+    fn redacts_phone_numbers_but_not_short_digit_runs() {
+        let (text, applied) = redactor().redact("call +82 10-1234-5678 before 2024-01-15");
+        assert_eq!(text, "call [PHONE] before 2024-01-15");
+        assert_eq!(applied, vec!["phone"]);
+    }
+
+    #[test]
+    fn applies_custom_patterns() {
+        let config = RedactionConfig {
+            enabled: true,
+            custom_patterns: vec![CustomPattern {
+                name: "order_id".to_string(),
+                pattern: r"\bORD-\d{6}\b".to_string(),
+            }],
+        };
+        let (text, applied) = Redactor::new(&config).redact("my order ORD-123456 is stuck");
+        assert_eq!(text, "my order [ORDER_ID] is stuck");
+        assert_eq!(applied, vec!["order_id"]);
+    }
+
+    #[test]
+    fn leaves_text_alone_when_disabled() {
+        let config = RedactionConfig {
+            enabled: false,
+            custom_patterns: Vec::new(),
+        };
+        let (text, applied) = Redactor::new(&config).redact("jane.doe@example.com");
+        assert_eq!(text, "jane.doe@example.com");
+        assert!(applied.is_empty());
+    }
+
+    #[test]
+    fn median_averages_the_middle_pair() {
+        assert_eq!(median(&[3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(median(&[4.0, 1.0, 3.0, 2.0]), 2.5);
+    }
 }
@@ -0,0 +1,267 @@
+use crate::config::WatchlistRule;
+use chrono::{Duration, Utc};
+use mongodb::bson::doc;
+use mongodb::bson::Document;
+use mongodb::error::Error;
+use mongodb::Database;
+use regex::Regex;
+use serenity::model::id::ChannelId;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const HIT_COLLECTION: &str = "watchlist_hits";
+
+struct CompiledRule {
+    name: String,
+    regexes: Vec<Regex>,
+    guild_id: Option<u64>,
+    channels: Vec<u64>,
+    alert_channel: Option<ChannelId>,
+    dm_users: Vec<u64>,
+    cooldown: Duration,
+}
+
+pub struct WatchlistHit {
+    pub rule: String,
+    // The matched text, lowercased and without duplicates
+    pub terms: Vec<String>,
+    pub alert_channel: Option<ChannelId>,
+    pub dm_users: Vec<u64>,
+    // False while the rule is cooling down; the hit is still recorded
+    pub alert: bool,
+}
+
+pub struct Watchlist {
+    rules: Vec<CompiledRule>,
+    last_alerts: Mutex<HashMap<String, chrono::DateTime<Utc>>>,
+}
+
+impl Watchlist {
+    pub fn new(rules: &[WatchlistRule], default_channel_id: u64) -> Watchlist {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let mut regexes = Vec::new();
+                if !rule.keywords.is_empty() {
+                    // Keywords match whole words, case-insensitively
+                    let keywords: Vec<String> = rule
+                        .keywords
+                        .iter()
+                        .map(|keyword| keyword_pattern(keyword))
+                        .collect();
+                    let pattern = format!("(?i)(?:{})", keywords.join("|"));
+                    regexes.push(Regex::new(&pattern).expect("Invalid watchlist keyword"));
+                }
+                for pattern in &rule.patterns {
+                    regexes.push(Regex::new(pattern).expect("Invalid watchlist pattern"));
+                }
+
+                // Rules without any destination alert the notify channel
+                let alert_channel = match rule.alert_channel_id {
+                    Some(channel_id) => Some(ChannelId(channel_id)),
+                    None if rule.dm_users.is_empty() => Some(ChannelId(default_channel_id)),
+                    None => None,
+                };
+                CompiledRule {
+                    name: rule.name.clone(),
+                    regexes,
+                    guild_id: rule.guild_id,
+                    channels: rule.channels.clone(),
+                    alert_channel,
+                    dm_users: rule.dm_users.clone(),
+                    cooldown: Duration::minutes(rule.cooldown_minutes),
+                }
+            })
+            .collect();
+
+        Watchlist {
+            rules,
+            last_alerts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Returns a hit for every rule scoped to the guild and channel that matches the content,
+    // and starts the cooldown of the rules that alert. Rules scoped to a channel cover its
+    // threads and forum posts, which pass the channel as `parent_id`.
+    pub fn check(
+        &self,
+        guild_id: Option<u64>,
+        channel_id: u64,
+        parent_id: Option<u64>,
+        content: &str,
+        at: chrono::DateTime<Utc>,
+    ) -> Vec<WatchlistHit> {
+        let mut hits = Vec::new();
+        for rule in &self.rules {
+            if rule.guild_id.is_some() && rule.guild_id != guild_id {
+                continue;
+            }
+            if !rule.channels.is_empty()
+                && !rule.channels.contains(&channel_id)
+                && !parent_id.is_some_and(|parent_id| rule.channels.contains(&parent_id))
+            {
+                continue;
+            }
+
+            let mut terms: Vec<String> = Vec::new();
+            for regex in &rule.regexes {
+                for found in regex.find_iter(content) {
+                    let term = found.as_str().to_lowercase();
+                    if !terms.contains(&term) {
+                        terms.push(term);
+                    }
+                }
+            }
+            if terms.is_empty() {
+                continue;
+            }
+
+            let mut last_alerts = self.last_alerts.lock().unwrap();
+            let alert = match last_alerts.get(&rule.name) {
+                Some(last) => at - *last >= rule.cooldown,
+                None => true,
+            };
+            if alert {
+                last_alerts.insert(rule.name.clone(), at);
+            }
+
+            hits.push(WatchlistHit {
+                rule: rule.name.clone(),
+                terms,
+                alert_channel: rule.alert_channel,
+                dm_users: rule.dm_users.clone(),
+                alert,
+            });
+        }
+
+        hits
+    }
+}
+
+// `\b` only matches next to a word character, so keywords that start or end with a symbol
+// ("$SCAM") use `\B` on that side: the neighbouring character must not be a word character
+fn keyword_pattern(keyword: &str) -> String {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let boundary = |c: Option<char>| match c {
+        Some(c) if is_word(c) => r"\b",
+        _ => r"\B",
+    };
+    format!(
+        "{}{}{}",
+        boundary(keyword.chars().next()),
+        regex::escape(keyword),
+        boundary(keyword.chars().last())
+    )
+}
+
+// Stores the hit in `watchlist_hits`. The message text isn't stored, only the matched terms.
+// `author_id` is None for users who opted out.
+pub async fn record_hit(
+    db: &Database,
+    hit: &WatchlistHit,
+    message_id: &str,
+    channel_id: &str,
+    author_id: Option<&str>,
+    sentiment: Option<&str>,
+    created_at: chrono::DateTime<Utc>,
+) -> Result<(), Error> {
+    let collection = db.collection::<Document>(HIT_COLLECTION);
+    let mut hit_document = doc! {
+        "rule": &hit.rule,
+        "terms": &hit.terms,
+        "messageId": message_id,
+        "channelId": channel_id,
+        "alerted": hit.alert,
+        "createdAt": bson::DateTime::from_chrono(created_at),
+    };
+    if let Some(author_id) = author_id {
+        hit_document.insert("authorId", author_id);
+    }
+    if let Some(sentiment) = sentiment {
+        hit_document.insert("sentiment", sentiment);
+    }
+    collection.insert_one(hit_document, None).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const GUILD: u64 = 1;
+    const ANNOUNCEMENTS: u64 = 10;
+    const GENERAL: u64 = 11;
+
+    fn watchlist(rules: &str) -> Watchlist {
+        let rules: Vec<WatchlistRule> = serde_yaml::from_str(rules).unwrap();
+        Watchlist::new(&rules, 99)
+    }
+
+    fn at(minute: u32) -> chrono::DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, 12, minute, 0).unwrap()
+    }
+
+    fn rules_hit(hits: &[WatchlistHit]) -> Vec<&str> {
+        hits.iter().map(|hit| hit.rule.as_str()).collect()
+    }
+
+    #[test]
+    fn keywords_match_whole_words_in_any_case() {
+        let watchlist = watchlist("- name: launch\n  keywords: [airdrop, rug pull]\n");
+
+        let hits = watchlist.check(None, GENERAL, None, "AIRDROP soon? Rug pull!", at(0));
+        assert_eq!(hits[0].terms, vec!["airdrop", "rug pull"]);
+        assert_eq!(hits[0].alert_channel, Some(ChannelId(99)));
+
+        let hits = watchlist.check(None, GENERAL, None, "airdrops and rug pulls", at(30));
+        assert!(hits.is_empty());
+    }
+
+    #[test]
+    fn keywords_with_symbols_match_on_their_own() {
+        let watchlist = watchlist("- name: tickers\n  keywords: [$SCAM, c++]\n");
+
+        let hits = watchlist.check(None, GENERAL, None, "buy $scam now, written in C++", at(0));
+        assert_eq!(hits[0].terms, vec!["$scam", "c++"]);
+
+        let hits = watchlist.check(None, GENERAL, None, "token$SCAM c++x", at(30));
+        assert!(hits.is_empty());
+    }
+
+    #[test]
+    fn rules_are_scoped_to_their_guild_and_channels() {
+        let watchlist = watchlist(&format!(
+            "- name: anywhere\n  keywords: [launch]\n\
+             - name: guild\n  keywords: [launch]\n  guild_id: {}\n\
+             - name: announcements\n  keywords: [launch]\n  channels: [{}]\n",
+            GUILD, ANNOUNCEMENTS
+        ));
+
+        let hits = watchlist.check(Some(2), GENERAL, None, "launch", at(0));
+        assert_eq!(rules_hit(&hits), vec!["anywhere"]);
+
+        let hits = watchlist.check(Some(GUILD), GENERAL, None, "launch", at(0));
+        assert_eq!(rules_hit(&hits), vec!["anywhere", "guild"]);
+
+        let hits = watchlist.check(Some(GUILD), ANNOUNCEMENTS, None, "launch", at(0));
+        assert_eq!(rules_hit(&hits), vec!["anywhere", "guild", "announcements"]);
+
+        // A thread under the announcements channel
+        let hits = watchlist.check(Some(GUILD), 12, Some(ANNOUNCEMENTS), "launch", at(0));
+        assert_eq!(rules_hit(&hits), vec!["anywhere", "guild", "announcements"]);
+    }
+
+    #[test]
+    fn hits_during_the_cooldown_are_returned_without_alerting() {
+        let watchlist =
+            watchlist("- name: launch\n  patterns: [\"v\\\\d+\"]\n  cooldown_minutes: 10\n");
+
+        let alerts: Vec<bool> = [0, 5, 10, 15]
+            .iter()
+            .map(|minute| watchlist.check(None, GENERAL, None, "v2 is out", at(*minute))[0].alert)
+            .collect();
+        assert_eq!(alerts, vec![true, false, true, false]);
+    }
+}